@@ -3,6 +3,18 @@ use std::error::Error as StdError;
 use getopts::{self, Options};
 use codecs;
 use codecs::Codec;
+use collision;
+use companion;
+use filter;
+use glob;
+use path;
+use source;
+use utils;
+use verify;
+use quality;
+use gain;
+use streams;
+use metadata;
 
 #[derive(Debug)]
 pub enum Error {
@@ -14,6 +26,11 @@ pub enum Error {
         error: getopts::Fail,
     },
     Help { program_name: String },
+    InvalidValue {
+        program_name: String,
+        option: &'static str,
+        value: String,
+    },
 }
 
 impl StdError for Error {
@@ -24,6 +41,7 @@ impl StdError for Error {
             Error::MissingSourceDir { .. } => "No INPUT_DIRECTORY specified",
             Error::GetOptsFail { .. } => "Argument error",
             Error::Help { .. } => "Help specified",
+            Error::InvalidValue { .. } => "Invalid option value",
         }
     }
     fn cause(&self) -> Option<&StdError> {
@@ -33,12 +51,18 @@ impl StdError for Error {
             Error::MissingTargetDir { .. } => None,
             Error::MissingSourceDir { .. } => None,
             Error::GetOptsFail { ref error, .. } => Some(error),
+            Error::InvalidValue { .. } => None,
         }
     }
 }
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.description())
+        match *self {
+            Error::InvalidValue { option, ref value, .. } => {
+                write!(f, "{} for --{}: {:?}", self.description(), option, value)
+            }
+            _ => write!(f, "{}", self.description()),
+        }
     }
 }
 
@@ -46,7 +70,138 @@ pub fn opts() -> Options {
     let mut opts = Options::new();
     opts.optflag("d", "dry-run", "No paths are created or updated");
     opts.optflag("h", "help", "Display this help and exit");
-    opts.optflag("f", "format", "Set the output format");
+    opts.optopt("f", "format", "Set the output format", "FORMAT");
+    opts.optopt("",
+                "collisions",
+                "How to resolve sources mapping to the same target: extension (default), \
+                 number or error",
+                "POLICY");
+    opts.optopt("",
+                "companions",
+                "What to do with non-media files: copy, hardlink, symlink or ignore (default)",
+                "POLICY");
+    opts.optmulti("",
+                  "companion",
+                  "Only treat non-media files matching GLOB as companions",
+                  "GLOB");
+    opts.optmulti("",
+                  "companion-mime",
+                  "Only treat non-media files with a mime-type matching GLOB as companions",
+                  "GLOB");
+    opts.optmulti("",
+                  "include",
+                  "Only pick up files matching GLOB from INPUT_DIRECTORY",
+                  "GLOB");
+    opts.optmulti("", "exclude", "Skip files and directories matching GLOB", "GLOB");
+    opts.optopt("", "min-size", "Skip files smaller than SIZE (e.g. 10M)", "SIZE");
+    opts.optopt("", "max-size", "Skip files larger than SIZE (e.g. 4G)", "SIZE");
+    opts.optopt("",
+                "min-duration",
+                "Skip sources shorter than DURATION (seconds or HH:MM:SS)",
+                "DURATION");
+    opts.optopt("",
+                "max-duration",
+                "Skip sources longer than DURATION (seconds or HH:MM:SS)",
+                "DURATION");
+    opts.optflag("", "hidden", "Include hidden files and directories");
+    opts.optflag("",
+                 "no-ignore-files",
+                 "Do not read .transcodeignore files in INPUT_DIRECTORY, nor skip @eaDir and \
+                  Sample directories");
+    opts.optopt("",
+                "symlinks",
+                "What to do with symbolic links: follow (default), skip or error, which aborts",
+                "POLICY");
+    opts.optflag("",
+                 "one-file-system",
+                 "Do not descend into directories on other filesystems");
+    opts.optflag("",
+                 "mime-fallback",
+                 "Ask the external 'file' command about content that is not recognized");
+    opts.optopt("",
+                "verify",
+                "Check outputs before they replace the temporary file: none (default), probe \
+                 (compare duration and streams with the source) or decode (also decode \
+                 everything)",
+                "LEVEL");
+    opts.optopt("",
+                "quality",
+                "Measure converted video against the source, METRICS is a comma separated \
+                 list of ssim, psnr and vmaf (vmaf needs ffmpeg with libvmaf)",
+                "METRICS");
+    opts.optopt("",
+                "quality-subsample",
+                "Only compare every N-th frame when measuring quality",
+                "N");
+    opts.optopt("",
+                "min-ssim",
+                "Fail conversions with an SSIM below SCORE (0 to 1)",
+                "SCORE");
+    opts.optopt("",
+                "min-psnr",
+                "Fail conversions with a PSNR below SCORE (in dB)",
+                "SCORE");
+    opts.optopt("",
+                "min-vmaf",
+                "Fail conversions with a VMAF below SCORE (0 to 100)",
+                "SCORE");
+    opts.optopt("",
+                "report",
+                "Write the status and quality scores of each conversion to FILE as JSON",
+                "FILE");
+    opts.optopt("",
+                "gain-tags",
+                "Measure the loudness of each file and of each directory as an album, and tag \
+                 outputs with it: replaygain or r128. Directories with existing targets get \
+                 no album gain. Refused for normalized or mixed down audio, and for MP4 and \
+                 WAV outputs",
+                "STYLE");
+    opts.optopt("",
+                "metadata",
+                "What happens to the global and stream metadata of sources: copy (default) or \
+                 strip",
+                "POLICY");
+    opts.optmulti("",
+                  "tag",
+                  "Set the tag KEY on every target. {name}, {stem} and {profile} in TEMPLATE \
+                   are replaced with the file name of the source, the file name without \
+                   extension and the --format",
+                  "KEY=TEMPLATE");
+    opts.optflag("",
+                 "title-from-name",
+                 "Set the title of every target to the file name of the source without \
+                  extension");
+    opts.optopt("",
+                "languages",
+                "Keep only audio and subtitle streams in LANGS, in order of preference, e.g. \
+                 eng,dan,und",
+                "LANGS");
+    opts.optflag("",
+                 "drop-commentary",
+                 "Drop commentary audio and subtitle streams");
+    opts.optflag("", "forced-subtitles", "Keep only forced subtitles");
+    opts.optflag("",
+                 "best-audio",
+                 "Keep only the audio stream with the most channels of each language");
+    opts.optflag("",
+                 "no-covers",
+                 "Do not add cover.jpg, folder.jpg or poster.jpg next to a source to its \
+                  target");
+    opts.optopt("",
+                "jobs",
+                "Number of files to probe at the same time (default: number of CPUs)",
+                "N");
+    opts.optopt("",
+                "format-registry",
+                "Read extra container formats from FILE, one 'CATEGORY FORMAT_NAME' per line \
+                 (categories: video, audio, image, subtitle, playlist)",
+                "FILE");
+    opts.optflag("",
+                 "strict",
+                 "Abort when a file cannot be read or probed instead of skipping it");
+    opts.optflag("",
+                 "no-probe-cache",
+                 "Do not read or write the cache of ffprobe results");
     opts
 }
 
@@ -71,6 +226,20 @@ pub struct Args {
     pub paths: Vec<String>,
     pub dry_run: bool,
     pub format: Option<String>,
+    pub collisions: collision::Policy,
+    pub companions: companion::Policy,
+    pub companion_selector: companion::Selector,
+    pub discovery: source::Options,
+    pub probe_cache: bool,
+    pub format_registry: Option<String>,
+    pub verify: verify::Level,
+    pub quality: quality::Options,
+    pub report: Option<String>,
+    pub gain: Option<gain::Style>,
+    pub streams: streams::Rules,
+    /// Add cover images found next to the sources
+    pub covers: bool,
+    pub metadata: metadata::Options,
 }
 
 impl Args {
@@ -99,6 +268,103 @@ impl Args {
         let dry_run = args.opt_present("dry-run");
         let format = args.opt_str("format");
 
+        let collisions = try!(parse_value(&program_name,
+                                          &args,
+                                          "collisions",
+                                          collision::Policy::from_str))
+            .unwrap_or_default();
+
+        let companions = try!(parse_value(&program_name,
+                                          &args,
+                                          "companions",
+                                          companion::Policy::from_str))
+            .unwrap_or_default();
+
+        let mime_fallback = args.opt_present("mime-fallback");
+        let probe_cache = !args.opt_present("no-probe-cache");
+        let format_registry = args.opt_str("format-registry");
+        let verify = try!(parse_value(&program_name, &args, "verify", verify::Level::from_str))
+            .unwrap_or_default();
+        let report = args.opt_str("report");
+        let gain = try!(parse_value(&program_name, &args, "gain-tags", gain::Style::from_str));
+        let mut tags = try!(parse_tags(&program_name, args.opt_strs("tag")));
+        if args.opt_present("title-from-name") {
+            tags.extend(metadata::parse_tag("title={stem}"));
+        }
+        let metadata = metadata::Options {
+            policy: try!(parse_value(&program_name, &args, "metadata", metadata::Policy::from_str))
+                .unwrap_or_default(),
+            tags: tags,
+        };
+        let streams = streams::Rules {
+            languages: try!(parse_value(&program_name,
+                                        &args,
+                                        "languages",
+                                        streams::parse_languages))
+                .unwrap_or_default(),
+            drop_commentary: args.opt_present("drop-commentary"),
+            forced_subtitles: args.opt_present("forced-subtitles"),
+            best_audio: args.opt_present("best-audio"),
+        };
+        let covers = !args.opt_present("no-covers");
+
+        let quality = quality::Options {
+            metrics: try!(parse_value(&program_name,
+                                      &args,
+                                      "quality",
+                                      quality::Metric::parse_list))
+                .unwrap_or_default(),
+            subsample: try!(parse_value(&program_name, &args, "quality-subsample", parse_jobs))
+                .unwrap_or(1) as u64,
+            min_ssim: try!(parse_value(&program_name, &args, "min-ssim", parse_score)),
+            min_psnr: try!(parse_value(&program_name, &args, "min-psnr", parse_score)),
+            min_vmaf: try!(parse_value(&program_name, &args, "min-vmaf", parse_score)),
+        };
+
+        let companion_selector = companion::Selector {
+            globs: try!(parse_globs(&program_name, "companion", args.opt_strs("companion"))),
+            mime_types: try!(parse_globs(&program_name,
+                                         "companion-mime",
+                                         args.opt_strs("companion-mime"))),
+            mime_fallback: mime_fallback,
+        };
+
+        let filter = filter::Filter {
+            include: try!(parse_globs(&program_name, "include", args.opt_strs("include"))),
+            exclude: try!(parse_globs(&program_name, "exclude", args.opt_strs("exclude"))),
+            min_size: try!(parse_value(&program_name, &args, "min-size", utils::parse_size)),
+            max_size: try!(parse_value(&program_name, &args, "max-size", utils::parse_size)),
+            min_duration: try!(parse_value(&program_name,
+                                           &args,
+                                           "min-duration",
+                                           utils::parse_duration)),
+            max_duration: try!(parse_value(&program_name,
+                                           &args,
+                                           "max-duration",
+                                           utils::parse_duration)),
+            hidden: args.opt_present("hidden"),
+            ignore_files: !args.opt_present("no-ignore-files"),
+        };
+
+        let walk = path::WalkOptions {
+            symlinks: try!(parse_value(&program_name,
+                                       &args,
+                                       "symlinks",
+                                       path::SymlinkPolicy::from_str))
+                .unwrap_or_default(),
+            one_file_system: args.opt_present("one-file-system"),
+        };
+
+        let discovery = source::Options {
+            filter: filter,
+            walk: walk,
+            mime_fallback: mime_fallback,
+            jobs: try!(parse_value(&program_name, &args, "jobs", parse_jobs))
+                .unwrap_or_else(utils::default_jobs),
+            strict: args.opt_present("strict"),
+            formats: Default::default(),
+        };
+
         let (source_dir, target_dir, mut files) = match (args.free.len(), args.free) {
             (0, _) => return Err(Error::MissingSourceDir { program_name: program_name }),
             (1, _) => return Err(Error::MissingTargetDir { program_name: program_name }),
@@ -116,6 +382,19 @@ impl Args {
             dry_run: dry_run,
             paths: files,
             format: format,
+            collisions: collisions,
+            companions: companions,
+            companion_selector: companion_selector,
+            discovery: discovery,
+            probe_cache: probe_cache,
+            format_registry: format_registry,
+            verify: verify,
+            quality: quality,
+            report: report,
+            gain: gain,
+            streams: streams,
+            covers: covers,
+            metadata: metadata,
         })
     }
     pub fn from_env() -> Result<Args, Error> {
@@ -126,3 +405,74 @@ impl Args {
         return format!("{} path0 path1 path2", program_name);
     }
 }
+
+fn parse_value<T, F>(program_name: &str,
+                     args: &getopts::Matches,
+                     option: &'static str,
+                     parse: F)
+                     -> Result<Option<T>, Error>
+    where F: Fn(&str) -> Option<T>
+{
+    match args.opt_str(option) {
+        None => Ok(None),
+        Some(value) => {
+            match parse(&value) {
+                Some(v) => Ok(Some(v)),
+                None => {
+                    Err(Error::InvalidValue {
+                        program_name: program_name.to_string(),
+                        option: option,
+                        value: value,
+                    })
+                }
+            }
+        }
+    }
+}
+
+fn parse_jobs(s: &str) -> Option<usize> {
+    match s.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(n) => Some(n),
+    }
+}
+
+fn parse_score(s: &str) -> Option<f64> {
+    match s.parse() {
+        Ok(n) if n >= 0. => Some(n),
+        _ => None,
+    }
+}
+
+fn parse_tags(program_name: &str,
+              values: Vec<String>)
+              -> Result<Vec<(String, metadata::Template)>, Error> {
+    values.into_iter()
+        .map(|value| {
+            metadata::parse_tag(&value).ok_or_else(|| {
+                Error::InvalidValue {
+                    program_name: program_name.to_string(),
+                    option: "tag",
+                    value: value.clone(),
+                }
+            })
+        })
+        .collect()
+}
+
+fn parse_globs(program_name: &str,
+               option: &'static str,
+               values: Vec<String>)
+               -> Result<Vec<glob::Pattern>, Error> {
+    values.into_iter()
+        .map(|value| {
+            glob::Pattern::new(&value).map_err(|_| {
+                Error::InvalidValue {
+                    program_name: program_name.to_string(),
+                    option: option,
+                    value: value.clone(),
+                }
+            })
+        })
+        .collect()
+}
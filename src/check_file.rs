@@ -6,6 +6,7 @@ use std::io;
 use std::string::FromUtf8Error;
 use std::error::Error as StdError;
 use std::fmt;
+use sniff;
 
 const MIME_WHITELIST: &'static [&'static str] = &["audio/mpeg",
                                                   "application/octet-stream",
@@ -19,8 +20,23 @@ const MIME_WHITELIST: &'static [&'static str] = &["audio/mpeg",
                                                   "video/x-ms-asf",
                                                   "video/x-msvideo",
                                                   "audio/ogg",
-                                                  "audio/x-wav"];
+                                                  "audio/x-wav",
+                                                  "audio/aac",
+                                                  "audio/flac",
+                                                  "audio/x-flac",
+                                                  "audio/x-ape",
+                                                  "audio/x-aiff",
+                                                  "audio/x-wavpack",
+                                                  "audio/mp4",
+                                                  "video/3gpp",
+                                                  "video/mp2t",
+                                                  "video/ogg"];
 const MIME_BLACKLIST: &'static [&'static str] = &["application/CDFV2",
+                                                  "application/x-unknown",
+                                                  "application/x-rar",
+                                                  "application/x-dosexec",
+                                                  "image/bmp",
+                                                  "image/tiff",
                                                   "application/pdf",
                                                   "application/x-7z-compressed",
                                                   "application/x-executable",
@@ -39,11 +55,13 @@ const MIME_BLACKLIST: &'static [&'static str] = &["application/CDFV2",
                                                   "image/svg+xml",
                                                   "application/zip",
                                                   "application/x-shockwave-flash",
-                                                  "image/x-xcf"];
+                                                  "image/x-xcf",
+                                                  "image/webp"];
 
 
 #[derive(Debug)]
 pub enum ErrorKind {
+    ReadError(io::Error),
     RunError(io::Error),
     StdOutUTF8Error(FromUtf8Error),
     StdErrUTF8Error(FromUtf8Error),
@@ -68,11 +86,32 @@ impl Error {
             kind: kind,
         };
     }
+
+    /// Whether `file` could not be run at all
+    pub fn is_run_error(&self) -> bool {
+        match self.kind {
+            ErrorKind::RunError(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Like the `Display` output, but without the path
+    pub fn reason(&self) -> String {
+        match self.kind {
+            ErrorKind::ReadError(ref e) |
+            ErrorKind::RunError(ref e) => format!("{}: {}", self.description(), e),
+            ErrorKind::UnknownMimeType { ref mime_type } => {
+                format!("{} {:?}", self.description(), mime_type)
+            }
+            _ => self.description().to_string(),
+        }
+    }
 }
 
 impl StdError for Error {
     fn description(&self) -> &str {
         match self.kind {
+            ErrorKind::ReadError(_) => "Could not read file",
             ErrorKind::RunError(ref e) if e.kind() == io::ErrorKind::NotFound => {
                 "Could not find executable 'file'"
             }
@@ -86,6 +125,7 @@ impl StdError for Error {
     }
     fn cause(&self) -> Option<&StdError> {
         match (*self).kind {
+            ErrorKind::ReadError(ref e) => Some(e),
             ErrorKind::RunError(ref e) => Some(e),
             ErrorKind::StdOutUTF8Error(ref e) => Some(e),
             ErrorKind::StdErrUTF8Error(ref e) => Some(e),
@@ -98,6 +138,7 @@ impl StdError for Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.kind {
+            ErrorKind::ReadError(_) |
             ErrorKind::StdOutUTF8Error(_) |
             ErrorKind::StdErrUTF8Error(_) |
             ErrorKind::ParseError { .. } => write!(f, "{:?}: {}", self.path, self.description()),
@@ -113,10 +154,12 @@ impl fmt::Display for Error {
 
 
 pub fn check_files<'a, T: AsRef<Path>, U: Iterator<Item = T>>
-    (paths: U)
+    (paths: U,
+     fallback: bool)
      -> Result<(Vec<T>, Vec<T>), Error> {
-    let paths: Vec<(T, bool)> = try!(paths.map(|p| check_file(p.as_ref()).map(|r| (p, r)))
-        .collect());
+    let paths: Vec<(T, bool)> =
+        try!(paths.map(|p| check_file(p.as_ref(), fallback).map(|r| (p, r)))
+            .collect());
 
     let (good, bad): (Vec<_>, Vec<_>) = paths.into_iter().partition(|&(_, ref f)| *f);
 
@@ -126,7 +169,40 @@ pub fn check_files<'a, T: AsRef<Path>, U: Iterator<Item = T>>
     Ok((good.collect(), bad.collect()))
 }
 
-pub fn check_file<'a>(path: &Path) -> Result<bool, Error> {
+pub fn check_file<'a>(path: &Path, fallback: bool) -> Result<bool, Error> {
+    let mime_type = try!(mime_type(path, fallback));
+    check_mime_type(path, &mime_type)
+}
+
+/// Whether a file of `mime_type` is a video/audio file. Mime-types on
+/// neither list are an error.
+pub fn check_mime_type(path: &Path, mime_type: &str) -> Result<bool, Error> {
+    if MIME_BLACKLIST.contains(&mime_type) {
+        return Ok(false);
+    }
+    if MIME_WHITELIST.contains(&mime_type) {
+        return return Ok(true);
+    }
+
+    return Err(Error::new(path,
+                          ErrorKind::UnknownMimeType { mime_type: mime_type.to_string() }));
+}
+
+/// Detects the mime-type from the first bytes of the file. Unrecognized
+/// binary content is passed on to the external `file` command when
+/// `fallback` is set.
+pub fn mime_type(path: &Path, fallback: bool) -> Result<String, Error> {
+    let mime_type = match sniff::sniff_path(path) {
+        Ok(m) => m,
+        Err(e) => return Err(Error::new(path, ErrorKind::ReadError(e))),
+    };
+    if mime_type != sniff::UNKNOWN || !fallback {
+        return Ok(mime_type.to_string());
+    }
+    file_mime_type(path)
+}
+
+fn file_mime_type(path: &Path) -> Result<String, Error> {
     let mut c = Command::new("file");
 
     c.args(&[OsStr::new("--mime-type"),
@@ -157,17 +233,7 @@ pub fn check_file<'a>(path: &Path) -> Result<bool, Error> {
                               }));
     }
 
-    let mime_type = stdout.trim();
-
-    if MIME_BLACKLIST.contains(&mime_type) {
-        return Ok(false);
-    }
-    if MIME_WHITELIST.contains(&mime_type) {
-        return return Ok(true);
-    }
-
-    return Err(Error::new(path,
-                          ErrorKind::UnknownMimeType { mime_type: mime_type.to_string() }));
+    Ok(stdout.trim().to_string())
 
     // println!("Stdout:\n{:#?}\n", stdout.trim());
     // println!("Stderr:\n{:#?}\n", stderr.trim());
@@ -0,0 +1,121 @@
+use std::ffi::OsString;
+use std::iter::Peekable;
+use super::super::{next_option, Codec as CodecTrait, Error};
+use super::channels::{Channels, Remix};
+use super::loudnorm::{Loudnorm, Measurement};
+use super::settings::Settings;
+use ffprobe::Audio;
+
+// The native encoder handles the standard layouts up to 7.1
+const MAX_CHANNELS: u64 = 8;
+const RATES: &'static [u64] = &[8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000,
+                                 64000, 88200, 96000];
+
+/// AAC-LC with the native encoder of ffmpeg
+#[derive(Clone, Debug)]
+pub struct Codec {
+    bitrate: u64,
+    channels: Channels,
+    settings: Settings,
+    /// Computed for each source by `for_source`
+    remix: Option<Remix>,
+    source_rate: Option<u64>,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec {
+            bitrate: 160,
+            channels: Channels::default(),
+            settings: Settings::default(),
+            remix: None,
+            source_rate: None,
+        }
+    }
+}
+
+impl Codec {
+    pub fn bitrate(&self) -> u64 {
+        self.bitrate
+    }
+    pub fn loudnorm(&self) -> Option<&Loudnorm> {
+        self.settings.loudnorm.as_ref()
+    }
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        Codec { settings: self.settings.with_loudness(measured), ..self.clone() }
+    }
+    pub fn for_source(&self, audio: Option<&Audio>) -> Self {
+        Codec {
+            remix: audio.map(|a| Remix::new(self.channels, a, MAX_CHANNELS)),
+            source_rate: audio.and_then(|a| a.sample_rate),
+            ..self.clone()
+        }
+    }
+    pub fn with_channels(&self, channels: Channels) -> Self {
+        Codec { channels: channels, ..self.clone() }
+    }
+    /// The remix that `for_source` resolved
+    pub fn remix(&self) -> Option<&Remix> {
+        self.remix.as_ref()
+    }
+    /// The remix, followed by the loudnorm filter of the measurement pass
+    pub fn measure_filter(&self) -> Option<String> {
+        self.settings.measure_filter(self.remix.iter().flat_map(|r| r.filters.clone()).collect())
+    }
+    /// Arguments for the `n`th audio stream of the output
+    pub fn stream_args(&self, n: usize) -> Vec<OsString> {
+        self.args(Some(n))
+    }
+    fn args(&self, stream: Option<usize>) -> Vec<OsString> {
+        let options = [("c", "aac".to_string()), ("b", format!("{}k", self.bitrate))];
+        let filters: Vec<String> = self.remix
+            .iter()
+            .flat_map(|r| r.filters.clone())
+            .chain(self.settings.filters(None, None, self.source_rate))
+            .collect();
+        super::encoder_args(stream, &options, &[], &filters)
+    }
+}
+
+impl CodecTrait for Codec {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
+        let bitrate = match args.next() {
+            Some(s) => s,
+            None => return Err(Error::TooShort)
+        };
+
+        let bitrate = match bitrate.parse::<i64>() {
+            Ok(s) if s >= 16 && s <= 512 => s as u64,
+            Ok(_) => return Err(Error::InvalidArg(bitrate.to_string(), "Bitrate must be between 16 and 512")),
+            Err(_) => return Err(Error::InvalidArg(bitrate.to_string(), "Bitrate must be a number"))
+        };
+
+        let mut codec = Codec { bitrate: bitrate, ..Codec::default() };
+        // Optional key=value settings follow the bitrate
+        while let Some((key, value)) = next_option(&mut args, &["channels", "loudnorm", "rate", "resampler"]) {
+            match key {
+                "channels" => codec.channels = match Channels::parse(value) {
+                    Some(c) => c,
+                    None => return Err(Error::InvalidArg(value.to_string(), "Channels must be keep, mono, stereo or a count from 1 to 8")),
+                },
+                _ => try!(codec.settings.set(key, value)),
+            }
+        }
+        match codec.settings.sampling.rate {
+            Some(rate) if !RATES.contains(&rate) => {
+                return Err(Error::InvalidArg(rate.to_string(), "AAC takes the rates of MPEG-4 from 8000 to 96000 Hz"))
+            }
+            _ => (),
+        }
+        Ok((codec, args))
+    }
+    fn to_ffmpeg_args<'a>(&self) -> Vec<OsString> {
+        self.args(None)
+    }
+    fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
+        (None, Some("aac"))
+    }
+    fn to_examples() -> Vec<Vec<&'static str>> {
+        vec![vec!["160"], vec!["256", "channels=6", "rate=44.1k"]]
+    }
+}
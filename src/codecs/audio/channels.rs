@@ -0,0 +1,193 @@
+use ffprobe::Audio;
+
+// Downmix levels of ITU-R BS.775, with the LFE kept at -6 dB so stereo
+// speakers still get the bass
+const CENTRE: f64 = 0.707;
+const SURROUND: f64 = 0.707;
+const LFE: f64 = 0.5;
+
+/// The channels of common layouts, named as ffmpeg names them
+const LAYOUTS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("mono", &["FC"]),
+    ("stereo", &["FL", "FR"]),
+    ("2.1", &["FL", "FR", "LFE"]),
+    ("3.0", &["FL", "FR", "FC"]),
+    ("3.0(back)", &["FL", "FR", "BC"]),
+    ("3.1", &["FL", "FR", "FC", "LFE"]),
+    ("4.0", &["FL", "FR", "FC", "BC"]),
+    ("quad", &["FL", "FR", "BL", "BR"]),
+    ("quad(side)", &["FL", "FR", "SL", "SR"]),
+    ("4.1", &["FL", "FR", "FC", "LFE", "BC"]),
+    ("5.0", &["FL", "FR", "FC", "BL", "BR"]),
+    ("5.0(side)", &["FL", "FR", "FC", "SL", "SR"]),
+    ("5.1", &["FL", "FR", "FC", "LFE", "BL", "BR"]),
+    ("5.1(side)", &["FL", "FR", "FC", "LFE", "SL", "SR"]),
+    ("6.0", &["FL", "FR", "FC", "BC", "SL", "SR"]),
+    ("6.1", &["FL", "FR", "FC", "LFE", "BC", "SL", "SR"]),
+    ("6.1(back)", &["FL", "FR", "FC", "LFE", "BL", "BR", "BC"]),
+    ("7.0", &["FL", "FR", "FC", "BL", "BR", "SL", "SR"]),
+    ("7.1", &["FL", "FR", "FC", "LFE", "BL", "BR", "SL", "SR"]),
+    ("7.1(wide)", &["FL", "FR", "FC", "LFE", "BL", "BR", "FLC", "FRC"]),
+    ("7.1(wide-side)", &["FL", "FR", "FC", "LFE", "FLC", "FRC", "SL", "SR"]),
+];
+
+/// What happens to the channels of the source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channels {
+    Keep,
+    /// Downmixes sources with more channels
+    Max(u64),
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Channels::Keep
+    }
+}
+
+impl Channels {
+    /// Parses `keep`, `mono`, `stereo` or a channel count from 1 to 8
+    pub fn parse(s: &str) -> Option<Channels> {
+        match s {
+            "keep" => Some(Channels::Keep),
+            "mono" => Some(Channels::Max(1)),
+            "stereo" => Some(Channels::Max(2)),
+            s => {
+                match s.parse::<u64>() {
+                    Ok(n) if n >= 1 && n <= 8 => Some(Channels::Max(n)),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// The layout ffmpeg uses by default for `count` channels
+pub fn standard_layout(count: u64) -> Option<&'static str> {
+    match count {
+        1 => Some("mono"),
+        2 => Some("stereo"),
+        3 => Some("3.0"),
+        4 => Some("quad"),
+        5 => Some("5.0"),
+        6 => Some("5.1"),
+        7 => Some("6.1"),
+        8 => Some("7.1"),
+        _ => None,
+    }
+}
+
+fn layout_channels(layout: &str) -> Option<&'static [&'static str]> {
+    LAYOUTS.iter().find(|&&(name, _)| name == layout).map(|&(_, channels)| channels)
+}
+
+/// How much of a source channel goes to the left and right output
+fn coefficients(channel: &str) -> (f64, f64) {
+    match channel {
+        "FL" | "FLC" => (1., 0.),
+        "FR" | "FRC" => (0., 1.),
+        "FC" => (CENTRE, CENTRE),
+        "LFE" => (LFE, LFE),
+        "BL" | "SL" => (SURROUND, 0.),
+        "BR" | "SR" => (0., SURROUND),
+        "BC" => (SURROUND * CENTRE, SURROUND * CENTRE),
+        _ => (0., 0.),
+    }
+}
+
+/// A `pan` expression for one output channel, e.g. `FL+0.707*FC`
+fn pan_sum<F: Fn((f64, f64)) -> f64>(channels: &[&str], coefficient: F) -> String {
+    channels.iter()
+        .map(|&c| (c, coefficient(coefficients(c))))
+        .filter(|&(_, k)| k > 0.)
+        .map(|(c, k)| match k == 1. {
+            true => c.to_string(),
+            false => format!("{}*{}", k, c),
+        })
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+/// The result of remixing a source
+#[derive(Clone, Debug, PartialEq)]
+pub struct Remix {
+    /// Filters that go before any other audio filter
+    pub filters: Vec<String>,
+    /// Channel count of the output, if known
+    pub channels: Option<u64>,
+    /// Channel layout of the output, if known
+    pub layout: Option<String>,
+}
+
+impl Remix {
+    /// Remixes `source` to at most `limit` channels, the most an encoder
+    /// supports. Mono and stereo downmixes of known layouts use explicit
+    /// centre, surround and LFE levels, other downmixes are left to ffmpeg.
+    pub fn new(channels: Channels, source: &Audio, limit: u64) -> Remix {
+        let layout = source.layout.as_ref().map(|l| l.as_str());
+        let known = layout.and_then(layout_channels);
+        let count = source.channels.or(known.map(|c| c.len() as u64));
+        let max = match channels {
+            Channels::Keep => limit,
+            Channels::Max(n) => ::std::cmp::min(n, limit),
+        };
+
+        match count {
+            Some(c) if c > max => (),
+            _ => {
+                return Remix {
+                    filters: Vec::new(),
+                    channels: count,
+                    layout: layout.map(|l| l.to_string()),
+                }
+            }
+        }
+        let filter = match (max, known) {
+            (1, Some(known)) => format!("pan=mono|c0<{}", pan_sum(known, |(l, r)| l + r)),
+            (2, Some(known)) => {
+                format!("pan=stereo|FL<{}|FR<{}",
+                        pan_sum(known, |(l, _)| l),
+                        pan_sum(known, |(_, r)| r))
+            }
+            _ => {
+                match standard_layout(max) {
+                    Some(l) => format!("aformat=channel_layouts={}", l),
+                    None => format!("aformat=channel_layouts={}c", max),
+                }
+            }
+        };
+        Remix {
+            filters: vec![filter],
+            channels: Some(max),
+            layout: standard_layout(max).map(|l| l.to_string()),
+        }
+    }
+}
+
+#[test]
+fn test_remix() {
+    let source = Audio {
+        index: 1,
+        codec: "ac3".to_string(),
+        channels: Some(6),
+        layout: Some("5.1(side)".to_string()),
+        sample_rate: Some(48000),
+        bits: None,
+    };
+    let stereo = Remix::new(Channels::parse("stereo").unwrap(), &source, 8);
+    assert_eq!(stereo.filters,
+               vec!["pan=stereo|FL<FL+0.707*FC+0.5*LFE+0.707*SL|FR<FR+0.707*FC+0.5*LFE+0.707*SR"]);
+    assert_eq!(stereo.channels, Some(2));
+
+    let mono = Remix::new(Channels::Max(1), &source, 8);
+    assert_eq!(mono.filters,
+               vec!["pan=mono|c0<FL+FR+1.414*FC+LFE+0.707*SL+0.707*SR"]);
+
+    let kept = Remix::new(Channels::Keep, &source, 8);
+    assert_eq!(kept.filters, Vec::<String>::new());
+    assert_eq!(kept.layout, Some("5.1(side)".to_string()));
+
+    let quad = Remix::new(Channels::Max(4), &source, 8);
+    assert_eq!(quad.filters, vec!["aformat=channel_layouts=quad"]);
+    assert!(Channels::parse("9").is_none());
+}
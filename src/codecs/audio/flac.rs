@@ -0,0 +1,76 @@
+use std::ffi::OsString;
+use std::iter::Peekable;
+use super::super::{next_option, Codec as CodecTrait, Error};
+use super::loudnorm::{Loudnorm, Measurement};
+use super::settings::Settings;
+use ffprobe::Audio;
+
+/// Lossless FLAC
+#[derive(Clone, Debug, Default)]
+pub struct Codec {
+    /// 16 or 24, the bits of the source if not set
+    bits: Option<u64>,
+    settings: Settings,
+    /// Computed for each source by `for_source`
+    source_bits: Option<u64>,
+    source_rate: Option<u64>,
+}
+
+impl Codec {
+    pub fn for_source(&self, audio: Option<&Audio>) -> Self {
+        Codec {
+            source_bits: audio.and_then(|a| a.bits),
+            source_rate: audio.and_then(|a| a.sample_rate),
+            ..self.clone()
+        }
+    }
+    pub fn loudnorm(&self) -> Option<&Loudnorm> {
+        self.settings.loudnorm.as_ref()
+    }
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        Codec { settings: self.settings.with_loudness(measured), ..self.clone() }
+    }
+    /// The loudnorm filter of the measurement pass
+    pub fn measure_filter(&self) -> Option<String> {
+        self.settings.measure_filter(vec![])
+    }
+    /// Arguments for the `n`th audio stream of the output
+    pub fn stream_args(&self, n: usize) -> Vec<OsString> {
+        self.args(Some(n))
+    }
+    fn args(&self, stream: Option<usize>) -> Vec<OsString> {
+        // 24 bit samples are passed to the encoder in 32 bits
+        let format = self.bits.map(|bits| match bits {
+            16 => ("s16", 16),
+            _ => ("s32", 24),
+        });
+        let filters: Vec<String> = self.settings.filters(format, self.source_bits, self.source_rate);
+        super::encoder_args(stream, &[("c", "flac".to_string())], &[], &filters)
+    }
+}
+
+impl CodecTrait for Codec {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
+        let mut codec = Codec::default();
+        while let Some((key, value)) = next_option(&mut args, &["bits", "loudnorm", "rate", "resampler"]) {
+            match key {
+                "bits" => codec.bits = match value {
+                    "16" => Some(16),
+                    "24" => Some(24),
+                    _ => return Err(Error::InvalidArg(value.to_string(), "Bits must be 16 or 24")),
+                },
+                _ => try!(codec.settings.set(key, value)),
+            }
+        }
+        Ok((codec, args))
+    }
+    fn to_ffmpeg_args<'a>(&self) -> Vec<OsString> {
+        self.args(None)
+    }
+    fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
+        (None, Some("flac"))
+    }
+    fn to_examples() -> Vec<Vec<&'static str>> {
+        vec![vec![], vec!["bits=16", "rate=44.1k", "resampler=soxr:28"]]
+    }
+}
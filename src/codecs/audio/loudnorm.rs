@@ -0,0 +1,185 @@
+use rustc_serialize::json::Json;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Measures the whole file first, then normalizes linearly
+    TwoPass,
+    /// Normalizes in a single pass, adjusting the gain as it goes
+    Dynamic,
+}
+
+/// Values measured by the first pass of `loudnorm`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    /// Integrated loudness in LUFS, minus infinity for silence
+    pub integrated: f64,
+    /// True peak in dBTP
+    pub true_peak: f64,
+    /// Loudness range in LU
+    pub range: f64,
+    pub threshold: f64,
+    pub offset: f64,
+}
+
+impl Measurement {
+    /// Silence has no loudness to normalize, and `loudnorm` would only
+    /// amplify the noise floor
+    pub fn is_silent(&self) -> bool {
+        self.integrated == f64::NEG_INFINITY
+    }
+}
+
+/// EBU R128 loudness normalization
+#[derive(Clone, Debug, PartialEq)]
+pub struct Loudnorm {
+    pub mode: Mode,
+    /// Target integrated loudness in LUFS
+    pub integrated: f64,
+    /// Maximum true peak in dBTP
+    pub true_peak: f64,
+    /// Target loudness range in LU
+    pub range: f64,
+    pub measured: Option<Measurement>,
+}
+
+impl Loudnorm {
+    /// Parses `MODE[:I[:TP[:LRA]]]`, e.g. `two-pass:-16:-1.5:11`
+    pub fn parse(s: &str) -> Option<Loudnorm> {
+        let mut parts = s.split(':');
+        let mode = match parts.next() {
+            Some("two-pass") => Mode::TwoPass,
+            Some("dynamic") => Mode::Dynamic,
+            _ => return None,
+        };
+        let mut values = [-23., -1., 7.];
+        for (n, part) in parts.enumerate() {
+            match (values.get_mut(n), part.parse::<f64>()) {
+                (Some(value), Ok(v)) => *value = v,
+                _ => return None,
+            }
+        }
+        let (integrated, true_peak, range) = (values[0], values[1], values[2]);
+        // The ranges accepted by the filter
+        if integrated < -70. || integrated > -5. || true_peak < -9. || true_peak > 0. ||
+           range < 1. || range > 50. {
+            return None;
+        }
+        Some(Loudnorm {
+            mode: mode,
+            integrated: integrated,
+            true_peak: true_peak,
+            range: range,
+            measured: None,
+        })
+    }
+
+    fn targets(&self) -> String {
+        format!("loudnorm=I={}:TP={}:LRA={}",
+                self.integrated,
+                self.true_peak,
+                self.range)
+    }
+
+    /// Whether a measurement pass has to run before encoding
+    pub fn needs_measurement(&self) -> bool {
+        self.mode == Mode::TwoPass && self.measured.is_none()
+    }
+
+    /// The filter of the measurement pass
+    pub fn measure_filter(&self) -> String {
+        format!("{}:print_format=json", self.targets())
+    }
+
+    /// The filter used while encoding. Without a measurement the filter
+    /// falls back to dynamic normalization, and silence is left as it is.
+    pub fn filter(&self) -> Option<String> {
+        match self.measured {
+            Some(m) if m.is_silent() => None,
+            Some(m) if self.mode == Mode::TwoPass => {
+                Some(format!("{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:\
+                              offset={}:linear=true",
+                             self.targets(),
+                             m.integrated,
+                             m.true_peak,
+                             m.range,
+                             m.threshold,
+                             m.offset))
+            }
+            _ => Some(self.targets()),
+        }
+    }
+}
+
+/// Reads the JSON that `loudnorm` prints at the end of the measurement pass
+pub fn parse_measurement(stderr: &str) -> Option<Measurement> {
+    let start = match stderr.rfind('{') {
+        Some(n) => n,
+        None => return None,
+    };
+    let end = match stderr[start..].find('}') {
+        Some(n) => start + n + 1,
+        None => return None,
+    };
+    let json = match Json::from_str(&stderr[start..end]) {
+        Ok(j) => j,
+        Err(_) => return None,
+    };
+    // The values are printed as strings, and as "-inf" for silence
+    let get = |key: &str| {
+        json.find(key)
+            .and_then(|v| v.as_string())
+            .and_then(|v| v.parse::<f64>().ok())
+    };
+    match (get("input_i"), get("input_tp"), get("input_lra"), get("input_thresh"), get("target_offset")) {
+        (Some(i), Some(tp), Some(lra), Some(thresh), Some(offset))
+            if i == f64::NEG_INFINITY || [i, tp, lra, thresh, offset].iter().all(|v| v.is_finite()) => {
+            Some(Measurement {
+                integrated: i,
+                true_peak: tp,
+                range: lra,
+                threshold: thresh,
+                offset: offset,
+            })
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_parse_measurement() {
+    let stderr = "size=N/A time=00:03:20.00 bitrate=N/A speed= 200x\n\
+                  [Parsed_loudnorm_0 @ 0x55d]\n\
+                  {\n\
+                  \t\"input_i\" : \"-27.61\",\n\
+                  \t\"input_tp\" : \"-4.47\",\n\
+                  \t\"input_lra\" : \"18.06\",\n\
+                  \t\"input_thresh\" : \"-39.20\",\n\
+                  \t\"output_i\" : \"-16.58\",\n\
+                  \t\"normalization_type\" : \"dynamic\",\n\
+                  \t\"target_offset\" : \"0.58\"\n\
+                  }\n";
+    let measured = parse_measurement(stderr);
+    assert_eq!(measured,
+               Some(Measurement {
+                   integrated: -27.61,
+                   true_peak: -4.47,
+                   range: 18.06,
+                   threshold: -39.2,
+                   offset: 0.58,
+               }));
+    assert_eq!(parse_measurement("no json"), None);
+
+    let loudnorm = Loudnorm { measured: measured, ..Loudnorm::parse("two-pass:-16").unwrap() };
+    assert_eq!(loudnorm.filter(),
+               Some("loudnorm=I=-16:TP=-1:LRA=7:measured_I=-27.61:measured_TP=-4.47:\
+                     measured_LRA=18.06:measured_thresh=-39.2:offset=0.58:linear=true"
+                   .to_string()));
+
+    let silence = parse_measurement("{\"input_i\" : \"-inf\", \"input_tp\" : \"-inf\", \
+                                      \"input_lra\" : \"0.00\", \"input_thresh\" : \"-inf\", \
+                                      \"target_offset\" : \"inf\"}");
+    assert!(silence.map_or(false, |s| s.is_silent()));
+    assert_eq!(Loudnorm { measured: silence, ..loudnorm }.filter(), None);
+    assert!(Loudnorm::parse("dynamic:-80").is_none());
+    assert!(Loudnorm::parse("loud").is_none());
+}
@@ -1,12 +1,63 @@
+mod aac;
+pub mod channels;
+mod flac;
+pub mod loudnorm;
+mod mp3;
 mod opus;
+mod pcm;
+pub mod sampling;
+mod settings;
 use super::{Codec as CodecTrait, Error};
+use ffprobe::{Audio, FFProbe};
+use streams::Selection;
 use std::ffi;
-use std::iter::once;
+use std::iter::{once, Peekable};
 
 
+/// Arguments of an encoder for the `n`th audio stream of the output. Without
+/// a stream the `options` of the encoder, like `("c", "aac")`, apply to
+/// every audio stream, and the `source_options` and `filters`, which
+/// `for_source` resolved for one stream, to the first.
+fn encoder_args(stream: Option<usize>,
+                options: &[(&str, String)],
+                source_options: &[(&str, String)],
+                filters: &[String])
+                -> Vec<ffi::OsString> {
+    let (all, first) = match stream {
+        Some(n) => (format!(":a:{}", n), format!(":a:{}", n)),
+        None => (":a".to_string(), ":a:0".to_string()),
+    };
+    let mut args: Vec<ffi::OsString> = Vec::new();
+    for &(option, ref value) in options {
+        args.extend(vec![format!("-{}{}", option, all).into(), value.into()]);
+    }
+    for &(option, ref value) in source_options {
+        args.extend(vec![format!("-{}{}", option, first).into(), value.into()]);
+    }
+    if !filters.is_empty() {
+        args.extend(vec![format!("-filter{}", first).into(), filters.join(",").into()]);
+    }
+    args
+}
+
+/// The stream that becomes the first audio stream of the output: the first
+/// of `selection`, or else the one ffmpeg picks
+pub fn first_kept(probe: &FFProbe, selection: Option<&Selection>) -> Option<Audio> {
+    match selection.and_then(|s| s.audio.first()) {
+        Some(&index) => probe.stream(index).and_then(Audio::from_stream),
+        None => probe.audio.clone(),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Codec {
-    Opus(opus::Codec)
+    Opus(opus::Codec),
+    Aac(aac::Codec),
+    Mp3(mp3::Codec),
+    Flac(flac::Codec),
+    Pcm(pcm::Codec),
+    /// Keeps the audio as it is
+    Copy,
 }
 
 impl Default for Codec {
@@ -15,8 +66,133 @@ impl Default for Codec {
     }
 }
 
+impl Codec {
+    /// The bitrate in kbit/s, unknown for copied and lossless audio
+    pub fn bitrate(&self) -> Option<u64> {
+        match *self {
+            Codec::Opus(ref opus) => Some(opus.bitrate()),
+            Codec::Aac(ref aac) => Some(aac.bitrate()),
+            Codec::Mp3(ref mp3) => Some(mp3.bitrate()),
+            Codec::Flac(_) | Codec::Pcm(_) | Codec::Copy => None,
+        }
+    }
+    /// The loudness normalization, which every encoder takes
+    pub fn loudnorm(&self) -> Option<&loudnorm::Loudnorm> {
+        match *self {
+            Codec::Opus(ref opus) => opus.loudnorm(),
+            Codec::Aac(ref aac) => aac.loudnorm(),
+            Codec::Flac(ref flac) => flac.loudnorm(),
+            Codec::Mp3(ref mp3) => mp3.loudnorm(),
+            Codec::Pcm(ref pcm) => pcm.loudnorm(),
+            Codec::Copy => None,
+        }
+    }
+    pub fn with_loudness(&self, measured: loudnorm::Measurement) -> Self {
+        match *self {
+            Codec::Opus(ref opus) => Codec::Opus(opus.with_loudness(measured)),
+            Codec::Aac(ref aac) => Codec::Aac(aac.with_loudness(measured)),
+            Codec::Flac(ref flac) => Codec::Flac(flac.with_loudness(measured)),
+            Codec::Mp3(ref mp3) => Codec::Mp3(mp3.with_loudness(measured)),
+            Codec::Pcm(ref pcm) => Codec::Pcm(pcm.with_loudness(measured)),
+            Codec::Copy => self.clone(),
+        }
+    }
+    /// Whether the encoded audio is louder or quieter than the source,
+    /// because it is normalized or mixed down to fewer channels
+    pub fn changes_loudness(&self) -> bool {
+        let mixed = |remix: Option<&channels::Remix>| remix.map_or(false, |r| !r.filters.is_empty());
+        let remix = match *self {
+            Codec::Opus(ref opus) => opus.remix(),
+            Codec::Aac(ref aac) => aac.remix(),
+            Codec::Mp3(ref mp3) => mp3.remix(),
+            Codec::Flac(_) | Codec::Pcm(_) | Codec::Copy => None,
+        };
+        self.loudnorm().is_some() || mixed(remix)
+    }
+    /// Resolves the remix of the channels of `audio`
+    pub fn for_source(&self, audio: Option<&Audio>) -> Self {
+        match *self {
+            Codec::Opus(ref opus) => Codec::Opus(opus.for_source(audio)),
+            Codec::Aac(ref aac) => Codec::Aac(aac.for_source(audio)),
+            Codec::Mp3(ref mp3) => Codec::Mp3(mp3.for_source(audio)),
+            Codec::Flac(ref flac) => Codec::Flac(flac.for_source(audio)),
+            Codec::Pcm(ref pcm) => Codec::Pcm(pcm.for_source(audio)),
+            Codec::Copy => Codec::Copy,
+        }
+    }
+    /// Changes the channels before `for_source` resolves the remix
+    pub fn with_channels(&self, channels: channels::Channels) -> Self {
+        match *self {
+            Codec::Opus(ref opus) => Codec::Opus(opus.with_channels(channels)),
+            Codec::Aac(ref aac) => Codec::Aac(aac.with_channels(channels)),
+            Codec::Mp3(ref mp3) => Codec::Mp3(mp3.with_channels(channels)),
+            Codec::Flac(_) | Codec::Pcm(_) | Codec::Copy => self.clone(),
+        }
+    }
+    /// Arguments that only apply to the `n`th audio stream of the output
+    pub fn stream_args(&self, n: usize) -> Vec<ffi::OsString> {
+        match *self {
+            Codec::Opus(ref opus) => opus.stream_args(n),
+            Codec::Aac(ref aac) => aac.stream_args(n),
+            Codec::Mp3(ref mp3) => mp3.stream_args(n),
+            Codec::Flac(ref flac) => flac.stream_args(n),
+            Codec::Pcm(ref pcm) => pcm.stream_args(n),
+            Codec::Copy => vec![format!("-c:a:{}", n).into(), "copy".into()],
+        }
+    }
+    /// Resolves the remix for the first audio stream of the output, see
+    /// `first_kept`
+    pub fn for_first_stream(&self, probe: &FFProbe, selection: Option<&Selection>) -> Self {
+        self.for_source(first_kept(probe, selection).as_ref())
+    }
+    /// Codecs for the audio streams of `selection` after the first, each
+    /// resolved for its own stream of `probe`
+    pub fn for_other_streams(&self, probe: &FFProbe, selection: Option<&Selection>) -> Vec<Codec> {
+        selection.iter()
+            .flat_map(|s| s.audio.iter().skip(1))
+            .map(|&index| self.for_source(probe.stream(index).and_then(Audio::from_stream).as_ref()))
+            .collect()
+    }
+    /// The bitrate in kbit/s of all the kept audio streams, the streams of
+    /// `selection` or else the converted one. Copied and lossless audio
+    /// keeps the bitrate of the source.
+    pub fn kept_bitrate(&self, probe: &FFProbe, selection: Option<&Selection>) -> u64 {
+        let indexes: Vec<u64> = match selection {
+            Some(s) => s.audio.clone(),
+            None => probe.audio.iter().map(|a| a.index).collect(),
+        };
+        indexes.into_iter()
+            .map(|index| {
+                self.bitrate()
+                    .or_else(|| probe.stream(index).and_then(|s| s.bit_rate).map(|b| b / 1000))
+                    .unwrap_or(0)
+            })
+            .sum()
+    }
+    /// Arguments for the kept audio streams: this codec for the first and
+    /// `others` for the ones after it. Without mapped streams ffmpeg keeps
+    /// one audio stream, which this codec applies to.
+    pub fn kept_args(&self, others: &[Codec], mapped: bool) -> Vec<ffi::OsString> {
+        match mapped {
+            true => once(self).chain(others).enumerate().flat_map(|(n, c)| c.stream_args(n)).collect(),
+            false => self.to_ffmpeg_args(),
+        }
+    }
+    /// The filters of the loudness measurement pass
+    pub fn measure_filter(&self) -> Option<String> {
+        match *self {
+            Codec::Opus(ref opus) => opus.measure_filter(),
+            Codec::Aac(ref aac) => aac.measure_filter(),
+            Codec::Flac(ref flac) => flac.measure_filter(),
+            Codec::Mp3(ref mp3) => mp3.measure_filter(),
+            Codec::Pcm(ref pcm) => pcm.measure_filter(),
+            Codec::Copy => None,
+        }
+    }
+}
+
 impl CodecTrait for Codec {
-    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: T) -> Result<(Self, T), Error> {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
         let name = match args.next() {
             Some(s) => s,
             None => return Err(Error::TooShort),
@@ -28,6 +204,23 @@ impl CodecTrait for Codec {
                 let (codec, args) = try!(opus::Codec::from_args(args));
                 (Codec::Opus(codec), args)
             },
+            "aac" => {
+                let (codec, args) = try!(aac::Codec::from_args(args));
+                (Codec::Aac(codec), args)
+            },
+            "mp3" => {
+                let (codec, args) = try!(mp3::Codec::from_args(args));
+                (Codec::Mp3(codec), args)
+            },
+            "flac" => {
+                let (codec, args) = try!(flac::Codec::from_args(args));
+                (Codec::Flac(codec), args)
+            },
+            "pcm" => {
+                let (codec, args) = try!(pcm::Codec::from_args(args));
+                (Codec::Pcm(codec), args)
+            },
+            "copy" => (Codec::Copy, args),
             _ => return Err(Error::InvalidArg(name.to_string(), "Unsupported audio codec"))
         };
 
@@ -35,12 +228,22 @@ impl CodecTrait for Codec {
     }
     fn to_ffmpeg_args(&self) -> Vec<ffi::OsString> {
         match *self {
-            Codec::Opus(ref opus) => opus.to_ffmpeg_args()
+            Codec::Opus(ref opus) => opus.to_ffmpeg_args(),
+            Codec::Aac(ref aac) => aac.to_ffmpeg_args(),
+            Codec::Mp3(ref mp3) => mp3.to_ffmpeg_args(),
+            Codec::Flac(ref flac) => flac.to_ffmpeg_args(),
+            Codec::Pcm(ref pcm) => pcm.to_ffmpeg_args(),
+            Codec::Copy => vec!["-c:a".into(), "copy".into()],
         }
     }
     fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
         match *self {
-            Codec::Opus(ref opus) => opus.to_ffprobe_id()
+            Codec::Opus(ref opus) => opus.to_ffprobe_id(),
+            Codec::Aac(ref aac) => aac.to_ffprobe_id(),
+            Codec::Mp3(ref mp3) => mp3.to_ffprobe_id(),
+            Codec::Flac(ref flac) => flac.to_ffprobe_id(),
+            Codec::Pcm(ref pcm) => pcm.to_ffprobe_id(),
+            Codec::Copy => (None, None),
         }
 
 
@@ -48,7 +251,11 @@ impl CodecTrait for Codec {
     fn to_examples() -> Vec<Vec<&'static str>> {
         once(
             ("opus", opus::Codec::to_examples())
-        ).into_iter().flat_map(|(codec_name, examples)| {
+        ).chain(once(("aac", aac::Codec::to_examples())))
+        .chain(once(("mp3", mp3::Codec::to_examples())))
+        .chain(once(("flac", flac::Codec::to_examples())))
+        .chain(once(("pcm", pcm::Codec::to_examples())))
+        .chain(once(("copy", vec![vec![]]))).into_iter().flat_map(|(codec_name, examples)| {
             examples.into_iter().map(move |example| {
                 once(codec_name).chain(example).collect::<Vec<_>>()
             })
@@ -56,3 +263,74 @@ impl CodecTrait for Codec {
     }
 }
 
+
+#[test]
+fn test_kept_args() {
+    use formats::Registry;
+    use ffprobe;
+
+    let output = r#"{
+        "format": {"format_name": "matroska,webm", "duration": "60"},
+        "streams": [
+            {"index": 0, "codec_type": "audio", "codec_name": "ac3", "channels": 2,
+             "channel_layout": "stereo"},
+            {"index": 1, "codec_type": "audio", "codec_name": "ac3", "channels": 6,
+             "channel_layout": "5.1(side)"}
+        ]
+    }"#;
+    let probe = ffprobe::parse(output.to_string(), &Registry::default()).unwrap().unwrap();
+    let selection = Selection { audio: vec![0, 1], ..Selection::default_for(&probe) };
+    let (opus, _) = Codec::from_args(vec!["opus", "128"].into_iter().peekable()).unwrap();
+    let others = opus.for_other_streams(&probe, Some(&selection));
+    let args: Vec<String> = opus.for_first_stream(&probe, Some(&selection))
+        .kept_args(&others, true)
+        .into_iter()
+        .map(|a| a.into_string().unwrap())
+        .collect();
+    assert_eq!(args.join(" "),
+               "-c:a:0 libopus -b:a:0 128k -filter:a:0 aresample=osr=48000 \
+                -c:a:1 libopus -b:a:1 128k -mapping_family:a:1 1 \
+                -filter:a:1 channelmap=channel_layout=5.1,aresample=osr=48000");
+}
+
+#[test]
+fn test_loudnorm() {
+    use ffprobe::Audio;
+
+    let source = Audio {
+        index: 0,
+        codec: "flac".to_string(),
+        channels: Some(2),
+        layout: Some("stereo".to_string()),
+        sample_rate: Some(44100),
+        bits: Some(24),
+    };
+    let args = |spec: Vec<&str>| -> String {
+        let (codec, _) = Codec::from_args(spec.into_iter().peekable()).unwrap();
+        let args: Vec<String> = codec.for_source(Some(&source))
+            .to_ffmpeg_args()
+            .into_iter()
+            .map(|a| a.into_string().unwrap())
+            .collect();
+        args.join(" ")
+    };
+    // loudnorm outputs 192 kHz, which lossless codecs would keep
+    assert_eq!(args(vec!["flac", "loudnorm=dynamic:-16"]),
+               "-c:a flac -filter:a:0 loudnorm=I=-16:TP=-1:LRA=7,aresample=osr=44100");
+    assert_eq!(args(vec!["pcm", "bits=16", "loudnorm=dynamic"]),
+               "-c:a pcm_s16le -filter:a:0 loudnorm=I=-23:TP=-1:LRA=7,\
+                aresample=osr=44100:osf=s16:dither_method=triangular");
+    assert_eq!(args(vec!["aac", "160", "channels=mono", "loudnorm=dynamic:-16"]),
+               "-c:a aac -b:a 160k -filter:a:0 pan=mono|c0<FL+FR,loudnorm=I=-16:TP=-1:LRA=7,\
+                aresample=osr=44100");
+    assert_eq!(args(vec!["mp3", "v2", "loudnorm=dynamic"]),
+               "-c:a libmp3lame -q:a 2 -filter:a:0 loudnorm=I=-23:TP=-1:LRA=7,aresample=osr=44100");
+    assert_eq!(args(vec!["opus", "96", "loudnorm=dynamic"]),
+               "-c:a libopus -b:a 96k -filter:a:0 loudnorm=I=-23:TP=-1:LRA=7,aresample=osr=48000");
+    let (flac, _) = Codec::from_args(vec!["flac", "loudnorm=two-pass"].into_iter().peekable()).unwrap();
+    assert!(flac.changes_loudness());
+    assert_eq!(flac.measure_filter(), Some("loudnorm=I=-23:TP=-1:LRA=7:print_format=json".to_string()));
+    assert!(Codec::from_args(vec!["copy", "loudnorm=dynamic"].into_iter().peekable())
+        .map(|(_, mut rest)| rest.next().is_some())
+        .unwrap());
+}
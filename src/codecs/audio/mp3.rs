@@ -0,0 +1,132 @@
+use std::ffi::OsString;
+use std::iter::Peekable;
+use super::super::{next_option, Codec as CodecTrait, Error};
+use super::channels::{Channels, Remix};
+use super::loudnorm::{Loudnorm, Measurement};
+use super::settings::Settings;
+use ffprobe::Audio;
+
+// MPEG-1 and 2 only define mono and stereo
+const MAX_CHANNELS: u64 = 2;
+const RATES: &'static [u64] = &[8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000];
+/// The average bitrates of the LAME VBR presets V0 to V9, in kbit/s
+const VBR_BITRATES: [u64; 10] = [245, 225, 190, 175, 165, 130, 115, 100, 85, 65];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Rate {
+    /// A constant bitrate in kbit/s
+    Cbr(u64),
+    /// A VBR preset of LAME, 0 is the best
+    Vbr(u64),
+}
+
+/// MP3 with LAME
+#[derive(Clone, Debug)]
+pub struct Codec {
+    rate: Rate,
+    channels: Channels,
+    settings: Settings,
+    /// Computed for each source by `for_source`
+    remix: Option<Remix>,
+    source_rate: Option<u64>,
+}
+
+impl Codec {
+    /// The bitrate, on average for VBR
+    pub fn bitrate(&self) -> u64 {
+        match self.rate {
+            Rate::Cbr(bitrate) => bitrate,
+            Rate::Vbr(quality) => VBR_BITRATES[quality as usize],
+        }
+    }
+    pub fn loudnorm(&self) -> Option<&Loudnorm> {
+        self.settings.loudnorm.as_ref()
+    }
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        Codec { settings: self.settings.with_loudness(measured), ..self.clone() }
+    }
+    pub fn for_source(&self, audio: Option<&Audio>) -> Self {
+        Codec {
+            remix: audio.map(|a| Remix::new(self.channels, a, MAX_CHANNELS)),
+            source_rate: audio.and_then(|a| a.sample_rate),
+            ..self.clone()
+        }
+    }
+    pub fn with_channels(&self, channels: Channels) -> Self {
+        Codec { channels: channels, ..self.clone() }
+    }
+    /// The remix that `for_source` resolved
+    pub fn remix(&self) -> Option<&Remix> {
+        self.remix.as_ref()
+    }
+    /// The remix, followed by the loudnorm filter of the measurement pass
+    pub fn measure_filter(&self) -> Option<String> {
+        self.settings.measure_filter(self.remix.iter().flat_map(|r| r.filters.clone()).collect())
+    }
+    /// Arguments for the `n`th audio stream of the output
+    pub fn stream_args(&self, n: usize) -> Vec<OsString> {
+        self.args(Some(n))
+    }
+    fn args(&self, stream: Option<usize>) -> Vec<OsString> {
+        let rate = match self.rate {
+            Rate::Cbr(bitrate) => ("b", format!("{}k", bitrate)),
+            Rate::Vbr(quality) => ("q", format!("{}", quality)),
+        };
+        let filters: Vec<String> = self.remix
+            .iter()
+            .flat_map(|r| r.filters.clone())
+            .chain(self.settings.filters(None, None, self.source_rate))
+            .collect();
+        super::encoder_args(stream, &[("c", "libmp3lame".to_string()), rate], &[], &filters)
+    }
+}
+
+impl CodecTrait for Codec {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
+        let rate = match args.next() {
+            Some(s) => s,
+            None => return Err(Error::TooShort)
+        };
+
+        // Either a bitrate or a VBR preset, e.g. 320 or v0
+        let rate = match (rate.starts_with('v'), rate.trim_start_matches('v').parse::<i64>()) {
+            (false, Ok(b)) if b >= 32 && b <= 320 => Rate::Cbr(b as u64),
+            (true, Ok(q)) if q >= 0 && q <= 9 && rate.len() == 2 => Rate::Vbr(q as u64),
+            _ => return Err(Error::InvalidArg(rate.to_string(), "Bitrate must be between 32 and 320, or a VBR preset from v0 to v9")),
+        };
+
+        let mut codec = Codec {
+            rate: rate,
+            channels: Channels::default(),
+            settings: Settings::default(),
+            remix: None,
+            source_rate: None,
+        };
+        // Optional key=value settings follow the bitrate
+        while let Some((key, value)) = next_option(&mut args, &["channels", "loudnorm", "rate", "resampler"]) {
+            match key {
+                "channels" => codec.channels = match Channels::parse(value) {
+                    Some(Channels::Max(n)) if n <= MAX_CHANNELS => Channels::Max(n),
+                    _ => return Err(Error::InvalidArg(value.to_string(), "Channels must be mono or stereo")),
+                },
+                _ => try!(codec.settings.set(key, value)),
+            }
+        }
+        match codec.settings.sampling.rate {
+            Some(rate) if !RATES.contains(&rate) => {
+                return Err(Error::InvalidArg(rate.to_string(), "MP3 takes the rates of MPEG-1 and 2 from 8000 to 48000 Hz"))
+            }
+            _ => (),
+        }
+        Ok((codec, args))
+    }
+    fn to_ffmpeg_args<'a>(&self) -> Vec<OsString> {
+        self.args(None)
+    }
+    fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
+        (None, Some("mp3"))
+    }
+    fn to_examples() -> Vec<Vec<&'static str>> {
+        vec![vec!["v0"], vec!["320", "channels=stereo", "rate=44.1k"]]
+    }
+}
@@ -1,19 +1,104 @@
 use std::ffi::{OsString};
-use super::super::{Codec as CodecTrait, Error};
+use std::iter::Peekable;
+use super::super::{next_option, Codec as CodecTrait, Error};
+use super::channels::{self, Channels, Remix};
+use super::loudnorm::{Loudnorm, Measurement};
+use super::sampling::Sampling;
+use super::settings::Settings;
+use ffprobe::Audio;
+
+// Mapping family 1 covers the Vorbis layouts up to 7.1
+const MAX_CHANNELS: u64 = 8;
+// libopus takes lower rates too, but decoders always output 48 kHz
+const RATE: u64 = 48000;
 
 #[derive(Clone, Debug)]
 pub struct Codec {
-    bitrate: u64
+    bitrate: u64,
+    channels: Channels,
+    settings: Settings,
+    /// Computed for each source by `for_source`
+    remix: Option<Remix>,
 }
 
 impl Default for Codec {
     fn default() -> Self {
-        Codec { bitrate: 192 }
+        Codec {
+            bitrate: 192,
+            channels: Channels::default(),
+            settings: Settings {
+                sampling: Sampling { rate: Some(RATE), ..Sampling::default() },
+                ..Settings::default()
+            },
+            remix: None,
+        }
+    }
+}
+
+impl Codec {
+    pub fn bitrate(&self) -> u64 {
+        self.bitrate
+    }
+    pub fn loudnorm(&self) -> Option<&Loudnorm> {
+        self.settings.loudnorm.as_ref()
+    }
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        Codec { settings: self.settings.with_loudness(measured), ..self.clone() }
+    }
+    pub fn for_source(&self, audio: Option<&Audio>) -> Self {
+        Codec {
+            remix: audio.map(|a| Remix::new(self.channels, a, MAX_CHANNELS)),
+            ..self.clone()
+        }
+    }
+    pub fn with_channels(&self, channels: Channels) -> Self {
+        Codec { channels: channels, ..self.clone() }
+    }
+    /// The remix that `for_source` resolved
+    pub fn remix(&self) -> Option<&Remix> {
+        self.remix.as_ref()
+    }
+    /// The remix, followed by the loudnorm filter of the measurement pass
+    pub fn measure_filter(&self) -> Option<String> {
+        self.settings.measure_filter(self.remix_filters())
+    }
+    /// libopus only takes more than two channels with mapping family 1, and
+    /// then only in the standard layouts. Other layouts with the same count,
+    /// like `5.1(side)`, are relabeled.
+    fn remix_filters(&self) -> Vec<String> {
+        let remix = match self.remix {
+            Some(ref r) => r,
+            None => return Vec::new(),
+        };
+        let mut filters = remix.filters.clone();
+        if let Some(standard) = remix.channels.and_then(channels::standard_layout) {
+            if remix.channels > Some(2) && remix.layout.as_ref().map(|l| l.as_str()) != Some(standard) {
+                filters.push(format!("channelmap=channel_layout={}", standard));
+            }
+        }
+        filters
+    }
+    /// Arguments for the `n`th audio stream of the output
+    pub fn stream_args(&self, n: usize) -> Vec<OsString> {
+        self.args(Some(n))
+    }
+    fn args(&self, stream: Option<usize>) -> Vec<OsString> {
+        // The native encoder only does stereo
+        let options = [("c", "libopus".to_string()), ("b", format!("{}k", self.bitrate))];
+        let family: Vec<(&str, String)> = match self.remix.as_ref().and_then(|r| r.channels) {
+            Some(c) if c > 2 => vec![("mapping_family", "1".to_string())],
+            _ => vec![],
+        };
+        let filters: Vec<String> = self.remix_filters()
+            .into_iter()
+            .chain(self.settings.filters(None, None, None))
+            .collect();
+        super::encoder_args(stream, &options, &family, &filters)
     }
 }
 
 impl CodecTrait for Codec {
-    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: T) -> Result<(Self, T), Error> {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
         let bitrate = match args.next() {
             Some(s) => s,
             None => return Err(Error::TooShort)
@@ -24,22 +109,29 @@ impl CodecTrait for Codec {
             Ok(_) => return Err(Error::InvalidArg(bitrate.to_string(), "Bitrate must be between 6 and 255")),
             Err(_) => return Err(Error::InvalidArg(bitrate.to_string(), "Bitrate must be a number"))
         };
-        Ok((Codec { bitrate: bitrate }, args))
+
+        let mut codec = Codec { bitrate: bitrate, ..Codec::default() };
+        // Optional key=value settings follow the bitrate
+        while let Some((key, value)) = next_option(&mut args, &["loudnorm", "channels", "rate", "resampler"]) {
+            match key {
+                "channels" => codec.channels = match Channels::parse(value) {
+                    Some(c) => c,
+                    None => return Err(Error::InvalidArg(value.to_string(), "Channels must be keep, mono, stereo or a count from 1 to 8")),
+                },
+                "rate" => return Err(Error::InvalidArg(value.to_string(), "Opus is always encoded at 48000 Hz")),
+                _ => try!(codec.settings.set(key, value)),
+            }
+        }
+        Ok((codec, args))
     }
     fn to_ffmpeg_args<'a>(&self) -> Vec<OsString> {
-        vec![
-            "-c:a".into(),
-            "opus".into(),
-            "-b:a".into(),
-            format!("{}k", self.bitrate).into()
-        ]
+        self.args(None)
     }
     fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
         (None, Some("opus"))
     }
     fn to_examples() -> Vec<Vec<&'static str>> {
-        vec![vec!["192"]]
-
+        vec![vec!["192"], vec!["128", "loudnorm=two-pass:-23:-1:7"], vec!["96", "channels=stereo", "resampler=soxr"]]
     }
 }
 
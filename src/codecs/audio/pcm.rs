@@ -0,0 +1,86 @@
+use std::ffi::OsString;
+use std::iter::Peekable;
+use super::super::{next_option, Codec as CodecTrait, Error};
+use super::loudnorm::{Loudnorm, Measurement};
+use super::settings::Settings;
+use ffprobe::Audio;
+
+/// Uncompressed little-endian samples, as in WAV files
+#[derive(Clone, Debug, Default)]
+pub struct Codec {
+    /// 16, 24 or 32, the bits of the source if not set
+    bits: Option<u64>,
+    settings: Settings,
+    /// Computed for each source by `for_source`
+    source_bits: Option<u64>,
+    source_rate: Option<u64>,
+}
+
+impl Codec {
+    pub fn for_source(&self, audio: Option<&Audio>) -> Self {
+        Codec {
+            source_bits: audio.and_then(|a| a.bits),
+            source_rate: audio.and_then(|a| a.sample_rate),
+            ..self.clone()
+        }
+    }
+    pub fn loudnorm(&self) -> Option<&Loudnorm> {
+        self.settings.loudnorm.as_ref()
+    }
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        Codec { settings: self.settings.with_loudness(measured), ..self.clone() }
+    }
+    /// The loudnorm filter of the measurement pass
+    pub fn measure_filter(&self) -> Option<String> {
+        self.settings.measure_filter(vec![])
+    }
+    /// The bits of the output. Sources without a bit depth, like lossy
+    /// ones, get 16 bits.
+    fn bits(&self) -> u64 {
+        match self.bits.or(self.source_bits).unwrap_or(16) {
+            b if b <= 16 => 16,
+            b if b <= 24 => 24,
+            _ => 32,
+        }
+    }
+    /// Arguments for the `n`th audio stream of the output
+    pub fn stream_args(&self, n: usize) -> Vec<OsString> {
+        self.args(Some(n))
+    }
+    fn args(&self, stream: Option<usize>) -> Vec<OsString> {
+        let bits = self.bits();
+        let format = match bits {
+            16 => ("s16", 16),
+            _ => ("s32", bits),
+        };
+        let filters: Vec<String> = self.settings.filters(Some(format), self.source_bits, self.source_rate);
+        super::encoder_args(stream, &[("c", format!("pcm_s{}le", bits))], &[], &filters)
+    }
+}
+
+impl CodecTrait for Codec {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
+        let mut codec = Codec::default();
+        while let Some((key, value)) = next_option(&mut args, &["bits", "loudnorm", "rate", "resampler"]) {
+            match key {
+                "bits" => codec.bits = match value {
+                    "16" => Some(16),
+                    "24" => Some(24),
+                    "32" => Some(32),
+                    _ => return Err(Error::InvalidArg(value.to_string(), "Bits must be 16, 24 or 32")),
+                },
+                _ => try!(codec.settings.set(key, value)),
+            }
+        }
+        Ok((codec, args))
+    }
+    fn to_ffmpeg_args<'a>(&self) -> Vec<OsString> {
+        self.args(None)
+    }
+    fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
+        (None, None)
+    }
+    fn to_examples() -> Vec<Vec<&'static str>> {
+        vec![vec!["bits=16", "rate=44.1k"]]
+    }
+}
@@ -0,0 +1,117 @@
+use super::super::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resampler {
+    /// The resampler built into ffmpeg
+    Swr,
+    /// The SoX resampler, with a precision in bits
+    Soxr(Option<u64>),
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Resampler::Swr
+    }
+}
+
+impl Resampler {
+    /// Parses `swr`, `soxr` or `soxr:PRECISION`, e.g. `soxr:28`
+    pub fn parse(s: &str) -> Option<Resampler> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("swr"), None) => Some(Resampler::Swr),
+            (Some("soxr"), None) => Some(Resampler::Soxr(None)),
+            (Some("soxr"), Some(precision)) => {
+                match precision.parse::<u64>() {
+                    Ok(p) if p >= 15 && p <= 33 => Some(Resampler::Soxr(Some(p))),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses a sample rate in Hz, or in kHz with a `k`, e.g. `44.1k`
+pub fn parse_rate(s: &str) -> Option<u64> {
+    let rate = match s.ends_with('k') {
+        true => s[..s.len() - 1].parse::<f64>().ok().map(|k| (k * 1000.).round()),
+        false => s.parse::<u64>().ok().map(|r| r as f64),
+    };
+    match rate {
+        Some(r) if r >= 8000. && r <= 384000. => Some(r as u64),
+        _ => None,
+    }
+}
+
+/// The sample rate, sample format and resampler of an audio codec. Resampling
+/// comes last in the filters, after `loudnorm` which works at 192 kHz.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sampling {
+    pub rate: Option<u64>,
+    pub resampler: Resampler,
+}
+
+impl Sampling {
+    /// Applies a `rate=` or `resampler=` setting
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        match key {
+            "rate" => {
+                self.rate = match parse_rate(value) {
+                    Some(r) => Some(r),
+                    None => return Err(Error::InvalidArg(value.to_string(), "Sample rate must be between 8000 and 384000 Hz, e.g. 44100 or 44.1k")),
+                }
+            }
+            _ => {
+                self.resampler = match Resampler::parse(value) {
+                    Some(r) => r,
+                    None => return Err(Error::InvalidArg(value.to_string(), "Resampler must be swr, soxr or soxr:PRECISION with a precision from 15 to 33")),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// An `aresample` filter converting to the rate and to `format`, the
+    /// sample format of the encoder. Dithers when `format` has fewer bits
+    /// than the source. `None` if ffmpeg would do the same by itself.
+    pub fn filter(&self, format: Option<(&str, u64)>, source_bits: Option<u64>) -> Option<String> {
+        let mut options = Vec::new();
+        if let Some(rate) = self.rate {
+            options.push(format!("osr={}", rate));
+        }
+        if let Some((format, bits)) = format {
+            options.push(format!("osf={}", format));
+            if source_bits.map_or(false, |b| b > bits) {
+                options.push("dither_method=triangular".to_string());
+            }
+        }
+        match self.resampler {
+            Resampler::Swr => (),
+            Resampler::Soxr(precision) => {
+                options.push("resampler=soxr".to_string());
+                options.extend(precision.map(|p| format!("precision={}", p)));
+            }
+        }
+        match options.is_empty() {
+            true => None,
+            false => Some(format!("aresample={}", options.join(":"))),
+        }
+    }
+}
+
+#[test]
+fn test_sampling() {
+    assert_eq!(parse_rate("44.1k"), Some(44100));
+    assert_eq!(parse_rate("48000"), Some(48000));
+    assert_eq!(parse_rate("22"), None);
+
+    let mut sampling = Sampling::default();
+    assert_eq!(sampling.filter(None, Some(24)), None);
+    sampling.set("rate", "44.1k").unwrap();
+    sampling.set("resampler", "soxr:28").unwrap();
+    assert_eq!(sampling.filter(Some(("s16", 16)), Some(24)),
+               Some("aresample=osr=44100:osf=s16:dither_method=triangular:resampler=soxr:precision=28"
+                   .to_string()));
+    assert!(sampling.set("resampler", "soxr:50").is_err());
+}
@@ -0,0 +1,58 @@
+use super::super::Error;
+use super::loudnorm::{Loudnorm, Measurement};
+use super::sampling::Sampling;
+
+// What most sources are recorded at, for sources without a known rate
+const FALLBACK_RATE: u64 = 48000;
+
+/// The settings every encoder takes besides its own: loudness normalization
+/// and the sampling. Their filters follow any remix of the channels.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    pub loudnorm: Option<Loudnorm>,
+    pub sampling: Sampling,
+}
+
+impl Settings {
+    /// Applies a `loudnorm=`, `rate=` or `resampler=` setting
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        match key {
+            "loudnorm" => {
+                self.loudnorm = match Loudnorm::parse(value) {
+                    Some(l) => Some(l),
+                    None => return Err(Error::InvalidArg(value.to_string(), "Loudnorm must be two-pass or dynamic, optionally followed by :I:TP:LRA")),
+                };
+                Ok(())
+            }
+            _ => self.sampling.set(key, value),
+        }
+    }
+
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        Settings {
+            loudnorm: self.loudnorm.clone().map(|l| Loudnorm { measured: Some(measured), ..l }),
+            ..self.clone()
+        }
+    }
+
+    /// `remix`, followed by the loudnorm filter of the measurement pass
+    pub fn measure_filter(&self, remix: Vec<String>) -> Option<String> {
+        self.loudnorm.as_ref().map(|l| remix.into_iter().chain(Some(l.measure_filter())).collect::<Vec<_>>().join(","))
+    }
+
+    /// The loudnorm filter, then the `aresample` filter of the sampling.
+    /// `loudnorm` outputs 192 kHz, so without a rate of its own the audio
+    /// goes back to `source_rate`.
+    pub fn filters(&self,
+                   format: Option<(&str, u64)>,
+                   source_bits: Option<u64>,
+                   source_rate: Option<u64>)
+                   -> Vec<String> {
+        let loudnorm = self.loudnorm.as_ref().and_then(|l| l.filter());
+        let sampling = match (&loudnorm, self.sampling.rate) {
+            (&Some(_), None) => Sampling { rate: Some(source_rate.unwrap_or(FALLBACK_RATE)), ..self.sampling.clone() },
+            _ => self.sampling.clone(),
+        };
+        loudnorm.into_iter().chain(sampling.filter(format, source_bits)).collect()
+    }
+}
@@ -0,0 +1,106 @@
+use super::super::{next_option, Codec as CodecTrait, Error};
+use super::super::audio;
+use super::super::audio::loudnorm::{Loudnorm, Measurement};
+
+use cover;
+use ffprobe::FFProbe;
+use formats::Streams;
+use metadata::Chapters;
+use std::ffi::OsString;
+use streams::Selection;
+use std::iter::Peekable;
+use std::path::PathBuf;
+
+/// Matroska with only the audio of the source
+#[derive(Clone, Debug)]
+pub struct Codec {
+    audio: audio::Codec,
+    /// The kept audio streams after the first, each with the remix of its
+    /// own channels. Two-pass loudness normalization only measures the
+    /// first, so these are normalized dynamically.
+    other_audio: Vec<audio::Codec>,
+    chapters: Chapters,
+    /// Attached even when the source has a picture, since only its audio
+    /// is kept
+    cover: Option<PathBuf>,
+    streams: Option<Selection>,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec {
+            audio: audio::Codec::default(),
+            other_audio: Vec::new(),
+            chapters: Chapters::default(),
+            cover: None,
+            streams: None,
+        }
+    }
+}
+
+impl Codec {
+    pub fn for_source(&self, probe: &FFProbe) -> Self {
+        Codec {
+            audio: self.audio.for_first_stream(probe, self.streams.as_ref()),
+            other_audio: self.audio.for_other_streams(probe, self.streams.as_ref()),
+            ..self.clone()
+        }
+    }
+    pub fn loudnorm(&self) -> Option<&Loudnorm> {
+        self.audio.loudnorm()
+    }
+    pub fn measure_filter(&self) -> Option<String> {
+        self.audio.measure_filter()
+    }
+    /// Whether the first audio stream is louder or quieter than the source
+    pub fn changes_loudness(&self) -> bool {
+        self.audio.changes_loudness()
+    }
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        Codec { audio: self.audio.with_loudness(measured), ..self.clone() }
+    }
+    /// The source index of the first mapped audio stream
+    pub fn first_audio(&self) -> Option<u64> {
+        self.streams.as_ref().and_then(|s| s.audio.first().cloned())
+    }
+    pub fn with_streams(&self, selection: Selection) -> Self {
+        Codec { streams: Some(selection), ..self.clone() }
+    }
+    pub fn planned_streams(&self) -> Option<Streams> {
+        self.streams.as_ref().map(|s| s.planned(false, false))
+    }
+    pub fn with_cover(&self, path: PathBuf) -> Self {
+        Codec { cover: Some(path), ..self.clone() }
+    }
+}
+
+impl CodecTrait for Codec {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
+        let (audio, mut args) = try!(audio::Codec::from_args(args));
+
+        let chapters = match next_option(&mut args, &["chapters"]) {
+            Some((_, value)) => match Chapters::from_str(value) {
+                Some(c) => c,
+                None => return Err(Error::InvalidArg(value.to_string(), "Chapters must be keep or drop")),
+            },
+            None => Chapters::default(),
+        };
+
+        return Ok((Codec { audio: audio, other_audio: Vec::new(), chapters: chapters, cover: None, streams: None }, args))
+    }
+    fn to_ffmpeg_args(&self) -> Vec<OsString> {
+        ["-f", "matroska", "-vn"].iter().map(|&s| OsString::from(s))
+            .chain(self.streams.iter().flat_map(|s| s.to_ffmpeg_args(false, false)))
+            .chain(self.chapters.to_ffmpeg_args())
+            .chain(self.cover.iter().flat_map(|c| cover::attach_args(c, 0)))
+            .chain(self.audio.kept_args(&self.other_audio, self.streams.is_some()))
+            .collect()
+    }
+
+    fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
+        (None, self.audio.to_ffprobe_id().1)
+    }
+    fn to_examples() -> Vec<Vec<&'static str>> {
+        audio::Codec::to_examples()
+    }
+}
@@ -1,13 +1,34 @@
-use super::super::{Codec as CodecTrait, Error};
+use super::super::{next_option, Codec as CodecTrait, Error};
 use super::super::audio;
+use super::super::audio::channels::Channels;
+use super::super::audio::loudnorm::{Loudnorm, Measurement};
 use super::super::video;
 
+use cover;
+use ffprobe::FFProbe;
+use formats::Streams;
+use metadata::Chapters;
+use quality::Metric;
+use streams::Selection;
 use std::ffi::OsString;
+use std::iter::{once, Peekable};
+use std::path::PathBuf;
 
 #[derive(Clone, Debug)]
 pub struct Codec {
     video: video::Codec,
     audio: audio::Codec,
+    /// The kept audio streams after the first, each with the remix of its
+    /// own channels. Two-pass loudness normalization only measures the
+    /// first, so these are normalized dynamically.
+    other_audio: Vec<audio::Codec>,
+    /// A stereo track added next to the audio, for players that cannot
+    /// play it
+    compat: Option<audio::Codec>,
+    chapters: Chapters,
+    /// An image attached next to the attachments of the source
+    cover: Option<PathBuf>,
+    streams: Option<Selection>,
 }
 
 impl Default for Codec {
@@ -15,22 +36,141 @@ impl Default for Codec {
         Codec {
             video: video::Codec::default(),
             audio: audio::Codec::default(),
+            other_audio: Vec::new(),
+            compat: None,
+            chapters: Chapters::default(),
+            cover: None,
+            streams: None,
         }
     }
 }
 
+impl Codec {
+    pub fn crf_target(&self) -> Option<(Metric, f64)> {
+        self.video.crf_target()
+    }
+    pub fn with_crf(&self, crf: u64) -> Self {
+        Codec { video: self.video.with_crf(crf), ..self.clone() }
+    }
+    pub fn for_source(&self, probe: &FFProbe) -> Self {
+        // Attachments, like the fonts of styled subtitles, and the
+        // compatibility track need the streams to be mapped
+        let streams = match (self.streams.clone(), probe.streams.iter().any(|s| s.codec_type() == "attachment")) {
+            (None, true) => Some(Selection::default_for(probe)),
+            (streams, _) => streams,
+        };
+        // The compatibility track is a stereo copy of the first audio stream
+        let first = audio::first_kept(probe, streams.as_ref());
+        let (compat, streams) = match (self.compat.as_ref(), first.as_ref()) {
+            (Some(compat), Some(audio)) => {
+                let streams = streams.unwrap_or_else(|| Selection::default_for(probe));
+                (Some(compat.for_source(Some(audio))),
+                 Some(Selection { compat: Some(audio.index), ..streams }))
+            }
+            _ => (self.compat.clone(), streams),
+        };
+        let compat_bitrate = match streams.as_ref().and_then(|s| s.compat) {
+            Some(_) => compat.as_ref().and_then(|c| c.bitrate()).unwrap_or(0),
+            None => 0,
+        };
+        let audio_bitrate = self.audio.kept_bitrate(probe, streams.as_ref()) + compat_bitrate;
+        Codec {
+            video: self.video.for_source(probe.video.as_ref(), probe.duration, audio_bitrate),
+            audio: self.audio.for_source(first.as_ref()),
+            other_audio: self.audio.for_other_streams(probe, streams.as_ref()),
+            compat: compat,
+            chapters: self.chapters,
+            cover: self.cover.clone().filter(|_| !cover::is_embedded(probe)),
+            streams: streams,
+        }
+    }
+    pub fn passes(&self) -> u64 {
+        self.video.passes()
+    }
+    pub fn loudnorm(&self) -> Option<&Loudnorm> {
+        self.audio.loudnorm()
+    }
+    pub fn measure_filter(&self) -> Option<String> {
+        self.audio.measure_filter()
+    }
+    /// Whether the first audio stream is louder or quieter than the source
+    pub fn changes_loudness(&self) -> bool {
+        self.audio.changes_loudness()
+    }
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        Codec { audio: self.audio.with_loudness(measured), ..self.clone() }
+    }
+    /// The source index of the first mapped audio stream
+    pub fn first_audio(&self) -> Option<u64> {
+        self.streams.as_ref().and_then(|s| s.audio.first().cloned())
+    }
+    pub fn with_streams(&self, selection: Selection) -> Self {
+        Codec { streams: Some(selection), ..self.clone() }
+    }
+    pub fn with_cover(&self, path: PathBuf) -> Self {
+        Codec { cover: Some(path), ..self.clone() }
+    }
+    pub fn planned_streams(&self) -> Option<Streams> {
+        self.streams.as_ref().map(|s| s.planned(true, true))
+    }
+    pub fn video_bitrate(&self) -> Option<u64> {
+        self.video.bitrate()
+    }
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.video.frame_rate()
+    }
+}
+
 impl CodecTrait for Codec {
-    fn from_args<'a, T: Iterator<Item = &'a str>>(args: T) -> Result<(Self, T), Error> {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
         let (video, args) = try!(video::Codec::from_args(args));
-        let (audio, args) = try!(audio::Codec::from_args(args));
+        let (audio, mut args) = try!(audio::Codec::from_args(args));
 
-        return Ok((Codec { video: video, audio: audio }, args))
+        let (compat, mut args) = match args.peek() {
+            Some(&"compat") => {
+                args.next();
+                let (compat, args) = try!(audio::Codec::from_args(args));
+                if let audio::Codec::Copy = compat {
+                    return Err(Error::InvalidArg("copy".to_string(), "The compatibility track must be transcoded"));
+                }
+                (Some(compat.with_channels(Channels::Max(2))), args)
+            }
+            _ => (None, args),
+        };
+
+        let chapters = match next_option(&mut args, &["chapters"]) {
+            Some((_, value)) => match Chapters::from_str(value) {
+                Some(c) => c,
+                None => return Err(Error::InvalidArg(value.to_string(), "Chapters must be keep or drop")),
+            },
+            None => Chapters::default(),
+        };
+
+        let codec = Codec {
+            video: video,
+            audio: audio,
+            other_audio: Vec::new(),
+            compat: compat,
+            chapters: chapters,
+            cover: None,
+            streams: None,
+        };
+        return Ok((codec, args))
 
     }
     fn to_ffmpeg_args(&self) -> Vec<OsString> {
         ["-f", "matroska"].into_iter().map(|&s| OsString::from(s))
+            .chain(self.streams.iter().flat_map(|s| s.to_ffmpeg_args(true, true)))
+            .chain(self.chapters.to_ffmpeg_args())
+            .chain(self.streams.iter().flat_map(|s| s.attachment_args()))
+            .chain(self.cover.iter().flat_map(|c| {
+                cover::attach_args(c, self.streams.as_ref().map_or(0, |s| s.attachments.len()))
+            }))
             .chain(self.video.to_ffmpeg_args())
-            .chain(self.audio.to_ffmpeg_args())
+            .chain(self.audio.kept_args(&self.other_audio, self.streams.is_some()))
+            .chain(self.compat.iter()
+                .zip(self.streams.as_ref().and_then(|s| s.compat_position()))
+                .flat_map(|(c, n)| c.stream_args(n)))
             .collect()
     }
 
@@ -47,6 +187,73 @@ impl CodecTrait for Codec {
             .chain(audio_example)
             .collect();
 
-        return vec![res];
+        let compat = video::Codec::to_examples().into_iter().next().map(|v| {
+            v.into_iter().chain(vec!["copy", "compat", "aac", "160", "chapters=drop"]).collect()
+        });
+
+        return once(res).chain(compat).collect();
     }
 }
+
+#[test]
+fn test_stream_args() {
+    use ffprobe;
+    use formats::Registry;
+
+    let probe = |streams: &str| {
+        let output = format!("{{\"format\": {{\"format_name\": \"matroska,webm\", \"duration\": \"60\"}}, \
+                              \"streams\": [{}]}}",
+                             streams);
+        ffprobe::parse(output, &Registry::default()).unwrap().unwrap()
+    };
+    let args = |codec: &Codec, probe: &FFProbe| {
+        let args: Vec<String> = codec.for_source(probe)
+            .to_ffmpeg_args()
+            .into_iter()
+            .map(|a| a.into_string().unwrap())
+            .collect();
+        args.join(" ")
+    };
+    let video = "{\"index\": 0, \"codec_type\": \"video\", \"codec_name\": \"h264\", \"width\": 1920, \
+                 \"height\": 1080, \"r_frame_rate\": \"24/1\"}";
+    let surround = "{\"index\": 1, \"codec_type\": \"audio\", \"codec_name\": \"ac3\", \"channels\": 6, \
+                    \"channel_layout\": \"5.1(side)\", \"sample_rate\": \"48000\"}";
+    let subtitle = "{\"index\": 2, \"codec_type\": \"subtitle\", \"codec_name\": \"ass\"}";
+    let font = "{\"index\": 3, \"codec_type\": \"attachment\", \"codec_name\": \"ttf\", \
+                \"tags\": {\"filename\": \"font.ttf\"}}";
+
+    // The compatibility track is a downmix of the first audio stream, and
+    // the default one
+    let compat = vec!["h264", "18", "medium", "copy", "compat", "aac", "160"];
+    let (codec, _) = Codec::from_args(compat.into_iter().peekable()).unwrap();
+    let probed = probe(&[video, surround, subtitle].join(","));
+    let a = args(&codec, &probed);
+    assert!(a.contains("-map 0:0 -map 0:1 -map 0:1 -map 0:2 -disposition:a:0 0 -disposition:a:1 default "),
+            "{}",
+            a);
+    assert!(a.contains("-c:a:0 copy -c:a:1 aac -b:a:1 160k -filter:a:1 pan=stereo|FL<FL+0.707*FC"),
+            "{}",
+            a);
+    assert_eq!(codec.for_source(&probed).planned_streams(),
+               Some(Streams { video: 1, audio: 2, subtitle: 1, image: 0 }));
+
+    // Without audio there is nothing to downmix
+    let a = args(&codec, &probe(video));
+    assert!(!a.contains("-c:a:1"), "{}", a);
+
+    // Attachments are mapped, and a cover is attached after them
+    let (codec, _) = Codec::from_args(vec!["h264", "18", "medium", "copy"].into_iter().peekable()).unwrap();
+    let codec = codec.with_cover(PathBuf::from("folder.png"));
+    let a = args(&codec, &probe(&[video, surround, subtitle, font].join(",")));
+    assert!(a.contains("-map 0:0 -map 0:1 -map 0:2 "), "{}", a);
+    assert!(a.contains("-map 0:3 -c:t copy -attach folder.png -metadata:s:t:1 mimetype=image/png \
+                        -metadata:s:t:1 filename=cover.png "),
+            "{}",
+            a);
+
+    // A cover the source already has wins
+    let cover = "{\"index\": 3, \"codec_type\": \"attachment\", \"codec_name\": \"mjpeg\", \
+                 \"tags\": {\"filename\": \"cover.jpg\"}}";
+    let a = args(&codec, &probe(&[video, surround, cover].join(",")));
+    assert!(a.contains("-map 0:3 -c:t copy ") && !a.contains("-attach"), "{}", a);
+}
@@ -1,12 +1,28 @@
+pub mod mka;
 pub mod mkv;
+pub mod mp4;
+pub mod music;
+pub mod wav;
 
 use super::{Codec as CodecTrait, Error};
+use super::audio::loudnorm::{Loudnorm, Measurement};
+use ffprobe::FFProbe;
+use formats::Streams;
+use quality::Metric;
+use tagmap::Scheme;
+use streams::Selection;
 use std::ffi;
-use std::iter::once;
+use std::iter::{once, Peekable};
+use std::path::PathBuf;
 
 #[derive(Clone, Debug)]
 pub enum Codec {
-    Matroska(mkv::Codec)
+    Matroska(mkv::Codec),
+    MatroskaAudio(mka::Codec),
+    Mp4(mp4::Codec),
+    /// MP3, Ogg or M4A with one audio stream
+    Music(music::Codec),
+    Wav(wav::Codec),
 }
 impl Default for Codec {
     fn default() -> Self {
@@ -14,8 +30,181 @@ impl Default for Codec {
     }
 }
 
+impl Codec {
+    pub fn crf_target(&self) -> Option<(Metric, f64)> {
+        match *self {
+            Codec::Matroska(ref c) => c.crf_target(),
+            Codec::Mp4(ref c) => c.crf_target(),
+            Codec::MatroskaAudio(_) | Codec::Music(_) | Codec::Wav(_) => None,
+        }
+    }
+    pub fn with_crf(&self, crf: u64) -> Self {
+        match *self {
+            Codec::Matroska(ref c) => Codec::Matroska(c.with_crf(crf)),
+            Codec::Mp4(ref c) => Codec::Mp4(c.with_crf(crf)),
+            Codec::MatroskaAudio(_) | Codec::Music(_) | Codec::Wav(_) => self.clone(),
+        }
+    }
+    /// Resolves settings that depend on the source, like target sizes and
+    /// channel remixes
+    pub fn for_source(&self, probe: &FFProbe) -> Self {
+        match *self {
+            Codec::Matroska(ref c) => Codec::Matroska(c.for_source(probe)),
+            Codec::MatroskaAudio(ref c) => Codec::MatroskaAudio(c.for_source(probe)),
+            Codec::Mp4(ref c) => Codec::Mp4(c.for_source(probe)),
+            Codec::Music(ref c) => Codec::Music(c.for_source(probe)),
+            Codec::Wav(ref c) => Codec::Wav(c.for_source(probe)),
+        }
+    }
+    /// Maps the selected streams instead of the ones ffmpeg picks
+    pub fn with_streams(&self, selection: Selection) -> Self {
+        match *self {
+            Codec::Matroska(ref c) => Codec::Matroska(c.with_streams(selection)),
+            Codec::MatroskaAudio(ref c) => Codec::MatroskaAudio(c.with_streams(selection)),
+            Codec::Mp4(ref c) => Codec::Mp4(c.with_streams(selection)),
+            // Only ever hold one stream
+            Codec::Music(_) | Codec::Wav(_) => self.clone(),
+        }
+    }
+    /// The source index of the first audio stream of the output, if the
+    /// container maps the streams
+    pub fn first_audio(&self) -> Option<u64> {
+        match *self {
+            Codec::Matroska(ref c) => c.first_audio(),
+            Codec::MatroskaAudio(ref c) => c.first_audio(),
+            Codec::Mp4(ref c) => c.first_audio(),
+            Codec::Music(_) | Codec::Wav(_) => None,
+        }
+    }
+    /// How many times ffmpeg runs over the source
+    pub fn passes(&self) -> u64 {
+        match *self {
+            Codec::Matroska(ref c) => c.passes(),
+            Codec::Mp4(ref c) => c.passes(),
+            Codec::MatroskaAudio(_) | Codec::Music(_) | Codec::Wav(_) => 1,
+        }
+    }
+    pub fn loudnorm(&self) -> Option<&Loudnorm> {
+        match *self {
+            Codec::Matroska(ref c) => c.loudnorm(),
+            Codec::MatroskaAudio(ref c) => c.loudnorm(),
+            Codec::Mp4(ref c) => c.loudnorm(),
+            Codec::Music(ref c) => c.loudnorm(),
+            Codec::Wav(ref c) => c.loudnorm(),
+        }
+    }
+    /// The filters of the loudness measurement pass, which include any
+    /// remix of the channels
+    pub fn measure_filter(&self) -> Option<String> {
+        match *self {
+            Codec::Matroska(ref c) => c.measure_filter(),
+            Codec::MatroskaAudio(ref c) => c.measure_filter(),
+            Codec::Mp4(ref c) => c.measure_filter(),
+            Codec::Music(ref c) => c.measure_filter(),
+            Codec::Wav(ref c) => c.measure_filter(),
+        }
+    }
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        match *self {
+            Codec::Matroska(ref c) => Codec::Matroska(c.with_loudness(measured)),
+            Codec::MatroskaAudio(ref c) => Codec::MatroskaAudio(c.with_loudness(measured)),
+            Codec::Mp4(ref c) => Codec::Mp4(c.with_loudness(measured)),
+            Codec::Music(ref c) => Codec::Music(c.with_loudness(measured)),
+            Codec::Wav(ref c) => Codec::Wav(c.with_loudness(measured)),
+        }
+    }
+    /// The streams of each kind in the output, if the container maps them
+    /// instead of leaving the choice to ffmpeg
+    pub fn planned_streams(&self) -> Option<Streams> {
+        match *self {
+            Codec::Matroska(ref c) => c.planned_streams(),
+            Codec::MatroskaAudio(ref c) => c.planned_streams(),
+            Codec::Mp4(ref c) => c.planned_streams(),
+            Codec::Music(ref c) => Some(c.planned_streams()),
+            Codec::Wav(_) => None,
+        }
+    }
+    pub fn video_bitrate(&self) -> Option<u64> {
+        match *self {
+            Codec::Matroska(ref c) => c.video_bitrate(),
+            Codec::Mp4(ref c) => c.video_bitrate(),
+            Codec::MatroskaAudio(_) | Codec::Music(_) | Codec::Wav(_) => None,
+        }
+    }
+    /// The frame rate of the video, if the encoder changes it
+    pub fn frame_rate(&self) -> Option<f64> {
+        match *self {
+            Codec::Matroska(ref c) => c.frame_rate(),
+            Codec::Mp4(ref c) => c.frame_rate(),
+            Codec::MatroskaAudio(_) | Codec::Music(_) | Codec::Wav(_) => None,
+        }
+    }
+    /// Adds a cover image, which containers without pictures ignore
+    pub fn with_cover(&self, path: PathBuf) -> Self {
+        match *self {
+            Codec::Matroska(ref c) => Codec::Matroska(c.with_cover(path)),
+            Codec::MatroskaAudio(ref c) => Codec::MatroskaAudio(c.with_cover(path)),
+            Codec::Mp4(ref c) => Codec::Mp4(c.with_cover(path)),
+            Codec::Music(ref c) => Codec::Music(c.with_cover(path)),
+            Codec::Wav(_) => self.clone(),
+        }
+    }
+    /// Inputs read after the source, like the cover of MP4
+    pub fn input_args(&self) -> Vec<ffi::OsString> {
+        match *self {
+            Codec::Mp4(ref c) => c.input_args(),
+            Codec::Music(ref c) => c.input_args(),
+            Codec::Matroska(_) | Codec::MatroskaAudio(_) | Codec::Wav(_) => vec![],
+        }
+    }
+    /// How the tags of the source are renamed, if the format has names of
+    /// its own
+    pub fn tag_scheme(&self) -> Option<Scheme> {
+        match *self {
+            Codec::Music(ref c) => Some(c.tag_scheme()),
+            Codec::Matroska(_) | Codec::MatroskaAudio(_) | Codec::Mp4(_) | Codec::Wav(_) => None,
+        }
+    }
+    /// Whether the first audio stream is normalized or mixed down, so its
+    /// loudness differs from the source
+    pub fn changes_loudness(&self) -> bool {
+        match *self {
+            Codec::Matroska(ref c) => c.changes_loudness(),
+            Codec::MatroskaAudio(ref c) => c.changes_loudness(),
+            Codec::Mp4(ref c) => c.changes_loudness(),
+            Codec::Music(ref c) => c.changes_loudness(),
+            Codec::Wav(ref c) => c.changes_loudness(),
+        }
+    }
+    /// Whether players read gain tags from the format. Matroska keeps them
+    /// with the audio track, music formats where their scheme puts tags.
+    /// MP4 has no standard atoms for them, and WAV no tags players read.
+    pub fn holds_gain_tags(&self) -> bool {
+        match *self {
+            Codec::Matroska(_) | Codec::MatroskaAudio(_) => true,
+            Codec::Music(ref c) => c.tag_scheme() != Scheme::Mp4,
+            Codec::Mp4(_) | Codec::Wav(_) => false,
+        }
+    }
+    /// The file extension of targets
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Codec::Matroska(_) => "mkv",
+            Codec::MatroskaAudio(_) => "mka",
+            Codec::Mp4(_) => "mp4",
+            Codec::Music(ref c) => c.extension(),
+            Codec::Wav(_) => "wav",
+        }
+    }
+}
+
 impl CodecTrait for Codec {
-    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: T) -> Result<(Self, T), Error> {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
+        // The music formats share a codec, which reads the name itself
+        if let Some(_) = args.peek().and_then(|&s| music::Format::from_str(s)) {
+            let (codec, args) = try!(music::Codec::from_args(args));
+            return Ok((Codec::Music(codec), args));
+        }
         let name = match args.next() {
             Some(s) => s,
             None => return Err(Error::TooShort),
@@ -26,29 +215,52 @@ impl CodecTrait for Codec {
                 let (codec, args) = try!(mkv::Codec::from_args(args));
                 (Codec::Matroska(codec), args)
             },
-            _ => return Err(Error::InvalidArg(name.to_string(), "Unsupported audio codec"))
+            "mka" => {
+                let (codec, args) = try!(mka::Codec::from_args(args));
+                (Codec::MatroskaAudio(codec), args)
+            },
+            "mp4" => {
+                let (codec, args) = try!(mp4::Codec::from_args(args));
+                (Codec::Mp4(codec), args)
+            },
+            "wav" => {
+                let (codec, args) = try!(wav::Codec::from_args(args));
+                (Codec::Wav(codec), args)
+            },
+            _ => return Err(Error::InvalidArg(name.to_string(), "Unsupported container"))
         };
 
         return Ok((codec, args))
     }
     fn to_ffmpeg_args(&self) -> Vec<ffi::OsString> {
         match *self {
-            Codec::Matroska(ref c) => c.to_ffmpeg_args()
+            Codec::Matroska(ref c) => c.to_ffmpeg_args(),
+            Codec::MatroskaAudio(ref c) => c.to_ffmpeg_args(),
+            Codec::Mp4(ref c) => c.to_ffmpeg_args(),
+            Codec::Music(ref c) => c.to_ffmpeg_args(),
+            Codec::Wav(ref c) => c.to_ffmpeg_args(),
         }
     }
     fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
         match *self {
-            Codec::Matroska(ref c) => c.to_ffprobe_id()
+            Codec::Matroska(ref c) => c.to_ffprobe_id(),
+            Codec::MatroskaAudio(ref c) => c.to_ffprobe_id(),
+            Codec::Mp4(ref c) => c.to_ffprobe_id(),
+            Codec::Music(ref c) => c.to_ffprobe_id(),
+            Codec::Wav(ref c) => c.to_ffprobe_id(),
         }
     }
     fn to_examples() -> Vec<Vec<&'static str>> {
         once(
             ("mkv", mkv::Codec::to_examples())
         )
+        .chain(once(("mka", mka::Codec::to_examples())))
+        .chain(once(("mp4", mp4::Codec::to_examples())))
+        .chain(once(("wav", wav::Codec::to_examples())))
         .into_iter().flat_map(|(codec_name, examples)| {
             examples.into_iter().map(move |example| {
                 once(codec_name).chain(example.into_iter()).collect::<Vec<&'static str>>()
             })
-        }).collect::<Vec<_>>()
+        }).chain(music::Codec::to_examples()).collect::<Vec<_>>()
     }
 }
@@ -0,0 +1,188 @@
+use super::super::{next_option, Codec as CodecTrait, Error};
+use super::super::audio;
+use super::super::audio::loudnorm::{Loudnorm, Measurement};
+use super::super::video;
+
+use cover;
+use ffprobe::FFProbe;
+use formats::Streams;
+use metadata::Chapters;
+use quality::Metric;
+use streams::Selection;
+use std::ffi::OsString;
+use std::iter::Peekable;
+use std::path::PathBuf;
+
+/// MP4 with the video and audio of the source. Subtitles are left out,
+/// since MP4 only holds its own text format.
+#[derive(Clone, Debug)]
+pub struct Codec {
+    video: video::Codec,
+    audio: audio::Codec,
+    /// The kept audio streams after the first, each with the remix of its
+    /// own channels. Two-pass loudness normalization only measures the
+    /// first, so these are normalized dynamically.
+    other_audio: Vec<audio::Codec>,
+    chapters: Chapters,
+    /// An image added as an attached picture, read as a second input
+    cover: Option<PathBuf>,
+    streams: Option<Selection>,
+}
+
+impl Codec {
+    pub fn crf_target(&self) -> Option<(Metric, f64)> {
+        self.video.crf_target()
+    }
+    pub fn with_crf(&self, crf: u64) -> Self {
+        Codec { video: self.video.with_crf(crf), ..self.clone() }
+    }
+    pub fn for_source(&self, probe: &FFProbe) -> Self {
+        let cover = self.cover.clone().filter(|_| !cover::is_embedded(probe));
+        // Without any `-map` ffmpeg could take the cover for the video
+        let streams = match (self.streams.clone(), &cover) {
+            (None, &Some(_)) => Some(Selection::default_for(probe)),
+            (streams, _) => streams,
+        };
+        let audio_bitrate = self.audio.kept_bitrate(probe, streams.as_ref());
+        Codec {
+            video: self.video.for_source(probe.video.as_ref(), probe.duration, audio_bitrate),
+            audio: self.audio.for_first_stream(probe, streams.as_ref()),
+            other_audio: self.audio.for_other_streams(probe, streams.as_ref()),
+            chapters: self.chapters,
+            cover: cover,
+            streams: streams,
+        }
+    }
+    pub fn passes(&self) -> u64 {
+        self.video.passes()
+    }
+    pub fn loudnorm(&self) -> Option<&Loudnorm> {
+        self.audio.loudnorm()
+    }
+    pub fn measure_filter(&self) -> Option<String> {
+        self.audio.measure_filter()
+    }
+    /// Whether the first audio stream is louder or quieter than the source
+    pub fn changes_loudness(&self) -> bool {
+        self.audio.changes_loudness()
+    }
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        Codec { audio: self.audio.with_loudness(measured), ..self.clone() }
+    }
+    /// The source index of the first mapped audio stream
+    pub fn first_audio(&self) -> Option<u64> {
+        self.streams.as_ref().and_then(|s| s.audio.first().cloned())
+    }
+    pub fn with_streams(&self, selection: Selection) -> Self {
+        Codec { streams: Some(selection), ..self.clone() }
+    }
+    pub fn with_cover(&self, path: PathBuf) -> Self {
+        Codec { cover: Some(path), ..self.clone() }
+    }
+    /// The cover is an attached picture next to the mapped streams
+    pub fn planned_streams(&self) -> Option<Streams> {
+        self.streams.as_ref().map(|s| Streams { image: self.cover.iter().count(), ..s.planned(true, false) })
+    }
+    pub fn video_bitrate(&self) -> Option<u64> {
+        self.video.bitrate()
+    }
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.video.frame_rate()
+    }
+    pub fn input_args(&self) -> Vec<OsString> {
+        self.cover.iter().flat_map(|c| vec!["-i".into(), c.into()]).collect()
+    }
+}
+
+impl CodecTrait for Codec {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
+        let (video, args) = try!(video::Codec::from_args(args));
+        let (audio, mut args) = try!(audio::Codec::from_args(args));
+        if let audio::Codec::Pcm(_) = audio {
+            return Err(Error::InvalidArg("pcm".to_string(), "MP4 does not hold pcm audio"));
+        }
+
+        let chapters = match next_option(&mut args, &["chapters"]) {
+            Some((_, value)) => match Chapters::from_str(value) {
+                Some(c) => c,
+                None => return Err(Error::InvalidArg(value.to_string(), "Chapters must be keep or drop")),
+            },
+            None => Chapters::default(),
+        };
+
+        let codec = Codec {
+            video: video,
+            audio: audio,
+            other_audio: Vec::new(),
+            chapters: chapters,
+            cover: None,
+            streams: None,
+        };
+        Ok((codec, args))
+    }
+    fn to_ffmpeg_args(&self) -> Vec<OsString> {
+        // The cover follows the video of the source, if there is any
+        let picture = self.streams.as_ref().map_or(1, |s| s.video.iter().count());
+        ["-f", "mp4", "-movflags", "+faststart", "-sn"].iter().map(|&s| OsString::from(s))
+            .chain(self.streams.iter().flat_map(|s| s.to_ffmpeg_args(true, false)))
+            .chain(self.chapters.to_ffmpeg_args())
+            .chain(self.video.to_ffmpeg_args())
+            .chain(self.audio.kept_args(&self.other_audio, self.streams.is_some()))
+            .chain(self.cover.iter().flat_map(|_| cover::picture_args("1:0", picture)))
+            .collect()
+    }
+
+    fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
+        (self.video.to_ffprobe_id().0, self.audio.to_ffprobe_id().1)
+    }
+    fn to_examples() -> Vec<Vec<&'static str>> {
+        match video::Codec::to_examples().into_iter().next() {
+            Some(video) => vec![video.into_iter().chain(vec!["aac", "160"]).collect()],
+            None => vec![],
+        }
+    }
+}
+
+#[test]
+fn test_cover_args() {
+    use ffprobe;
+    use formats::Registry;
+
+    let probe = |streams: &str| {
+        let output = format!("{{\"format\": {{\"format_name\": \"matroska,webm\", \"duration\": \"60\"}}, \
+                              \"streams\": [{}]}}",
+                             streams);
+        ffprobe::parse(output, &Registry::default()).unwrap().unwrap()
+    };
+    let video = "{\"index\": 0, \"codec_type\": \"video\", \"codec_name\": \"h264\", \"width\": 1920, \
+                 \"height\": 1080, \"r_frame_rate\": \"24/1\"}";
+    let audio = "{\"index\": 1, \"codec_type\": \"audio\", \"codec_name\": \"aac\", \"channels\": 2}";
+    let subtitle = "{\"index\": 2, \"codec_type\": \"subtitle\", \"codec_name\": \"subrip\"}";
+    let (codec, _) = Codec::from_args(vec!["h264", "18", "medium", "copy"].into_iter().peekable()).unwrap();
+    let codec = codec.with_cover(PathBuf::from("cover.jpg"));
+    let args = |codec: &Codec| {
+        let args: Vec<String> = codec.to_ffmpeg_args().into_iter().map(|a| a.into_string().unwrap()).collect();
+        args.join(" ")
+    };
+
+    // The cover is the second input, mapped after the video of the source
+    // so ffmpeg cannot take it for the video
+    let c = codec.for_source(&probe(&[video, audio, subtitle].join(",")));
+    assert_eq!(c.input_args(), vec![OsString::from("-i"), OsString::from("cover.jpg")]);
+    let a = args(&c);
+    assert!(a.starts_with("-f mp4 -movflags +faststart -sn -map 0:0 -map 0:1 "), "{}", a);
+    assert!(!a.contains("-map 0:2"), "{}", a);
+    assert!(a.ends_with(" -map 1:0 -c:v:1 copy -disposition:v:1 attached_pic"), "{}", a);
+    assert_eq!(c.planned_streams(), Some(Streams { video: 1, audio: 1, subtitle: 0, image: 1 }));
+
+    // Music has no video before the cover
+    let a = args(&codec.for_source(&probe(audio)));
+    assert!(a.ends_with(" -map 1:0 -c:v:0 copy -disposition:v:0 attached_pic"), "{}", a);
+
+    // A source with a cover of its own keeps it
+    let picture = "{\"index\": 2, \"codec_type\": \"video\", \"codec_name\": \"mjpeg\", \"width\": 600, \
+                   \"height\": 600, \"r_frame_rate\": \"90000/1\", \"disposition\": {\"attached_pic\": 1}}";
+    let c = codec.for_source(&probe(&[video, audio, picture].join(",")));
+    assert!(c.input_args().is_empty());
+    assert!(!args(&c).contains("1:0"));
+}
@@ -0,0 +1,169 @@
+use super::super::{Codec as CodecTrait, Error};
+use super::super::audio;
+use super::super::audio::loudnorm::{Loudnorm, Measurement};
+
+use cover;
+use ffprobe::FFProbe;
+use formats::Streams;
+use tagmap::Scheme;
+use std::ffi::OsString;
+use std::iter::Peekable;
+use std::path::PathBuf;
+
+/// The formats of music players
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Mp3,
+    Ogg,
+    M4a,
+}
+
+impl Format {
+    pub fn from_str(s: &str) -> Option<Format> {
+        match s {
+            "mp3" => Some(Format::Mp3),
+            "ogg" => Some(Format::Ogg),
+            "m4a" => Some(Format::M4a),
+            _ => None,
+        }
+    }
+    fn name(&self) -> &'static str {
+        match *self {
+            Format::Mp3 => "mp3",
+            Format::Ogg => "ogg",
+            Format::M4a => "m4a",
+        }
+    }
+    /// Whether `audio` can go in the format
+    fn holds(&self, audio: &audio::Codec) -> bool {
+        match (*self, audio) {
+            (Format::Mp3, &audio::Codec::Mp3(_)) |
+            (Format::Ogg, &audio::Codec::Opus(_)) |
+            (Format::Ogg, &audio::Codec::Flac(_)) |
+            (Format::M4a, &audio::Codec::Aac(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// One audio stream with its tags in the names of the format, and a cover
+/// as an attached picture. ffmpeg cannot put pictures in Ogg.
+#[derive(Clone, Debug)]
+pub struct Codec {
+    format: Format,
+    audio: audio::Codec,
+    /// The stream of the source that is converted
+    audio_index: Option<u64>,
+    /// An attached picture of the source, which is kept over the cover
+    picture: Option<u64>,
+    cover: Option<PathBuf>,
+}
+
+impl Codec {
+    pub fn for_source(&self, probe: &FFProbe) -> Self {
+        let picture = match self.format {
+            Format::Ogg => None,
+            Format::Mp3 | Format::M4a => {
+                probe.streams.iter().find(|s| s.disposition.attached_pic).map(|s| s.index)
+            }
+        };
+        Codec {
+            audio: self.audio.for_source(probe.audio.as_ref()),
+            audio_index: probe.audio.as_ref().map(|a| a.index),
+            picture: picture,
+            cover: self.cover.clone().filter(|_| picture.is_none() && self.format != Format::Ogg),
+            ..self.clone()
+        }
+    }
+    pub fn loudnorm(&self) -> Option<&Loudnorm> {
+        self.audio.loudnorm()
+    }
+    pub fn measure_filter(&self) -> Option<String> {
+        self.audio.measure_filter()
+    }
+    /// Whether the first audio stream is louder or quieter than the source
+    pub fn changes_loudness(&self) -> bool {
+        self.audio.changes_loudness()
+    }
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        Codec { audio: self.audio.with_loudness(measured), ..self.clone() }
+    }
+    pub fn with_cover(&self, path: PathBuf) -> Self {
+        Codec { cover: Some(path), ..self.clone() }
+    }
+    pub fn input_args(&self) -> Vec<OsString> {
+        self.cover.iter().flat_map(|c| vec!["-i".into(), c.into()]).collect()
+    }
+    pub fn planned_streams(&self) -> Streams {
+        Streams {
+            audio: self.audio_index.iter().count(),
+            image: match (self.picture, &self.cover) {
+                (None, &None) => 0,
+                _ => 1,
+            },
+            ..Streams::default()
+        }
+    }
+    pub fn tag_scheme(&self) -> Scheme {
+        match self.format {
+            Format::Mp3 => Scheme::Id3v2,
+            Format::Ogg => Scheme::Vorbis,
+            Format::M4a => Scheme::Mp4,
+        }
+    }
+    /// Opus in Ogg goes by its own extension
+    pub fn extension(&self) -> &'static str {
+        match (self.format, &self.audio) {
+            (Format::Ogg, &audio::Codec::Opus(_)) => "opus",
+            (format, _) => format.name(),
+        }
+    }
+}
+
+impl CodecTrait for Codec {
+    /// Reads the name of the format before the audio codec, e.g. `mp3,mp3,v0`
+    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
+        let format = match args.next() {
+            Some(s) => match Format::from_str(s) {
+                Some(f) => f,
+                None => return Err(Error::InvalidArg(s.to_string(), "Unsupported container")),
+            },
+            None => return Err(Error::TooShort),
+        };
+        let (audio, args) = try!(audio::Codec::from_args(args));
+        if !format.holds(&audio) {
+            let reason = match format {
+                Format::Mp3 => "MP3 only holds mp3 audio",
+                Format::Ogg => "Ogg only holds opus or flac audio",
+                Format::M4a => "M4A only holds aac audio",
+            };
+            return Err(Error::InvalidArg(format.name().to_string(), reason));
+        }
+        let codec = Codec { format: format, audio: audio, audio_index: None, picture: None, cover: None };
+        Ok((codec, args))
+    }
+    fn to_ffmpeg_args(&self) -> Vec<OsString> {
+        let format: &[&str] = match self.format {
+            Format::Mp3 => &["-f", "mp3", "-id3v2_version", "4"],
+            Format::Ogg => &["-f", "ogg"],
+            Format::M4a => &["-f", "mp4", "-movflags", "+faststart"],
+        };
+        let picture = match (self.picture, &self.cover) {
+            (Some(index), _) => cover::picture_args(&format!("0:{}", index), 0),
+            (None, &Some(_)) => cover::picture_args("1:0", 0),
+            (None, &None) => vec![],
+        };
+        format.iter().map(|&s| OsString::from(s))
+            .chain(self.audio_index.iter().flat_map(|&i| vec!["-map".into(), format!("0:{}", i).into()]))
+            .chain(picture)
+            .chain(self.audio.to_ffmpeg_args())
+            .collect()
+    }
+
+    fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
+        (None, self.audio.to_ffprobe_id().1)
+    }
+    fn to_examples() -> Vec<Vec<&'static str>> {
+        vec![vec!["mp3", "mp3", "v0"], vec!["ogg", "opus", "128"], vec!["m4a", "aac", "256"]]
+    }
+}
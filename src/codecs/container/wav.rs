@@ -0,0 +1,54 @@
+use super::super::{Codec as CodecTrait, Error};
+use super::super::audio;
+use super::super::audio::loudnorm::{Loudnorm, Measurement};
+
+use ffprobe::FFProbe;
+use std::ffi::OsString;
+use std::iter::Peekable;
+
+/// WAV with the first audio stream of the source
+#[derive(Clone, Debug)]
+pub struct Codec {
+    audio: audio::Codec,
+}
+
+impl Codec {
+    pub fn for_source(&self, probe: &FFProbe) -> Self {
+        Codec { audio: self.audio.for_source(probe.audio.as_ref()) }
+    }
+    pub fn loudnorm(&self) -> Option<&Loudnorm> {
+        self.audio.loudnorm()
+    }
+    pub fn measure_filter(&self) -> Option<String> {
+        self.audio.measure_filter()
+    }
+    pub fn with_loudness(&self, measured: Measurement) -> Self {
+        Codec { audio: self.audio.with_loudness(measured) }
+    }
+    /// Whether the first audio stream is louder or quieter than the source
+    pub fn changes_loudness(&self) -> bool {
+        self.audio.changes_loudness()
+    }
+}
+
+impl CodecTrait for Codec {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
+        let (audio, args) = try!(audio::Codec::from_args(args));
+        match audio {
+            audio::Codec::Pcm(_) => Ok((Codec { audio: audio }, args)),
+            _ => Err(Error::InvalidArg("wav".to_string(), "WAV only holds pcm audio")),
+        }
+    }
+    fn to_ffmpeg_args(&self) -> Vec<OsString> {
+        ["-f", "wav", "-vn", "-sn"].iter().map(|&s| OsString::from(s))
+            .chain(self.audio.to_ffmpeg_args())
+            .collect()
+    }
+
+    fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
+        (None, self.audio.to_ffprobe_id().1)
+    }
+    fn to_examples() -> Vec<Vec<&'static str>> {
+        audio::Codec::to_examples().into_iter().filter(|e| e.first() == Some(&"pcm")).collect()
+    }
+}
@@ -3,6 +3,7 @@ pub mod video;
 pub mod container;
 
 use std::ffi::OsString;
+use std::iter::Peekable;
 use std::error::Error as StdError;
 use std::fmt;
 
@@ -44,17 +45,34 @@ impl fmt::Display for Error {
 }
 
 pub trait Codec: Sized {
-    fn from_args<'a, T: Iterator<Item = &'a str>>(T) -> Result<(Self, T), Error>;
+    fn from_args<'a, T: Iterator<Item = &'a str>>(Peekable<T>) -> Result<(Self, Peekable<T>), Error>;
     fn to_ffmpeg_args(&self) -> Vec<OsString>;
     fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>);
     fn to_examples() -> Vec<Vec<&'static str>>;
 }
 
-
+/// Takes the next `key=value` setting off `args` if its key is one of
+/// `keys`. Settings are optional and follow the other arguments of a codec.
+pub fn next_option<'a, T: Iterator<Item = &'a str>>(args: &mut Peekable<T>,
+                                                    keys: &[&str])
+                                                    -> Option<(&'a str, &'a str)> {
+    let option = match args.peek() {
+        Some(s) => *s,
+        None => return None,
+    };
+    let mut parts = option.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if keys.contains(&key) => {
+            args.next();
+            Some((key, value))
+        }
+        _ => None,
+    }
+}
 
 pub fn get_container(s: Option<String>) -> Result<container::Codec, Error> {
     match s {
-        Some(s) => match try!(container::Codec::from_args(s.split(","))) {
+        Some(s) => match try!(container::Codec::from_args(s.split(",").peekable())) {
             (codec, mut rest) => {
                 match rest.next().is_some() {
                     false => {
@@ -1,15 +1,288 @@
 use std::ffi::{OsString};
+use std::iter::Peekable;
 use super::super::{Codec as CodecTrait, Error};
+use ffprobe::Video;
+use quality::Metric;
+use utils;
+
+const DEFAULT_CRF: u64 = 18;
+// Leaves room for the container and keeps very short files encodable when
+// a size is requested
+const SIZE_OVERHEAD: f64 = 0.02;
+const MIN_BITRATE: u64 = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rate {
+    Crf(u64),
+    /// The highest CRF that reaches the score, searched for each file
+    Target(Metric, f64),
+    /// Average bitrate in kbit/s, encoded in two passes
+    Abr(u64),
+    /// Target file size in bytes, turned into an average bitrate per file
+    Size(u64),
+    /// The default CRF, capped at a bitrate in kbit/s over a buffer in kbit
+    Vbr { maxrate: u64, bufsize: u64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    Baseline,
+    Main,
+    High,
+}
+
+impl Profile {
+    fn from_str(s: &str) -> Option<Profile> {
+        match s {
+            "baseline" => Some(Profile::Baseline),
+            "main" => Some(Profile::Main),
+            "high" => Some(Profile::High),
+            _ => None,
+        }
+    }
+    fn name(&self) -> &'static str {
+        match *self {
+            Profile::Baseline => "baseline",
+            Profile::Main => "main",
+            Profile::High => "high",
+        }
+    }
+    /// The bitrate and buffer limits of High are 25% above the levels table
+    fn rate_factor(&self) -> f64 {
+        match *self {
+            Profile::Baseline | Profile::Main => 1.,
+            Profile::High => 1.25,
+        }
+    }
+}
+
+/// Limits of a level, from table A-1 of the H.264 specification
+#[derive(Debug, PartialEq)]
+pub struct Level {
+    name: &'static str,
+    /// Macroblocks per second
+    max_mbps: u64,
+    /// Macroblocks per frame
+    max_fs: u64,
+    /// Macroblocks in the decoded picture buffer
+    max_dpb_mbs: u64,
+    /// Video bitrate in kbit/s
+    max_br: u64,
+    /// Coded picture buffer in kbit
+    max_cpb: u64,
+}
+
+const LEVELS: &'static [Level] = &[
+    Level { name: "1", max_mbps: 1485, max_fs: 99, max_dpb_mbs: 396, max_br: 64, max_cpb: 175 },
+    Level { name: "1b", max_mbps: 1485, max_fs: 99, max_dpb_mbs: 396, max_br: 128, max_cpb: 350 },
+    Level { name: "1.1", max_mbps: 3000, max_fs: 396, max_dpb_mbs: 900, max_br: 192, max_cpb: 500 },
+    Level { name: "1.2", max_mbps: 6000, max_fs: 396, max_dpb_mbs: 2376, max_br: 384, max_cpb: 1000 },
+    Level { name: "1.3", max_mbps: 11880, max_fs: 396, max_dpb_mbs: 2376, max_br: 768, max_cpb: 2000 },
+    Level { name: "2", max_mbps: 11880, max_fs: 396, max_dpb_mbs: 2376, max_br: 2000, max_cpb: 2000 },
+    Level { name: "2.1", max_mbps: 19800, max_fs: 792, max_dpb_mbs: 4752, max_br: 4000, max_cpb: 4000 },
+    Level { name: "2.2", max_mbps: 20250, max_fs: 1620, max_dpb_mbs: 8100, max_br: 4000, max_cpb: 4000 },
+    Level { name: "3", max_mbps: 40500, max_fs: 1620, max_dpb_mbs: 8100, max_br: 10000, max_cpb: 10000 },
+    Level { name: "3.1", max_mbps: 108000, max_fs: 3600, max_dpb_mbs: 18000, max_br: 14000, max_cpb: 14000 },
+    Level { name: "3.2", max_mbps: 216000, max_fs: 5120, max_dpb_mbs: 20480, max_br: 20000, max_cpb: 20000 },
+    Level { name: "4", max_mbps: 245760, max_fs: 8192, max_dpb_mbs: 32768, max_br: 20000, max_cpb: 25000 },
+    Level { name: "4.1", max_mbps: 245760, max_fs: 8192, max_dpb_mbs: 32768, max_br: 50000, max_cpb: 62500 },
+    Level { name: "4.2", max_mbps: 522240, max_fs: 8704, max_dpb_mbs: 34816, max_br: 50000, max_cpb: 62500 },
+    Level { name: "5", max_mbps: 589824, max_fs: 22080, max_dpb_mbs: 110400, max_br: 135000, max_cpb: 135000 },
+    Level { name: "5.1", max_mbps: 983040, max_fs: 36864, max_dpb_mbs: 184320, max_br: 240000, max_cpb: 240000 },
+    Level { name: "5.2", max_mbps: 2073600, max_fs: 36864, max_dpb_mbs: 184320, max_br: 240000, max_cpb: 240000 },
+    Level { name: "6", max_mbps: 4177920, max_fs: 139264, max_dpb_mbs: 696320, max_br: 240000, max_cpb: 240000 },
+    Level { name: "6.1", max_mbps: 8355840, max_fs: 139264, max_dpb_mbs: 696320, max_br: 480000, max_cpb: 480000 },
+    Level { name: "6.2", max_mbps: 16711680, max_fs: 139264, max_dpb_mbs: 696320, max_br: 800000, max_cpb: 800000 },
+];
+const DEFAULT_LEVEL: usize = 12;
+const MAX_REFS: u64 = 16;
+
+fn find_level(name: &str) -> Option<&'static Level> {
+    // Accept 41 and 4.0 as well as 4.1 and 4
+    let name = match (name.len(), name.contains('.'), name.ends_with(".0")) {
+        (2, false, false) if name != "1b" => format!("{}.{}", &name[..1], &name[1..]),
+        (_, true, true) => name[..name.len() - 2].to_string(),
+        _ => name.to_string(),
+    };
+    LEVELS.iter().find(|l| l.name == name)
+}
+
+fn macroblocks(pixels: u64) -> u64 {
+    (pixels + 15) / 16
+}
+
+/// What a source needs to stay within a level
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    pub scale: Option<(u64, u64)>,
+    pub fps: Option<f64>,
+    pub refs: u64,
+}
+
+impl Level {
+    /// Shrinks frames larger than the level allows, keeping the aspect ratio,
+    /// and caps the frame rate to the macroblock rate of the level
+    fn limits(&self, width: u64, height: u64, fps: f64) -> Limits {
+        // No side may be longer than sqrt(8 * MaxFS) macroblocks
+        let max_side = ((8 * self.max_fs) as f64).sqrt().floor() as u64 * 16;
+        let fits = |w: u64, h: u64| {
+            macroblocks(w) * macroblocks(h) <= self.max_fs && w <= max_side && h <= max_side
+        };
+
+        let (mut w, mut h) = (width, height);
+        let mut scale = None;
+        if width != 0 && height != 0 && !fits(width, height) {
+            let ratio = (self.max_fs as f64 / (macroblocks(width) * macroblocks(height)) as f64)
+                .sqrt()
+                .min(max_side as f64 / width as f64)
+                .min(max_side as f64 / height as f64);
+            w = (width as f64 * ratio / 16.).floor() as u64 * 16 + 16;
+            h = 0;
+            while w > 16 && (h == 0 || !fits(w, h)) {
+                w -= 16;
+                h = ((height as f64 * w as f64 / width as f64 / 2.).round() as u64 * 2).max(2);
+            }
+            scale = Some((w, h));
+        }
+
+        let frame = macroblocks(w) * macroblocks(h);
+        let max_fps = match frame {
+            0 => fps,
+            frame => (self.max_mbps as f64 / frame as f64).floor(),
+        };
+        Limits {
+            scale: scale,
+            fps: match fps > max_fps && max_fps >= 1. {
+                true => Some(max_fps),
+                false => None,
+            },
+            refs: match frame {
+                0 => MAX_REFS,
+                frame => (self.max_dpb_mbs / frame).max(1).min(MAX_REFS),
+            },
+        }
+    }
+}
+
+fn parse_kbit(s: &str) -> Option<u64> {
+    let (number, multiplier) = match s.chars().last() {
+        Some('k') | Some('K') => (&s[..s.len() - 1], 1.),
+        Some('m') | Some('M') => (&s[..s.len() - 1], 1000.),
+        _ => (s, 1.),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n > 0. => Some((n * multiplier).round() as u64),
+        _ => None,
+    }
+}
+
+fn parse_rate(s: &str) -> Result<Rate, Error> {
+    let mut parts = s.splitn(2, '=');
+    let (key, value) = match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) => (key, value),
+        _ => {
+            return match s.parse::<i64>() {
+                Ok(n) if n >= 0 && n <= 51 => Ok(Rate::Crf(n as u64)),
+                Ok(_) => Err(Error::InvalidArg(s.to_string(), "CRF must be between 0 and 51")),
+                Err(_) => Err(Error::InvalidArg(s.to_string(), "CRF must be a number, or one of size=, abr=, vbr= or a target like vmaf=95")),
+            }
+        }
+    };
+    match key {
+        "size" => match utils::parse_size(value) {
+            Some(n) if n > 0 => Ok(Rate::Size(n)),
+            _ => Err(Error::InvalidArg(s.to_string(), "Size must be like 700M")),
+        },
+        "abr" => match parse_kbit(value) {
+            Some(n) => Ok(Rate::Abr(n)),
+            None => Err(Error::InvalidArg(s.to_string(), "Bitrate must be in kbit/s like 2000 or 2M")),
+        },
+        "vbr" => {
+            let mut rates = value.splitn(2, ':').map(parse_kbit);
+            match (rates.next(), rates.next()) {
+                (Some(Some(maxrate)), None) => Ok(Rate::Vbr { maxrate: maxrate, bufsize: maxrate * 2 }),
+                (Some(Some(maxrate)), Some(Some(bufsize))) => Ok(Rate::Vbr { maxrate: maxrate, bufsize: bufsize }),
+                _ => Err(Error::InvalidArg(s.to_string(), "Constrained VBR must be MAXRATE or MAXRATE:BUFSIZE in kbit")),
+            }
+        },
+        _ => match Metric::parse_target(s) {
+            Some((metric, score)) => Ok(Rate::Target(metric, score)),
+            None => Err(Error::InvalidArg(s.to_string(), "Unknown rate control")),
+        },
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Codec {
-    crf: u64,
-    speed: &'static str
+    rate: Rate,
+    speed: &'static str,
+    profile: Profile,
+    level: &'static Level,
+    /// Computed for each source by `for_source`
+    limits: Option<Limits>,
 }
 
 impl Default for Codec {
     fn default() -> Self {
-        Codec { crf: 18, speed: SPEEDS[5] }
+        Codec {
+            rate: Rate::Crf(DEFAULT_CRF),
+            speed: SPEEDS[5],
+            profile: Profile::High,
+            level: &LEVELS[DEFAULT_LEVEL],
+            limits: None,
+        }
+    }
+}
+
+impl Codec {
+    pub fn crf_target(&self) -> Option<(Metric, f64)> {
+        match self.rate {
+            Rate::Target(metric, score) => Some((metric, score)),
+            _ => None,
+        }
+    }
+    pub fn with_crf(&self, crf: u64) -> Self {
+        Codec { rate: Rate::Crf(crf), ..self.clone() }
+    }
+    /// Turns a target size into the average bitrate that fills it, given the
+    /// duration in seconds and the bitrate of the audio in kbit/s, and
+    /// computes the limits the source needs to comply with the level
+    pub fn for_source(&self, video: Option<&Video>, duration: f64, audio_bitrate: u64) -> Self {
+        let rate = match self.rate {
+            Rate::Size(size) if duration > 0. => {
+                let total = size as f64 * 8. / 1000. / duration * (1. - SIZE_OVERHEAD);
+                let video = (total - audio_bitrate as f64).max(MIN_BITRATE as f64);
+                Rate::Abr(video.floor() as u64)
+            }
+            ref rate => rate.clone(),
+        };
+        Codec {
+            rate: rate,
+            limits: video.map(|v| self.level.limits(v.width, v.height, v.fps)),
+            ..self.clone()
+        }
+    }
+    /// The bitrate in kbit/s and buffer in kbit allowed by the level
+    fn vbv(&self) -> (u64, u64) {
+        let factor = self.profile.rate_factor();
+        ((self.level.max_br as f64 * factor) as u64, (self.level.max_cpb as f64 * factor) as u64)
+    }
+    pub fn passes(&self) -> u64 {
+        match self.rate {
+            Rate::Abr(_) | Rate::Size(_) => 2,
+            Rate::Crf(_) | Rate::Target(..) | Rate::Vbr { .. } => 1,
+        }
+    }
+    /// The average bitrate in kbit/s, if the rate is controlled by bitrate
+    pub fn bitrate(&self) -> Option<u64> {
+        match self.rate {
+            Rate::Abr(bitrate) => Some(bitrate),
+            _ => None,
+        }
+    }
+    /// The frame rate the level caps the video to, see `for_source`
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.limits.as_ref().and_then(|l| l.fps)
     }
 }
 
@@ -46,41 +319,135 @@ fn translate_speed<'a>(s: &'a str) -> Option<&'static str> {
 }
 
 impl CodecTrait for Codec {
-    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: T) -> Result<(Self, T), Error> {
-        let (crf, speed) = match (args.next(),args.next(),) {
-            (Some(crf),Some(speed)) => (crf, speed),
+    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
+        let (rate, speed) = match (args.next(),args.next(),) {
+            (Some(rate),Some(speed)) => (rate, speed),
             _ => return Err(Error::TooShort)
         };
 
-        let crf = match crf.parse::<i64>() {
-            Ok(s) if s >= 0 && s <= 51 => s as u64,
-            Ok(_) => return Err(Error::InvalidArg(crf.to_string(), "CRF must be between 0 and 51")),
-            Err(_) => return Err(Error::InvalidArg(crf.to_string(), "CRF must be a number"))
-        };
+        let rate = try!(parse_rate(rate));
         let speed = match translate_speed(speed) {
             Some(s) => s,
-            None => return Err(Error::InvalidArg(crf.to_string(), "Speed must be "))
+            None => return Err(Error::InvalidArg(speed.to_string(), "Speed must be "))
         };
 
-        Ok((Codec { crf: crf, speed: speed }, args))
+        let mut codec = Codec { rate: rate, speed: speed, ..Codec::default() };
+        // Optional key=value settings follow the speed
+        loop {
+            let option = match args.peek() {
+                Some(s) if s.starts_with("profile=") || s.starts_with("level=") => *s,
+                _ => break,
+            };
+            args.next();
+            let mut parts = option.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("profile"), Some(value)) => codec.profile = match Profile::from_str(value) {
+                    Some(p) => p,
+                    None => return Err(Error::InvalidArg(option.to_string(), "Profile must be baseline, main or high")),
+                },
+                (_, Some(value)) => codec.level = match find_level(value) {
+                    Some(l) => l,
+                    None => return Err(Error::InvalidArg(option.to_string(), "Unknown H.264 level")),
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        Ok((codec, args))
     }
     fn to_ffmpeg_args<'a>(&self) -> Vec<OsString> {
-        vec![
+        // All three profiles only allow 8 bit 4:2:0
+        let mut args: Vec<OsString> = vec![
             "-c:v".into(),
             "libx264".into(),
+            "-profile:v".into(),
+            self.profile.name().into(),
             "-level".into(),
-            "4.1".into(),
+            self.level.name.into(),
+            "-pix_fmt".into(),
+            "yuv420p".into(),
             "-preset".into(),
             self.speed.into(),
-            "-crf".into(),
-            format!("{}", self.crf).into(),
-        ]
+        ];
+
+        if let Some(ref limits) = self.limits {
+            args.extend(vec!["-refs".into(), format!("{}", limits.refs).into()]);
+            if let Some((width, height)) = limits.scale {
+                args.extend(vec!["-filter:v:0".into(), format!("scale={}:{}", width, height).into()]);
+            }
+            if let Some(fps) = limits.fps {
+                args.extend(vec!["-r:v:0".into(), format!("{}", fps).into()]);
+            }
+        }
+
+        // Targets that were not searched for (dry runs) and sizes that were
+        // not resolved with `for_source` fall back to the default CRF
+        let (level_maxrate, level_bufsize) = self.vbv();
+        let (rate, maxrate, bufsize) = match self.rate {
+            Rate::Abr(bitrate) => {
+                let bitrate = bitrate.min(level_maxrate);
+                (vec!["-b:v".into(), format!("{}k", bitrate).into()], level_maxrate, level_bufsize)
+            }
+            Rate::Vbr { maxrate, bufsize } => {
+                (vec!["-crf".into(), format!("{}", DEFAULT_CRF).into()],
+                 maxrate.min(level_maxrate),
+                 bufsize.min(level_bufsize))
+            }
+            Rate::Crf(crf) => (vec!["-crf".into(), format!("{}", crf).into()], level_maxrate, level_bufsize),
+            Rate::Target(..) | Rate::Size(_) => {
+                (vec!["-crf".into(), format!("{}", DEFAULT_CRF).into()], level_maxrate, level_bufsize)
+            }
+        };
+        args.extend(rate);
+        args.extend(vec!["-maxrate".into(),
+                         format!("{}k", maxrate).into(),
+                         "-bufsize".into(),
+                         format!("{}k", bufsize).into()]);
+        args
     }
     fn to_ffprobe_id(&self) -> (Option<&'static str>, Option<&'static str>) {
         (None, Some("h264"))
     }
     fn to_examples() -> Vec<Vec<&'static str>> {
-        vec![vec!["18", "normal"]]
-
+        vec![vec!["18", "normal"],
+             vec!["vmaf=95", "medium"],
+             vec!["size=700M", "slow"],
+             vec!["abr=2M", "slow"],
+             vec!["vbr=4000:8000", "medium"],
+             vec!["18", "medium", "profile=main", "level=4.0"]]
     }
 }
+
+#[test]
+fn test_parse_rate() {
+    assert_eq!(parse_rate("20").ok(), Some(Rate::Crf(20)));
+    assert_eq!(parse_rate("abr=2M").ok(), Some(Rate::Abr(2000)));
+    assert_eq!(parse_rate("abr=1500k").ok(), Some(Rate::Abr(1500)));
+    assert_eq!(parse_rate("vbr=4000").ok(), Some(Rate::Vbr { maxrate: 4000, bufsize: 8000 }));
+    assert_eq!(parse_rate("size=1M").ok(), Some(Rate::Size(1 << 20)));
+    assert!(parse_rate("52").is_err());
+    assert!(parse_rate("abr=fast").is_err());
+
+    // 1 MiB over 10 seconds is ~839 kbit/s, less overhead and 128 kbit/s audio
+    let codec = Codec { rate: Rate::Size(1 << 20), ..Codec::default() };
+    assert_eq!(codec.for_source(None, 10., 128).bitrate(), Some(694));
+    assert_eq!(codec.for_source(None, 0.5, 20000).bitrate(), Some(MIN_BITRATE));
+}
+
+#[test]
+fn test_level_limits() {
+    let level = find_level("4.1").unwrap();
+    assert_eq!(find_level("41"), Some(level));
+    assert_eq!(find_level("4.0"), find_level("4"));
+
+    // 1080p fits 4.1, but only up to 30 fps
+    assert_eq!(level.limits(1920, 1080, 25.),
+               Limits { scale: None, fps: None, refs: 4 });
+    assert_eq!(level.limits(1920, 1080, 60.),
+               Limits { scale: None, fps: Some(30.), refs: 4 });
+    // 4K is scaled down to fit 8192 macroblocks
+    let limits = level.limits(3840, 2160, 24.);
+    let (w, h) = limits.scale.unwrap();
+    assert!(macroblocks(w) * macroblocks(h) <= 8192);
+    assert_eq!((w, h), (1920, 1080));
+}
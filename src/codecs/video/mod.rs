@@ -1,8 +1,10 @@
 mod h264;
 
 use super::{Codec as CodecTrait, Error};
+use ffprobe::Video;
+use quality::Metric;
 use std::ffi;
-use std::iter::once;
+use std::iter::{once, Peekable};
 
 
 #[derive(Clone, Debug)]
@@ -16,9 +18,43 @@ impl Default for Codec {
     }
 }
 
+impl Codec {
+    /// The quality to search a CRF for, if the rate is given as a target
+    pub fn crf_target(&self) -> Option<(Metric, f64)> {
+        match *self {
+            Codec::H264(ref c) => c.crf_target()
+        }
+    }
+    pub fn with_crf(&self, crf: u64) -> Self {
+        match *self {
+            Codec::H264(ref c) => Codec::H264(c.with_crf(crf))
+        }
+    }
+    pub fn for_source(&self, video: Option<&Video>, duration: f64, audio_bitrate: u64) -> Self {
+        match *self {
+            Codec::H264(ref c) => Codec::H264(c.for_source(video, duration, audio_bitrate))
+        }
+    }
+    pub fn passes(&self) -> u64 {
+        match *self {
+            Codec::H264(ref c) => c.passes()
+        }
+    }
+    pub fn bitrate(&self) -> Option<u64> {
+        match *self {
+            Codec::H264(ref c) => c.bitrate()
+        }
+    }
+    /// The frame rate of the output, if it differs from the source
+    pub fn frame_rate(&self) -> Option<f64> {
+        match *self {
+            Codec::H264(ref c) => c.frame_rate()
+        }
+    }
+}
 
 impl CodecTrait for Codec {
-    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: T) -> Result<(Self, T), Error> {
+    fn from_args<'a, T: Iterator<Item = &'a str>>(mut args: Peekable<T>) -> Result<(Self, Peekable<T>), Error> {
         let name = match args.next() {
             Some(s) => s,
             None => return Err(Error::TooShort),
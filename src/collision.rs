@@ -0,0 +1,250 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error as StdError;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    Extension,
+    Number,
+    Error,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Extension
+    }
+}
+
+impl Policy {
+    pub fn from_str(s: &str) -> Option<Policy> {
+        match s {
+            "extension" => Some(Policy::Extension),
+            "number" => Some(Policy::Number),
+            "error" => Some(Policy::Error),
+            _ => None,
+        }
+    }
+    pub fn names() -> &'static [&'static str] {
+        &["extension", "number", "error"]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Collision {
+    pub source: PathBuf,
+    pub planned: PathBuf,
+    pub resolved: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub target: PathBuf,
+    pub sources: Vec<PathBuf>,
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        "Sources map to the same target, or to one that only differs in case from a file on disk"
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}: {:?}", self.description(), self.target));
+        for source in &self.sources {
+            try!(write!(f, "\n    {:?}", source));
+        }
+        Ok(())
+    }
+}
+
+// Targets are compared case-folded, so names that only differ in case are
+// treated as colliding. They would overwrite each other on case-insensitive
+// filesystems.
+fn key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+fn with_suffix(path: &Path, suffix: &OsStr) -> PathBuf {
+    let mut filename = path.file_stem().unwrap_or("".as_ref()).to_os_string();
+    filename.push(suffix);
+    if let Some(s) = path.extension() {
+        filename.push(".");
+        filename.push(s)
+    }
+    path.with_file_name(filename)
+}
+
+fn numbered<F: Fn(&Path) -> bool>(path: &Path, free: F) -> PathBuf {
+    let mut n = 2;
+    loop {
+        let candidate = with_suffix(path, OsStr::new(&format!(" ({})", n)));
+        if free(&candidate) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// The files in the directories of `targets`
+pub fn on_disk(targets: &[PathBuf]) -> Vec<PathBuf> {
+    let mut dirs: Vec<&Path> = targets.iter().filter_map(|t| t.parent()).collect();
+    dirs.sort();
+    dirs.dedup();
+    dirs.into_iter()
+        .filter_map(|dir| dir.read_dir().ok())
+        .flat_map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()))
+        .collect()
+}
+
+/// Takes (source, planned target) pairs and returns the resolved target for
+/// each pair in the same order, together with a report of every rename.
+/// Targets also collide with the files in `on_disk` whose names only differ
+/// from them in case. Files with the very same name are left alone, they
+/// are skipped as existing targets. The targets in `reserved` were resolved
+/// before and keep their names, so targets colliding with them are renamed.
+pub fn resolve(planned: &[(PathBuf, PathBuf)],
+               reserved: &[PathBuf],
+               on_disk: &[PathBuf],
+               policy: Policy)
+               -> Result<(Vec<PathBuf>, Vec<Collision>), Error> {
+    let on_disk: HashMap<String, &PathBuf> = on_disk.iter().map(|p| (key(p), p)).collect();
+    let reserved: HashSet<String> = reserved.iter().map(|p| key(p)).collect();
+    let clashes = |path: &Path| {
+        reserved.contains(&key(path)) || on_disk.get(&key(path)).map_or(false, |&p| p != path)
+    };
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_of: HashMap<String, usize> = HashMap::new();
+
+    for (i, &(_, ref target)) in planned.iter().enumerate() {
+        let k = key(target);
+        match group_of.get(&k).map(|&g| g) {
+            Some(g) => groups[g].push(i),
+            None => {
+                group_of.insert(k, groups.len());
+                groups.push(vec![i]);
+            }
+        }
+    }
+
+    let mut taken: HashSet<String> = group_of.keys().chain(&reserved).cloned().collect();
+    let mut resolved: Vec<PathBuf> = planned.iter().map(|&(_, ref t)| t.clone()).collect();
+    let mut collisions = Vec::new();
+
+    for group in groups.into_iter().filter(|g| g.len() > 1 || clashes(&planned[g[0]].1)) {
+        let renamed: Vec<usize> = match policy {
+            Policy::Error => {
+                return Err(Error {
+                    target: planned[group[0]].1.clone(),
+                    sources: group.iter().map(|&i| planned[i].0.clone()).collect(),
+                })
+            }
+            Policy::Number if !clashes(&planned[group[0]].1) => group.into_iter().skip(1).collect(),
+            Policy::Extension | Policy::Number => group,
+        };
+
+        for i in renamed {
+            let (ref source, ref target) = planned[i];
+            let by_extension = source.extension().map(|ext| {
+                let mut suffix = OsString::from(".");
+                suffix.push(ext);
+                with_suffix(target, &suffix)
+            });
+
+            let path = {
+                let free = |p: &Path| !taken.contains(&key(p)) && !clashes(p);
+                match by_extension {
+                    Some(ref p) if policy == Policy::Extension && free(p) => p.clone(),
+                    _ => numbered(target, free),
+                }
+            };
+
+            taken.insert(key(&path));
+            collisions.push(Collision {
+                source: source.clone(),
+                planned: target.clone(),
+                resolved: path.clone(),
+            });
+            resolved[i] = path;
+        }
+    }
+
+    Ok((resolved, collisions))
+}
+
+#[test]
+fn test_resolve() {
+    let planned: Vec<(PathBuf, PathBuf)> = vec![("a/holiday.avi".into(), "b/holiday.mkv".into()),
+                                                ("a/holiday.mp4".into(), "b/holiday.mkv".into()),
+                                                ("a/Holiday.MP4".into(), "b/Holiday.mkv".into()),
+                                                ("a/other.avi".into(), "b/other.mkv".into())];
+
+    let (paths, collisions) = resolve(&planned, &[], &[], Policy::Extension).unwrap();
+    assert_eq!(paths,
+               vec![PathBuf::from("b/holiday.avi.mkv"),
+                    PathBuf::from("b/holiday.mp4.mkv"),
+                    PathBuf::from("b/Holiday (2).mkv"),
+                    PathBuf::from("b/other.mkv")]);
+    assert_eq!(collisions.len(), 3);
+
+    let (paths, collisions) = resolve(&planned, &[], &[], Policy::Number).unwrap();
+    assert_eq!(paths,
+               vec![PathBuf::from("b/holiday.mkv"),
+                    PathBuf::from("b/holiday (2).mkv"),
+                    PathBuf::from("b/Holiday (3).mkv"),
+                    PathBuf::from("b/other.mkv")]);
+    assert_eq!(collisions.len(), 2);
+
+    assert!(resolve(&planned, &[], &[], Policy::Error).is_err());
+
+    // A file of the same name is an existing target, one that only differs
+    // in case is another file
+    let on_disk = vec![PathBuf::from("b/other.mkv"), PathBuf::from("b/Holiday (2).MKV")];
+    let (paths, collisions) = resolve(&planned[1..], &[], &on_disk, Policy::Number).unwrap();
+    assert_eq!(paths,
+               vec![PathBuf::from("b/holiday.mkv"),
+                    PathBuf::from("b/Holiday (3).mkv"),
+                    PathBuf::from("b/other.mkv")]);
+    assert_eq!(collisions.len(), 1);
+    let on_disk = vec![PathBuf::from("b/OTHER.mkv")];
+    let (paths, _) = resolve(&planned[3..], &[], &on_disk, Policy::Number).unwrap();
+    assert_eq!(paths, vec![PathBuf::from("b/other (2).mkv")]);
+
+    // Reserved targets keep their names, in any case
+    let reserved = vec![PathBuf::from("b/OTHER.mkv")];
+    let (paths, collisions) = resolve(&planned[3..], &reserved, &[], Policy::Extension).unwrap();
+    assert_eq!(paths, vec![PathBuf::from("b/other.avi.mkv")]);
+    assert_eq!(collisions.len(), 1);
+    assert!(resolve(&planned[3..], &reserved, &[], Policy::Error).is_err());
+}
+
+#[test]
+fn test_on_disk() {
+    use std::fs::{File, create_dir, remove_dir_all};
+
+    let root = ::std::env::temp_dir().join(format!("transcode-collision-{}", ::std::process::id()));
+    let _ = remove_dir_all(&root);
+    create_dir(&root).unwrap();
+    File::create(root.join("Holiday.MKV")).unwrap();
+    File::create(root.join("other.mkv")).unwrap();
+
+    // Directories that do not exist yet hold no files
+    let planned: Vec<(PathBuf, PathBuf)> = vec![("a/holiday.avi".into(), root.join("holiday.mkv")),
+                                                ("a/other.avi".into(), root.join("other.mkv")),
+                                                ("a/new.avi".into(), root.join("new").join("new.mkv"))];
+    let targets: Vec<PathBuf> = planned.iter().map(|&(_, ref t)| t.clone()).collect();
+    let mut files = on_disk(&targets);
+    files.sort();
+    assert_eq!(files, vec![root.join("Holiday.MKV"), root.join("other.mkv")]);
+
+    let (paths, collisions) = resolve(&planned, &[], &files, Policy::Extension).unwrap();
+    assert_eq!(paths,
+               vec![root.join("holiday.avi.mkv"), root.join("other.mkv"), root.join("new").join("new.mkv")]);
+    assert_eq!(collisions.len(), 1);
+    assert!(resolve(&planned, &[], &files, Policy::Error).is_err());
+
+    remove_dir_all(&root).unwrap();
+}
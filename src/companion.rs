@@ -0,0 +1,335 @@
+use check_file;
+use collision;
+use glob;
+use path;
+use source::BasedPath;
+use std::error::Error as StdError;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    Copy,
+    HardLink,
+    SymLink,
+    Ignore,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Ignore
+    }
+}
+
+impl Policy {
+    pub fn from_str(s: &str) -> Option<Policy> {
+        match s {
+            "copy" => Some(Policy::Copy),
+            "hardlink" => Some(Policy::HardLink),
+            "symlink" => Some(Policy::SymLink),
+            "ignore" => Some(Policy::Ignore),
+            _ => None,
+        }
+    }
+    pub fn verb(&self) -> &'static str {
+        match *self {
+            Policy::Copy => "Copying",
+            Policy::HardLink => "Hard-linking",
+            Policy::SymLink => "Symlinking",
+            Policy::Ignore => "Ignoring",
+        }
+    }
+}
+
+/// Decides which non-media files are companions. When no patterns are given
+/// every non-media file is selected.
+#[derive(Debug, Clone, Default)]
+pub struct Selector {
+    pub globs: Vec<glob::Pattern>,
+    pub mime_types: Vec<glob::Pattern>,
+    pub mime_fallback: bool,
+}
+
+impl Selector {
+    fn is_empty(&self) -> bool {
+        self.globs.is_empty() && self.mime_types.is_empty()
+    }
+
+    fn matches(&self, path: &BasedPath) -> Result<bool, Error> {
+        if self.is_empty() {
+            return Ok(true);
+        }
+        let relative = path.relative();
+        let by_glob = self.globs.iter().any(|g| g.matches_relative(&relative));
+        if by_glob || self.mime_types.is_empty() {
+            return Ok(by_glob);
+        }
+
+        let mime_type = try!(check_file::mime_type(&path.path, self.mime_fallback).map_err(Error::MimeError));
+        Ok(self.mime_types.iter().any(|g| g.matches(&mime_type)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Companion {
+    pub source: BasedPath,
+    pub target: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    MimeError(check_file::Error),
+    CollisionError(collision::Error),
+    NormalizeError { path: PathBuf, error: io::Error },
+    MkDirError { path: PathBuf, error: io::Error },
+    TransferError {
+        from: PathBuf,
+        to: PathBuf,
+        error: io::Error,
+    },
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::MimeError(_) => "Could not get mime-type of companion file",
+            Error::CollisionError(_) => "Companion target collision",
+            Error::NormalizeError { .. } => "Could not normalize companion target path",
+            Error::MkDirError { .. } => "Could not create parent directories for companion file",
+            Error::TransferError { .. } => "Could not transfer companion file",
+        }
+    }
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::MimeError(ref error) => Some(error),
+            Error::CollisionError(ref error) => Some(error),
+            Error::NormalizeError { ref error, .. } |
+            Error::MkDirError { ref error, .. } |
+            Error::TransferError { ref error, .. } => Some(error),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::MimeError(_) |
+            Error::CollisionError(_) => write!(f, "{}", self.description()),
+            Error::NormalizeError { ref path, .. } |
+            Error::MkDirError { ref path, .. } => write!(f, "{}: {:?}", self.description(), path),
+            Error::TransferError { ref from, ref to, .. } => {
+                write!(f, "{}: {:?} -> {:?}", self.description(), from, to)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Companions {
+    pub policy: Policy,
+    pub companions: Vec<Companion>,
+    pub existing: Vec<Companion>,
+    pub collisions: Vec<collision::Collision>,
+}
+
+impl Companions {
+    /// Picks companions among `candidates` and maps them into `target_dir`.
+    ///
+    /// `media` holds (source, target) pairs of every media file. A companion
+    /// sharing the stem of a media file in the same directory (`movie.srt`,
+    /// `movie.en.srt` next to `movie.avi`) follows the media file's target name.
+    /// Targets colliding with each other or with media targets are resolved
+    /// by `collisions`, and media targets keep their names.
+    /// Returns the candidates that were not selected as the second value.
+    pub fn plan(candidates: Vec<BasedPath>,
+                media: &[(PathBuf, PathBuf)],
+                target_dir: &str,
+                policy: Policy,
+                selector: &Selector,
+                collisions: collision::Policy)
+                -> Result<(Companions, Vec<BasedPath>), Error> {
+        let mut companions = Companions {
+            policy: policy,
+            companions: Vec::new(),
+            existing: Vec::new(),
+            collisions: Vec::new(),
+        };
+        if policy == Policy::Ignore {
+            return Ok((companions, candidates));
+        }
+
+        let mut selected = Vec::new();
+        let mut rest = Vec::new();
+        for candidate in candidates {
+            if !try!(selector.matches(&candidate)) {
+                rest.push(candidate);
+                continue;
+            }
+
+            let target = match sidecar_target(&candidate.path, media) {
+                Some(t) => t,
+                None => Path::new(target_dir).join(candidate.relative()),
+            };
+            let target = try!(path::normalize(&target).map_err(|e| {
+                Error::NormalizeError {
+                    path: target,
+                    error: e,
+                }
+            }));
+
+            selected.push((candidate, target));
+        }
+
+        let planned: Vec<(PathBuf, PathBuf)> = selected.iter()
+            .map(|&(ref source, ref target)| (source.path.clone(), target.clone()))
+            .collect();
+        let reserved: Vec<PathBuf> = media.iter().map(|&(_, ref target)| target.clone()).collect();
+        let on_disk = collision::on_disk(&planned.iter().map(|&(_, ref t)| t.clone()).collect::<Vec<_>>());
+        let (targets, collided) = try!(collision::resolve(&planned, &reserved, &on_disk, collisions)
+            .map_err(Error::CollisionError));
+        companions.collisions = collided;
+
+        for ((source, _), target) in selected.into_iter().zip(targets) {
+            let companion = Companion {
+                source: source,
+                target: target,
+            };
+            match companion.target.exists() {
+                true => companions.existing.push(companion),
+                false => companions.companions.push(companion),
+            }
+        }
+        Ok((companions, rest))
+    }
+
+    pub fn len(&self) -> usize {
+        self.companions.len()
+    }
+
+    pub fn transfer<F: FnMut(Error)>(&self, dry_run: bool, mut on_error: F) {
+        if dry_run {
+            return;
+        }
+        for companion in &self.companions {
+            if let Err(e) = transfer(self.policy, &companion.source, &companion.target) {
+                on_error(e);
+            }
+        }
+    }
+}
+
+fn sidecar_target(path: &Path, media: &[(PathBuf, PathBuf)]) -> Option<PathBuf> {
+    let name = match path.file_name() {
+        Some(n) => n.to_string_lossy(),
+        None => return None,
+    };
+    let parent = path.parent();
+
+    let best = media.iter()
+        .filter(|&&(ref source, _)| source.parent() == parent)
+        .filter_map(|&(ref source, ref target)| {
+            source.file_stem().map(|stem| (stem.to_string_lossy().into_owned(), target))
+        })
+        .filter(|&(ref stem, _)| name.len() > stem.len() + 1 && name.starts_with(stem.as_str()) &&
+                                 name[stem.len()..].starts_with('.'))
+        .max_by_key(|&(ref stem, _)| stem.len());
+
+    best.and_then(|(stem, target)| {
+        target.file_stem().map(|target_stem| {
+            let mut filename = OsString::from(target_stem);
+            filename.push(&name[stem.len()..]);
+            target.with_file_name(filename)
+        })
+    })
+}
+
+fn transfer(policy: Policy, from: &Path, to: &Path) -> Result<(), Error> {
+    try!(path::mkdir_parent(to).map_err(|e| {
+        Error::MkDirError {
+            path: to.to_path_buf(),
+            error: e,
+        }
+    }));
+
+    let result = match policy {
+        Policy::Copy => fs::copy(from, to).map(|_| ()),
+        Policy::HardLink => fs::hard_link(from, to),
+        Policy::SymLink => symlink(from, to),
+        Policy::Ignore => Ok(()),
+    };
+    result.map_err(|e| {
+        Error::TransferError {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            error: e,
+        }
+    })
+}
+
+#[cfg(unix)]
+fn symlink(from: &Path, to: &Path) -> io::Result<()> {
+    ::std::os::unix::fs::symlink(from, to)
+}
+
+#[cfg(windows)]
+fn symlink(from: &Path, to: &Path) -> io::Result<()> {
+    ::std::os::windows::fs::symlink_file(from, to)
+}
+
+#[test]
+fn test_sidecar_target() {
+    let media = vec![(PathBuf::from("/in/holiday.avi"), PathBuf::from("/out/holiday.avi.mkv")),
+                     (PathBuf::from("/in/holiday.extended.avi"),
+                      PathBuf::from("/out/holiday.extended.mkv"))];
+
+    let cases = [("/in/holiday.srt", Some("/out/holiday.avi.srt")),
+                 ("/in/holiday.en.srt", Some("/out/holiday.avi.en.srt")),
+                 ("/in/holiday.extended.nfo", Some("/out/holiday.extended.nfo")),
+                 ("/in/holidays.nfo", None),
+                 ("/in/sub/holiday.srt", None)];
+
+    for &(path, expected) in &cases {
+        assert_eq!(sidecar_target(Path::new(path), &media),
+                   expected.map(PathBuf::from));
+    }
+}
+
+#[test]
+fn test_plan_collisions() {
+    let based = |path: &str| {
+        BasedPath {
+            path: PathBuf::from(path),
+            base: PathBuf::from("/nonexistent/in"),
+        }
+    };
+    let media = vec![(PathBuf::from("/nonexistent/in/foo.mp4"), PathBuf::from("/nonexistent/out/foo.mkv"))];
+    let candidates = vec![based("/nonexistent/in/foo.mkv"),
+                          based("/nonexistent/in/notes.txt"),
+                          based("/nonexistent/in/Notes.TXT")];
+    let (companions, rest) = Companions::plan(candidates,
+                                              &media,
+                                              "/nonexistent/out",
+                                              Policy::Copy,
+                                              &Selector::default(),
+                                              collision::Policy::Number)
+        .unwrap();
+    assert!(rest.is_empty());
+    let targets: Vec<PathBuf> = companions.companions.iter().map(|c| c.target.clone()).collect();
+    assert_eq!(targets,
+               vec![PathBuf::from("/nonexistent/out/foo (2).mkv"),
+                    PathBuf::from("/nonexistent/out/notes.txt"),
+                    PathBuf::from("/nonexistent/out/Notes (2).TXT")]);
+    assert_eq!(companions.collisions.len(), 2);
+
+    let candidates = vec![based("/nonexistent/in/foo.mkv")];
+    assert!(Companions::plan(candidates,
+                             &media,
+                             "/nonexistent/out",
+                             Policy::Copy,
+                             &Selector::default(),
+                             collision::Policy::Error)
+        .is_err());
+}
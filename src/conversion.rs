@@ -1,13 +1,25 @@
+use collision;
+use cover;
+use crf;
+use gain;
+use metadata;
 use ffmpeg;
+use ffprobe;
 use progress::{Status, status_sum};
+use quality;
 use source::{Sources, Source};
+use streams;
+use tagmap;
 use std::error::Error as StdError;
 use std::ffi::OsStr;
 use std::fmt;
 use std::ops::{Deref, DerefMut};
 use std::path::{PathBuf, Path};
 use target;
+use verify;
 use utils::erase_up;
+use formats;
+use codecs::audio::loudnorm;
 use codecs::container::Codec;
 
 #[derive(Debug, Clone)]
@@ -16,6 +28,26 @@ pub struct Conversion {
     pub source: Source,
     pub target: target::Target,
     pub status: Status,
+    pub scores: Option<quality::Scores>,
+    /// The CRF chosen by searching for a target quality
+    pub crf: Option<u64>,
+    /// The loudness of the source, measured for normalization
+    pub loudness: Option<loudnorm::Measurement>,
+    /// The loudness of the source and its album, measured for gain tags
+    pub gain: Option<gain::Gain>,
+    /// What happens to the metadata of the source
+    pub metadata: metadata::Policy,
+    /// Metadata written to the target
+    pub tags: Vec<(String, String)>,
+    /// Gain tags of the first audio stream, written where the format of
+    /// the target keeps them
+    pub gain_tags: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Skipped {
+    pub source: Source,
+    pub target: PathBuf,
 }
 
 #[derive(Debug)]
@@ -25,19 +57,49 @@ pub enum Error {
         error: ffmpeg::Error,
     },
     TargetError(target::Error),
+    CollisionError(collision::Error),
+    VerifyError {
+        conversion: Conversion,
+        error: verify::Error,
+    },
+    QualityError {
+        conversion: Conversion,
+        error: quality::Error,
+    },
+    CrfSearchError {
+        conversion: Conversion,
+        error: crf::Error,
+    },
+    LoudnessError { conversion: Conversion },
+    GainTagsError {
+        conversion: Conversion,
+        reason: &'static str,
+    },
 }
 
 impl StdError for Error {
     fn description(&self) -> &str {
         match *self {
             Error::TargetError(_) => "Target error",
+            Error::CollisionError(_) => "Target collision",
             Error::FFmpegError { .. } => "FFmpeg error",
+            Error::VerifyError { .. } => "Verification failed, keeping the temporary file",
+            Error::QualityError { .. } => "Quality check failed, keeping the temporary file",
+            Error::CrfSearchError { .. } => "Could not find a CRF for the target quality",
+            Error::LoudnessError { .. } => "Could not read the loudness measured by ffmpeg",
+            Error::GainTagsError { .. } => "Cannot write gain tags",
         }
     }
     fn cause(&self) -> Option<&StdError> {
         match *self {
             Error::TargetError(ref error) => Some(error),
+            Error::CollisionError(ref error) => Some(error),
             Error::FFmpegError { ref error, .. } => Some(error),
+            Error::VerifyError { ref error, .. } => Some(error),
+            Error::QualityError { ref error, .. } => Some(error),
+            Error::CrfSearchError { ref error, .. } => Some(error),
+            Error::LoudnessError { .. } |
+            Error::GainTagsError { .. } => None,
         }
     }
 }
@@ -45,10 +107,27 @@ impl StdError for Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Error::TargetError(_) => write!(f, "{}", self.description()),
-            Error::FFmpegError { ref conversion, .. } => {
+            Error::TargetError(_) |
+            Error::CollisionError(_) => write!(f, "{}", self.description()),
+            Error::FFmpegError { ref conversion, .. } |
+            Error::CrfSearchError { ref conversion, .. } |
+            Error::LoudnessError { ref conversion } => {
                 write!(f, "{}: {:?}", self.description(), conversion)
             }
+            Error::VerifyError { ref conversion, .. } |
+            Error::QualityError { ref conversion, .. } => {
+                write!(f,
+                       "{}: {:?}",
+                       self.description(),
+                       conversion.target.path_tmp)
+            }
+            Error::GainTagsError { ref conversion, reason } => {
+                write!(f,
+                       "{}: {:?}, {}",
+                       self.description(),
+                       conversion.source.path.path,
+                       reason)
+            }
         }
 
     }
@@ -56,42 +135,150 @@ impl fmt::Display for Error {
 
 impl Conversion {
     pub fn new(id: u64, target: target::Target, source: Source) -> Self {
-        let status = Status::new(source.ffprobe.mpixel());
+        let status = Status::new(source.ffprobe.mpixel(),
+                                 source.ffprobe.duration_source.is_estimate());
+        // Tracks of a CUE sheet start out with the tags of the sheet
+        let tags = source.track.as_ref().map_or(Vec::new(), |t| t.tags());
 
         Conversion {
             id: id,
             target: target,
             source: source,
             status: status,
+            scores: None,
+            crf: None,
+            loudness: None,
+            gain: None,
+            metadata: metadata::Policy::default(),
+            tags: tags,
+            gain_tags: Vec::new(),
+        }
+    }
+
+    /// Tags that the format of the target cannot hold, including the gain
+    /// tags that are added while converting
+    pub fn dropped_tags(&self, gain: Option<gain::Style>) -> Vec<String> {
+        let scheme = match self.target.codec.tag_scheme() {
+            Some(scheme) => scheme,
+            None => return Vec::new(),
+        };
+        let mut tags = match self.metadata {
+            metadata::Policy::Copy => tagmap::source_tags(&self.source.ffprobe),
+            metadata::Policy::Strip => Vec::new(),
+        };
+        tags.extend(self.tags.iter().cloned());
+        if let (Some(style), true) = (gain, self.source.ffprobe.audio.is_some()) {
+            tags.extend(style.tag_names().iter().map(|&name| (name.to_string(), String::new())));
         }
+        scheme.dropped(&tags)
     }
 }
 
 #[derive(Debug)]
 pub struct Conversions(Vec<Conversion>);
 
+/// Directories with sources that are left out of the run because their
+/// targets exist. Their albums are only partly converted.
+pub fn partial_albums(skipped: &[Skipped]) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = skipped.iter()
+        .filter(|s| s.source.ffprobe.audio.is_some())
+        .filter_map(|s| s.source.path.path.parent().map(|p| p.to_path_buf()))
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+/// The gains of tracks given as (directory, measured loudness, duration),
+/// with `None` for tracks that could not be measured. Each directory is an
+/// album, which only gets an album gain when all of its tracks were measured
+/// and it is not in `partial_albums`. An album of silent tracks gets the
+/// gain of the track.
+fn album_gains(tracks: &[(Option<&Path>, Option<gain::Loudness>, f64)],
+               partial_albums: &[PathBuf])
+               -> Vec<Option<gain::Gain>> {
+    tracks.iter()
+        .map(|&(directory, track, _)| {
+            track.map(|track| {
+                let album: Vec<_> = tracks.iter().filter(|&&(d, _, _)| d == directory).collect();
+                let complete = album.iter().all(|&&(_, t, _)| t.is_some()) &&
+                               !directory.map_or(false, |d| partial_albums.iter().any(|p| p == d));
+                let measured: Vec<_> = album.iter().filter_map(|&&(_, t, d)| t.map(|t| (t, d))).collect();
+                gain::Gain {
+                    track: track,
+                    album: match complete {
+                        true => Some(gain::album(&measured).unwrap_or(track)),
+                        false => None,
+                    },
+                }
+            })
+        })
+        .collect()
+}
+
 impl Conversions {
     pub fn from_sources(s: Sources,
                         target_dir: &str,
-                        codec: Codec
-                        )
-                        -> Result<(Conversions, Vec<PathBuf>), Error> {
+                        codec: Codec,
+                        rules: &streams::Rules,
+                        covers: bool,
+                        collisions: collision::Policy)
+                        -> Result<(Conversions, Vec<Skipped>, Vec<collision::Collision>), Error> {
         let target_dir = Path::new(&target_dir);
-        let extension = OsStr::new("mkv");
+        let extension = OsStr::new(codec.extension());
 
         if s.len() == 0 {
-            return Ok((Conversions(Vec::new()), Vec::new()));
+            return Ok((Conversions(Vec::new()), Vec::new(), Vec::new()));
         }
 
-        let sources = s.into_iter()
+        let planned: Result<Vec<_>, Error> = s.iter()
             .map(|source| {
-                target::Target::new(target_dir, &source.path.relative(), extension, codec.clone())
-                    .map(|t| (t, source))
+                // Tracks are named after their titles, next to the file
+                let relative = match source.track {
+                    Some(ref track) => {
+                        source.path.relative().with_file_name(track.file_name(&extension.to_string_lossy()))
+                    }
+                    None => source.path.relative(),
+                };
+                target::Target::plan_path(target_dir, &relative, extension)
+                    .map(|t| (source.path.path.clone(), t))
+                    .map_err(Error::TargetError)
             })
-            .map(|result| {
-                match result {
-                    Ok(s) => Ok(Ok(s)),
-                    Err(target::Error::Exists { path }) => Ok(Err(path)),
+            .collect();
+
+        let planned = try!(planned);
+
+        // Files already in the target directories
+        let targets: Vec<PathBuf> = planned.iter().map(|&(_, ref t)| t.clone()).collect();
+        let on_disk = collision::on_disk(&targets);
+
+        let (paths, collisions) = try!(collision::resolve(&planned, &[], &on_disk, collisions)
+            .map_err(Error::CollisionError));
+
+        let sources = s.into_iter()
+            .zip(paths)
+            .map(|(mut source, path)| {
+                let mut codec = codec.clone();
+                // The first kept audio stream is the one that gets converted
+                if let Some(selection) = rules.select(&source.ffprobe) {
+                    let audio = selection.audio
+                        .first()
+                        .and_then(|&index| source.ffprobe.stream(index))
+                        .and_then(ffprobe::Audio::from_stream);
+                    source.ffprobe.audio = audio;
+                    codec = codec.with_streams(selection);
+                }
+                if let (true, Some(path)) = (covers, cover::find(&source.path.path)) {
+                    codec = codec.with_cover(path);
+                }
+                match target::Target::new(path, codec.for_source(&source.ffprobe)) {
+                    Ok(t) => Ok(Ok((t, source))),
+                    Err(target::Error::Exists { path }) => {
+                        Ok(Err(Skipped {
+                            source: source,
+                            target: path,
+                        }))
+                    }
                     Err(e) => Err(Error::TargetError(e)),
                 }
             });
@@ -107,7 +294,7 @@ impl Conversions {
 
 
 
-        Ok((Conversions(conversions.collect()), skipped.collect()))
+        Ok((Conversions(conversions.collect()), skipped.collect(), collisions))
     }
     pub fn print_table(&self) -> usize {
         use table::print_table;
@@ -124,6 +311,12 @@ impl Conversions {
         fn eta<'a, 'b>(s: &'b Status) -> Cell<'a> {
             match *s {
                 Status::Pending(_) => Empty,
+                Status::Progress(ref p) if s.is_estimate() => {
+                    p.eta().map_or(Empty, |n| {
+                        Text(Right(format!("~{}", pretty_centiseconds((n * 100.).round() as i64))
+                            .into()))
+                    })
+                }
                 Status::Progress(ref p) => p.eta().map_or(Empty, seconds_to_cell),
                 Status::Done(ref p) => seconds_to_cell(p.duration),
                 Status::Fail(ref p) => seconds_to_cell(p.duration),
@@ -169,14 +362,193 @@ impl Conversions {
         print_table(Some(vec!["Num", "Path", "Status", "Eta", ""]), data)
     }
 
-    pub fn convert<F: FnMut(Error)>(mut self, dry_run: bool, mut on_error: F) {
+    /// Prints the final status, the searched CRF, the measured loudness and
+    /// the quality scores of every conversion
+    pub fn print_results<'a>(&'a self, metrics: &[quality::Metric]) -> usize {
+        use table::print_table;
+        use table::Cell::{self, Text, Empty, Integer};
+        use table::Alignment::{Left, Right};
+        use strings::truncate_left;
+        use std::borrow::Cow;
+
+        let show_crf = self.iter().any(|c| c.crf.is_some());
+        let show_loudness = self.iter().any(|c| c.loudness.is_some() || c.gain.is_some());
+        let row = |c: &'a Conversion| -> Vec<Cell<'a>> {
+            let mut row = vec![
+                Integer(Cow::Owned(c.id as i64)),
+                Text(Left(truncate_left(c.target.path.to_string_lossy(), "...", 60))),
+                Text(Left((&c.status).into())),
+            ];
+            if show_crf {
+                row.push(c.crf.map_or(Empty, |n| Integer(Cow::Owned(n as i64))));
+            }
+            if show_loudness {
+                let integrated = c.loudness
+                    .map(|l| l.integrated)
+                    .or(c.gain.map(|g| g.track.integrated));
+                row.push(integrated.map_or(Empty, |i| Text(Right(format!("{:.1}", i).into()))));
+            }
+            for &metric in metrics {
+                row.push(c.scores
+                    .and_then(|s| s.get(metric))
+                    .map_or(Empty, |score| Text(Right(format!("{:.3}", score).into()))));
+            }
+            row
+        };
+
+        let mut header = vec!["Num", "Path", "Status"];
+        if show_crf {
+            header.push("CRF");
+        }
+        if show_loudness {
+            header.push("LUFS");
+        }
+        header.extend(metrics.iter().map(|m| m.name()));
+        header.push("");
+        print_table(Some(header), self.iter().map(row))
+    }
+
+    /// Sets the metadata policy and the templated tags of every conversion.
+    /// `profile` is the `--format` the targets are converted with.
+    pub fn set_metadata(&mut self, options: &metadata::Options, profile: &str) {
+        for c in self.iter_mut() {
+            let tags = options.tags(&metadata::Variables {
+                source: &c.source.path.path,
+                profile: profile,
+            });
+            c.metadata = options.policy;
+            c.tags.extend(tags);
+        }
+    }
+
+    /// Gain tags describe the source as it is measured, so they are refused
+    /// for audio that is encoded louder or quieter, and for formats that
+    /// have no place for them
+    pub fn check_gain_tags(&self) -> Result<(), Error> {
+        for c in self.iter().filter(|c| c.source.ffprobe.audio.is_some()) {
+            let reason = if c.target.codec.changes_loudness() {
+                "the audio is normalized or mixed down"
+            } else if !c.target.codec.holds_gain_tags() {
+                "the target format has no gain tags"
+            } else {
+                continue;
+            };
+            return Err(Error::GainTagsError {
+                conversion: c.clone(),
+                reason: reason,
+            });
+        }
+        Ok(())
+    }
+
+    /// Measures the loudness of every source with audio, and adds gain tags
+    /// with each directory treated as an album, see `album_gains`. Counts as
+    /// one run of ffmpeg in the progress of a conversion.
+    fn analyze_gain<F: FnMut(Error)>(&mut self,
+                                     style: gain::Style,
+                                     partial_albums: &[PathBuf],
+                                     lines: &mut usize,
+                                     on_error: &mut F) {
+        let mut tracks: Vec<Option<gain::Loudness>> = vec![None; self.len()];
+        'analyze_loop: for n in 0..self.len() {
+            let con = self[n].clone();
+            if con.source.ffprobe.audio.is_none() {
+                continue;
+            }
+            self[n].status.start();
+            let runs = ffmpeg::Pass::plan(&con, false).len() + 1;
+            let mut ffmpegiter = match ffmpeg::FFmpegIterator::new(&con, false, ffmpeg::Pass::Gain) {
+                Ok(iter) => iter,
+                Err(e) => {
+                    erase_up(*lines);
+                    *lines = 0;
+                    on_error(Error::FFmpegError {
+                        conversion: con,
+                        error: e,
+                    });
+                    self[n].status.fail();
+                    continue 'analyze_loop;
+                }
+            };
+            for time in &mut ffmpegiter {
+                {
+                    let ref mut c = self[n];
+                    match time {
+                        Ok(t) => {
+                            let progress = t / c.source.ffprobe.duration / runs as f64;
+                            c.status.update(progress * c.source.ffprobe.mpixel());
+                        }
+                        Err(e) => {
+                            erase_up(*lines);
+                            *lines = 0;
+                            on_error(Error::FFmpegError {
+                                conversion: con,
+                                error: e,
+                            });
+                            c.status.fail();
+                            continue 'analyze_loop;
+                        }
+                    }
+                }
+                erase_up(*lines);
+                *lines = self.print_table();
+            }
+            match gain::parse_loudness(&ffmpegiter.stderr()) {
+                Some(loudness) => tracks[n] = Some(loudness),
+                None => {
+                    erase_up(*lines);
+                    *lines = 0;
+                    on_error(Error::LoudnessError { conversion: con });
+                    self[n].status.fail();
+                }
+            }
+        }
+
+        let audio: Vec<usize> = (0..self.len()).filter(|&n| self[n].source.ffprobe.audio.is_some()).collect();
+        let gains = {
+            let measured: Vec<_> = audio.iter()
+                .map(|&n| (self[n].source.path.path.parent(), tracks[n], self[n].source.ffprobe.duration))
+                .collect();
+            album_gains(&measured, partial_albums)
+        };
+        for (n, gain) in audio.into_iter().zip(gains) {
+            if let Some(gain) = gain {
+                let ref mut c = self[n];
+                c.gain_tags = gain::tags(style, &gain);
+                c.gain = Some(gain);
+            }
+        }
+    }
+
+    /// Runs the conversions and returns them with their final status, so the
+    /// results can be reported
+    pub fn convert<F: FnMut(Error)>(mut self,
+                                    dry_run: bool,
+                                    verify: verify::Level,
+                                    formats: &formats::Registry,
+                                    measurer: &quality::Measurer,
+                                    gain: Option<gain::Style>,
+                                    partial_albums: &[PathBuf],
+                                    mut on_error: F)
+                                    -> Conversions {
         let mut lines = 0;
+        if let (false, Some(style)) = (dry_run, gain) {
+            self.analyze_gain(style, partial_albums, &mut lines, &mut on_error);
+        }
         'convert_loop: for n in 0..self.len() {
             // Okay, hope this scope thing is going to be better in the future :)
-            let (local_mpixel, ffmpeg_con): (f64, Conversion) = {
+            let (local_mpixel, mut ffmpeg_con): (f64, Conversion) = {
                 let ref mut c = self[n];
                 (c.source.ffprobe.mpixel(), c.clone())
             };
+            // Failed while analyzing
+            if let Status::Fail(_) = ffmpeg_con.status {
+                continue 'convert_loop;
+            }
+            let analyzed = match ffmpeg_con.gain {
+                Some(_) => 1,
+                None => 0,
+            };
 
             if !dry_run {
                 match ffmpeg_con.target.mkdir_parent() {
@@ -204,47 +576,156 @@ impl Conversions {
                 Ok(true) | Ok(false) => (),
             }
 
-            let ffmpegiter = match ffmpeg::FFmpegIterator::new(&ffmpeg_con, dry_run) {
-                Ok(iter) => iter,
-                Err(e) => {
+            if let (false, Some(target)) = (dry_run, ffmpeg_con.target.codec.crf_target()) {
+                let ref mut c = self[n];
+                c.status.start();
+                erase_up(lines);
+                lines = 0;
+                println!("Searching CRF for {}", c.source.path.relative().to_string_lossy());
+                let searched = crf::search(&c.target.codec,
+                                           target,
+                                           measurer.options.subsample,
+                                           &c.source.path.path,
+                                           &c.source.ffprobe,
+                                           &c.target.path_tmp);
+                erase_up(1);
+                match searched {
+                    Ok(Some(crf)) => {
+                        match crf {
+                            crf::Crf::Reaches(n) => {
+                                println!("CRF {} reaches {} {} for {}",
+                                         n,
+                                         target.0.name(),
+                                         target.1,
+                                         c.source.path.relative().to_string_lossy())
+                            }
+                            crf::Crf::Misses(n) => {
+                                println!("No CRF reaches {} {} for {}, using CRF {}",
+                                         target.0.name(),
+                                         target.1,
+                                         c.source.path.relative().to_string_lossy(),
+                                         n)
+                            }
+                        }
+                        c.target.codec = c.target.codec.with_crf(crf.value());
+                        c.crf = Some(crf.value());
+                    }
+                    Ok(None) => (),
+                    Err(e) => {
+                        on_error(Error::CrfSearchError {
+                            conversion: ffmpeg_con,
+                            error: e,
+                        });
+                        c.status.fail();
+                        continue 'convert_loop;
+                    }
+                }
+                ffmpeg_con = c.clone();
+            }
+
+            let passes = ffmpeg::Pass::plan(&ffmpeg_con, dry_run);
+            for (pass_index, &pass) in passes.iter().enumerate() {
+                let mut ffmpegiter = match ffmpeg::FFmpegIterator::new(&ffmpeg_con, dry_run, pass) {
+                    Ok(iter) => iter,
+                    Err(e) => {
+                        erase_up(lines);
+                        lines = 0;
+                        ffmpeg_con.target.remove_passlogs();
+                        on_error(Error::FFmpegError {
+                            conversion: ffmpeg_con,
+                            error: e,
+                        });
+                        let ref mut c = self[n];
+                        c.status.fail();
+                        continue 'convert_loop;
+                    }
+                };
+
+                for time in &mut ffmpegiter {
+                    {
+                        let ref mut c = self[n];
+                        let time = match time {
+                            Ok(t) => t,
+                            Err(e) => {
+                                erase_up(lines);
+                                lines = 0;
+                                ffmpeg_con.target.remove_passlogs();
+                                on_error(Error::FFmpegError {
+                                    conversion: ffmpeg_con,
+                                    error: e,
+                                });
+                                c.status.fail();
+                                continue 'convert_loop;
+                            }
+                        };
+                        // Every run of ffmpeg takes an equal share of the progress
+                        let runs = (passes.len() + analyzed) as f64;
+                        let pass_progress = (pass_index + analyzed) as f64 +
+                                            time / c.source.ffprobe.duration;
+                        let local_progress = pass_progress / runs * local_mpixel;
+                        c.status.update(local_progress);
+                    }
+
                     erase_up(lines);
-                    lines = 0;
-                    on_error(Error::FFmpegError {
-                        conversion: ffmpeg_con,
-                        error: e,
-                    });
-                    let ref mut c = self[n];
-                    c.status.fail();
-                    continue 'convert_loop;
+                    lines = self.print_table();
                 }
-            };
 
-            for time in ffmpegiter {
-                {
+                if pass == ffmpeg::Pass::Loudness {
                     let ref mut c = self[n];
-                    let time = match time {
-                        Ok(t) => t,
-                        Err(e) => {
+                    match loudnorm::parse_measurement(&ffmpegiter.stderr()) {
+                        Some(measured) => {
+                            c.target.codec = c.target.codec.with_loudness(measured);
+                            c.loudness = Some(measured);
+                            ffmpeg_con = c.clone();
+                        }
+                        None => {
                             erase_up(lines);
                             lines = 0;
-                            on_error(Error::FFmpegError {
-                                conversion: ffmpeg_con,
-                                error: e,
-                            });
+                            on_error(Error::LoudnessError { conversion: ffmpeg_con });
                             c.status.fail();
                             continue 'convert_loop;
                         }
-                    };
-                    let local_progress = time / c.source.ffprobe.duration * local_mpixel;
-                    c.status.update(local_progress);
+                    }
                 }
-
-                erase_up(lines);
-                lines = self.print_table();
             }
+            ffmpeg_con.target.remove_passlogs();
             {
                 let ref mut c = self[n];
                 if !dry_run {
+                    if let Err(e) = verify::verify(verify,
+                                                   &c.source.ffprobe,
+                                                   c.target.codec.planned_streams(),
+                                                   &c.target.path_tmp,
+                                                   formats) {
+                        erase_up(lines);
+                        lines = 0;
+                        on_error(Error::VerifyError {
+                            conversion: ffmpeg_con,
+                            error: e,
+                        });
+                        c.status.fail();
+                        continue 'convert_loop;
+                    }
+                    let range = c.source.track.as_ref().map(|t| (t.start, c.source.ffprobe.duration));
+                    let measured = measurer.measure_range(&c.source.path.path,
+                                                          range,
+                                                          &c.source.ffprobe,
+                                                          c.target.codec.frame_rate(),
+                                                          &c.target.path_tmp);
+                    let checked = measured.and_then(|scores| {
+                        c.scores = scores;
+                        scores.map_or(Ok(()), |s| measurer.options.check(&s))
+                    });
+                    if let Err(e) = checked {
+                        erase_up(lines);
+                        lines = 0;
+                        on_error(Error::QualityError {
+                            conversion: ffmpeg_con,
+                            error: e,
+                        });
+                        c.status.fail();
+                        continue 'convert_loop;
+                    }
                     match ffmpeg_con.target.rename_path_tmp() {
                         Err(e) => {
                             erase_up(lines);
@@ -264,6 +745,7 @@ impl Conversions {
         erase_up(lines);
         self.print_table();
         print!("\n");
+        self
     }
 }
 
@@ -299,3 +781,22 @@ impl DerefMut for Conversions {
 
 //     (longest).into_iter().map(|&x| x).collect()
 // }
+
+#[test]
+fn test_album_gains() {
+    let loudness = |integrated| Some(gain::Loudness { integrated: integrated, peak: -1. });
+    let (a, b) = (Path::new("/music/a"), Path::new("/music/b"));
+    let tracks = vec![(Some(a), loudness(-20.), 100.),
+                      (Some(a), loudness(-20.), 100.),
+                      (Some(b), loudness(-20.), 100.),
+                      (Some(b), None, 100.)];
+    let gains = album_gains(&tracks, &[]);
+    assert_eq!(gains[0].and_then(|g| g.album), loudness(-20.));
+    // An album with a track that could not be measured gets no album gain
+    assert_eq!(gains[2].map(|g| (g.track, g.album)), Some((loudness(-20.).unwrap(), None)));
+    assert_eq!(gains[3], None);
+
+    // Nor does one with tracks left out of the run
+    let gains = album_gains(&tracks[..2], &[PathBuf::from("/music/a")]);
+    assert!(gains.iter().all(|g| g.map_or(false, |g| g.album.is_none())));
+}
@@ -0,0 +1,112 @@
+use ffprobe::FFProbe;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+// In order of preference
+const NAMES: &'static [&'static str] = &["cover", "folder", "poster"];
+const EXTENSIONS: &'static [&'static str] = &["jpg", "jpeg", "png"];
+
+/// Finds a cover image next to `source`, like `cover.jpg` or `poster.png`.
+/// Names are matched without regard to case.
+pub fn find(source: &Path) -> Option<PathBuf> {
+    let dir = match source.parent() {
+        Some(d) => d,
+        None => return None,
+    };
+    let mut images: Vec<(usize, PathBuf)> = match dir.read_dir() {
+        Ok(entries) => {
+            entries.filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.is_file())
+                .filter_map(|p| rank(&p).map(|r| (r, p)))
+                .collect()
+        }
+        Err(_) => return None,
+    };
+    images.sort();
+    images.into_iter().next().map(|(_, p)| p)
+}
+
+fn rank(path: &Path) -> Option<usize> {
+    let lower = |s: Option<&OsStr>| s.and_then(|s| s.to_str()).map(|s| s.to_lowercase());
+    match (lower(path.file_stem()), lower(path.extension())) {
+        (Some(stem), Some(extension)) => {
+            match EXTENSIONS.contains(&extension.as_str()) {
+                true => NAMES.iter().position(|&n| n == stem),
+                false => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Whether the source carries a cover itself, as an attached picture or as
+/// a Matroska attachment named like one
+pub fn is_embedded(probe: &FFProbe) -> bool {
+    probe.streams.iter().any(|s| {
+        s.disposition.attached_pic ||
+        s.codec_type() == "attachment" && s.tags.get("filename").map_or(false, |f| rank(Path::new(f)).is_some())
+    })
+}
+
+/// The mime type of a cover found by `find`
+pub fn mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()) {
+        Some(ref e) if e == "png" => "image/png",
+        _ => "image/jpeg",
+    }
+}
+
+/// The name of the attachment in Matroska, which players look for
+pub fn attachment_name(path: &Path) -> &'static str {
+    match mime_type(path) {
+        "image/png" => "cover.png",
+        _ => "cover.jpg",
+    }
+}
+
+/// Adds the cover as the `n`th attachment of a Matroska output
+pub fn attach_args(path: &Path, n: usize) -> Vec<OsString> {
+    vec!["-attach".into(),
+         path.into(),
+         format!("-metadata:s:t:{}", n).into(),
+         format!("mimetype={}", mime_type(path)).into(),
+         format!("-metadata:s:t:{}", n).into(),
+         format!("filename={}", attachment_name(path)).into()]
+}
+
+/// Maps the picture at `input`, like `1:0` for a cover read as the second
+/// input, as the `n`th video stream of the output, which players show as
+/// the picture of the file
+pub fn picture_args(input: &str, n: usize) -> Vec<OsString> {
+    vec!["-map".into(),
+         input.into(),
+         format!("-c:v:{}", n).into(),
+         "copy".into(),
+         format!("-disposition:v:{}", n).into(),
+         "attached_pic".into()]
+}
+
+#[test]
+fn test_find() {
+    use std::fs::{File, create_dir, remove_dir_all, remove_file};
+
+    let root = ::std::env::temp_dir().join(format!("transcode-cover-{}", ::std::process::id()));
+    let _ = remove_dir_all(&root);
+    create_dir(&root).unwrap();
+    for name in &["movie.mkv", "back.jpg", "cover.txt", "poster.png", "Folder.JPG"] {
+        File::create(root.join(name)).unwrap();
+    }
+    create_dir(root.join("cover.jpg")).unwrap();
+
+    let source = root.join("movie.mkv");
+    assert_eq!(find(&source), Some(root.join("Folder.JPG")));
+    assert_eq!(attachment_name(&root.join("Folder.JPG")), "cover.jpg");
+    remove_file(root.join("Folder.JPG")).unwrap();
+    assert_eq!(find(&source), Some(root.join("poster.png")));
+    assert_eq!(attachment_name(&root.join("poster.png")), "cover.png");
+    remove_file(root.join("poster.png")).unwrap();
+    assert_eq!(find(&source), None);
+
+    remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,242 @@
+use codecs::Codec as CodecTrait;
+use codecs::container::Codec;
+use ffprobe::FFProbe;
+use quality::{self, Measurer, Metric};
+use std::error::Error as StdError;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// Samples are taken evenly spread over the file, so intros and credits are
+// mostly left out
+const SAMPLE_COUNT: u64 = 3;
+const SAMPLE_SECONDS: f64 = 5.;
+// Every file is encoded at these CRFs, the chosen CRF is interpolated
+// between them
+const CANDIDATES: &'static [u64] = &[18, 24, 30, 36];
+
+#[derive(Debug)]
+pub enum Error {
+    SpawnError(io::Error),
+    EncodeError {
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+    VmafUnavailable,
+    QualityError(quality::Error),
+    /// The measurement of a sample gave no score for the metric
+    MissingScore(Metric),
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::SpawnError(_) => "Could not spawn ffmpeg to encode a sample",
+            Error::EncodeError { .. } => "Could not encode a sample",
+            Error::VmafUnavailable => "Cannot target VMAF, ffmpeg is built without libvmaf",
+            Error::QualityError(_) => "Could not measure the quality of a sample",
+            Error::MissingScore(_) => "The quality measurement gave no score for a sample",
+        }
+    }
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::SpawnError(ref e) => Some(e),
+            Error::QualityError(ref e) => Some(e),
+            Error::EncodeError { .. } |
+            Error::VmafUnavailable |
+            Error::MissingScore(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::EncodeError { ref stderr, .. } => {
+                write!(f, "{}\nStdErr:\n{}", self.description(), stderr.trim())
+            }
+            Error::MissingScore(metric) => write!(f, "{}: {}", self.description(), metric.name()),
+            Error::SpawnError(_) |
+            Error::VmafUnavailable |
+            Error::QualityError(_) => write!(f, "{}", self.description()),
+        }
+    }
+}
+
+/// The outcome of a search
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Crf {
+    /// The highest CRF whose samples reach the target
+    Reaches(u64),
+    /// Not even the lowest candidate reaches the target, so it is used
+    Misses(u64),
+}
+
+impl Crf {
+    pub fn value(&self) -> u64 {
+        match *self {
+            Crf::Reaches(crf) | Crf::Misses(crf) => crf,
+        }
+    }
+}
+
+/// Finds the highest CRF for which samples of `source` reach `target`.
+/// Returns `None` for sources without video. Samples are written next to
+/// `tmp` and removed again.
+pub fn search(codec: &Codec,
+              target: (Metric, f64),
+              subsample: u64,
+              source: &Path,
+              probe: &FFProbe,
+              tmp: &Path)
+              -> Result<Option<Crf>, Error> {
+    let (metric, score) = target;
+    if probe.video.is_none() {
+        return Ok(None);
+    }
+
+    let measurer = Measurer::new(quality::Options {
+        metrics: vec![metric],
+        subsample: subsample,
+        ..quality::Options::default()
+    });
+    if !measurer.is_enabled() {
+        return Err(Error::VmafUnavailable);
+    }
+
+    let samples = samples(probe.duration);
+    let mut points = Vec::new();
+    for &crf in CANDIDATES {
+        let codec = codec.with_crf(crf);
+        let mut total = 0.;
+        for (n, &range) in samples.iter().enumerate() {
+            let path = sample_path(tmp, crf, n);
+            let measured = encode(&codec, source, range, &path).and_then(|_| {
+                measurer.measure_range(source, Some(range), probe, codec.frame_rate(), &path)
+                    .map_err(Error::QualityError)
+            });
+            let _ = fs::remove_file(&path);
+            total += match try!(measured).and_then(|s| s.get(metric)) {
+                Some(score) => score,
+                None => return Err(Error::MissingScore(metric)),
+            };
+        }
+        points.push((crf, total / samples.len() as f64));
+    }
+    Ok(Some(match interpolate(&points, score) {
+        Some(crf) => Crf::Reaches(crf),
+        None => Crf::Misses(CANDIDATES[0]),
+    }))
+}
+
+/// `(start, length)` of the samples in seconds
+fn samples(duration: f64) -> Vec<(f64, f64)> {
+    if duration <= SAMPLE_SECONDS * SAMPLE_COUNT as f64 {
+        return vec![(0., duration)];
+    }
+    (1..SAMPLE_COUNT + 1)
+        .map(|n| {
+            let center = duration * n as f64 / (SAMPLE_COUNT + 1) as f64;
+            (center - SAMPLE_SECONDS / 2., SAMPLE_SECONDS)
+        })
+        .collect()
+}
+
+fn sample_path(tmp: &Path, crf: u64, n: usize) -> PathBuf {
+    tmp.with_extension(format!("crf{}-{}.mkv", crf, n))
+}
+
+/// The arguments that encode the sample `range` of `source` to `path`. The
+/// inputs of the codec, like a cover, follow the source, since its
+/// arguments map them.
+fn sample_args(codec: &Codec, source: &Path, range: (f64, f64), path: &Path) -> Vec<OsString> {
+    ["-nostdin", "-hide_banner", "-v", "error"].iter().map(OsString::from)
+        .chain(quality::range_args(Some(range)).into_iter().map(OsString::from))
+        .chain(vec!["-i".into(), source.into()])
+        .chain(codec.input_args())
+        .chain(codec.to_ffmpeg_args())
+        .chain(["-an", "-sn", "-dn", "-y"].iter().map(OsString::from))
+        .chain(Some(path.into()))
+        .collect()
+}
+
+fn encode(codec: &Codec, source: &Path, range: (f64, f64), path: &Path) -> Result<(), Error> {
+    let output = try!(Command::new("ffmpeg")
+        .args(&sample_args(codec, source, range, path))
+        .stdin(Stdio::null())
+        .output()
+        .map_err(Error::SpawnError));
+
+    match output.status.success() {
+        true => Ok(()),
+        false => {
+            Err(Error::EncodeError {
+                exit_code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            })
+        }
+    }
+}
+
+/// Picks the highest CRF whose score is at least `target`, interpolating
+/// linearly between the measured `(crf, score)` points. `None` if no point
+/// reaches the target.
+fn interpolate(points: &[(u64, f64)], target: f64) -> Option<u64> {
+    let mut points = points.to_vec();
+    points.sort_by_key(|&(crf, _)| crf);
+
+    let mut best = match points.first() {
+        Some(&(crf, score)) if score >= target => crf,
+        _ => return None,
+    };
+    for pair in points.windows(2) {
+        let ((crf_a, score_a), (crf_b, score_b)) = (pair[0], pair[1]);
+        if score_a < target {
+            break;
+        }
+        if score_b >= target {
+            best = crf_b;
+            continue;
+        }
+        let ratio = (score_a - target) / (score_a - score_b);
+        best = crf_a + (ratio * (crf_b - crf_a) as f64).floor() as u64;
+        break;
+    }
+    Some(best)
+}
+
+#[test]
+fn test_interpolate() {
+    let points = [(30, 88.), (18, 97.), (24, 94.), (36, 80.)];
+    assert_eq!(interpolate(&points, 95.), Some(22));
+    assert_eq!(interpolate(&points, 93.), Some(25));
+    assert_eq!(interpolate(&points, 91.), Some(27));
+    assert_eq!(interpolate(&points, 70.), Some(36));
+    assert_eq!(interpolate(&points, 97.), Some(18));
+    assert_eq!(interpolate(&points, 99.), None);
+}
+
+#[test]
+fn test_sample_args() {
+    let args = vec!["mp4", "h264", "vmaf=95", "medium", "aac", "160"];
+    let (codec, _) = Codec::from_args(args.into_iter().peekable()).unwrap();
+    let codec = codec.with_cover(PathBuf::from("cover.jpg")).with_crf(24);
+    // Short sources are encoded whole
+    assert_eq!(samples(10.), vec![(0., 10.)]);
+    let ranges = samples(400.);
+    assert_eq!(ranges, vec![(97.5, 5.), (197.5, 5.), (297.5, 5.)]);
+    let args: Vec<String> = sample_args(&codec, Path::new("a.mkv"), ranges[0], Path::new("a.crf24-0.mkv"))
+        .into_iter()
+        .map(|a| a.into_string().unwrap())
+        .collect();
+    let args = args.join(" ");
+    // The cover is mapped as `1:0`, so it has to be the second input
+    assert!(args.starts_with("-nostdin -hide_banner -v error -ss 97.500 -t 5.000 -i a.mkv -i cover.jpg "),
+            "{}",
+            args);
+    assert!(args.contains("-map 1:0"), "{}", args);
+    assert!(args.ends_with("-an -sn -dn -y a.crf24-0.mkv"), "{}", args);
+}
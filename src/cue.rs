@@ -0,0 +1,333 @@
+use std::error::Error as StdError;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+// Times in a sheet count frames of audio CDs
+const FRAMES_PER_SECOND: f64 = 75.;
+
+#[derive(Debug)]
+pub enum Error {
+    Read(io::Error),
+    Parse { line: usize, reason: &'static str },
+    Invalid(&'static str),
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Read(_) => "Could not read CUE sheet",
+            Error::Parse { .. } |
+            Error::Invalid(_) => "Could not parse CUE sheet",
+        }
+    }
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::Read(ref error) => Some(error),
+            Error::Parse { .. } |
+            Error::Invalid(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Read(ref error) => write!(f, "{}: {}", self.description(), error),
+            Error::Parse { line, reason } => write!(f, "{}: line {}: {}", self.description(), line, reason),
+            Error::Invalid(reason) => write!(f, "{}: {}", self.description(), reason),
+        }
+    }
+}
+
+/// A track as it is written in the sheet
+#[derive(Debug, Clone, Default)]
+struct Entry {
+    number: u64,
+    title: Option<String>,
+    performer: Option<String>,
+    /// `INDEX 01`, where the track starts
+    start: Option<f64>,
+    /// The file that holds `INDEX 01`, which is not the file the track is
+    /// listed under when its pregap ends the file before
+    file: usize,
+}
+
+/// A track of a sheet, cut from the file it starts in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    pub number: u64,
+    /// The number of tracks in the sheet
+    pub total: u64,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub album: Option<String>,
+    pub album_performer: Option<String>,
+    pub date: Option<String>,
+    pub genre: Option<String>,
+    /// In seconds from the start of the file
+    pub start: f64,
+    /// `None` for the last track of a file
+    pub end: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sheet {
+    title: Option<String>,
+    performer: Option<String>,
+    date: Option<String>,
+    genre: Option<String>,
+    files: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+/// Splits a line into words, keeping quoted strings together
+fn words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(word.clone());
+                    word.clear();
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Parses `MM:SS:FF`, in minutes, seconds and frames
+fn parse_time(s: &str) -> Option<f64> {
+    let parts: Vec<u64> = match s.split(':').map(|p| p.parse().ok()).collect() {
+        Some(parts) => parts,
+        None => return None,
+    };
+    match parts.len() == 3 && parts[1] < 60 && parts[2] < 75 {
+        true => Some((parts[0] * 60 + parts[1]) as f64 + parts[2] as f64 / FRAMES_PER_SECOND),
+        false => None,
+    }
+}
+
+/// Sheets are written by CD rippers that predate UTF-8, so anything else
+/// is read as Latin-1
+fn decode(bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(s) => s.trim_start_matches('\u{feff}').to_string(),
+        Err(e) => e.into_bytes().into_iter().map(|b| b as char).collect(),
+    }
+}
+
+impl Sheet {
+    pub fn load(path: &Path) -> Result<Sheet, Error> {
+        let mut bytes = Vec::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_end(&mut bytes)).map_err(Error::Read));
+        Sheet::parse(&decode(bytes))
+    }
+
+    pub fn parse(text: &str) -> Result<Sheet, Error> {
+        let mut sheet = Sheet::default();
+        for (n, line) in text.lines().enumerate() {
+            let fail = |reason| Err(Error::Parse { line: n + 1, reason: reason });
+            let words = words(line);
+            let value = words.get(1).cloned();
+            let command = match words.first() {
+                Some(c) => c.to_uppercase(),
+                None => continue,
+            };
+            let file = sheet.files.len().checked_sub(1);
+            let entry = sheet.entries.last_mut();
+            match (command.as_str(), entry) {
+                ("FILE", _) => {
+                    match value {
+                        Some(name) => sheet.files.push(name),
+                        None => return fail("FILE without a name"),
+                    }
+                }
+                ("TRACK", _) => {
+                    let number = match value.and_then(|v| v.parse().ok()) {
+                        Some(number) => number,
+                        None => return fail("TRACK without a number"),
+                    };
+                    match file {
+                        Some(_) => sheet.entries.push(Entry { number: number, ..Entry::default() }),
+                        None => return fail("TRACK before FILE"),
+                    }
+                }
+                ("INDEX", Some(entry)) => {
+                    let time = words.get(2).and_then(|t| parse_time(t));
+                    match (value.as_ref().map(|v| v.as_str()), time) {
+                        (Some("01"), Some(time)) | (Some("1"), Some(time)) => {
+                            entry.start = Some(time);
+                            entry.file = file.unwrap_or(0);
+                        }
+                        // Other indexes mark pregaps and points within the
+                        // track. Pregaps stay at the end of the track before.
+                        (Some(_), Some(_)) => (),
+                        _ => return fail("INDEX without a number and a time"),
+                    }
+                }
+                ("INDEX", None) => return fail("INDEX before TRACK"),
+                ("TITLE", Some(entry)) => entry.title = value,
+                ("PERFORMER", Some(entry)) => entry.performer = value,
+                ("TITLE", None) => sheet.title = value,
+                ("PERFORMER", None) => sheet.performer = value,
+                ("REM", None) => {
+                    let rest = words.get(2).cloned();
+                    match value.map(|v| v.to_uppercase()) {
+                        Some(ref v) if v == "DATE" => sheet.date = rest,
+                        Some(ref v) if v == "GENRE" => sheet.genre = rest,
+                        _ => (),
+                    }
+                }
+                // PREGAP and POSTGAP are silence that is not in the file
+                _ => (),
+            }
+        }
+        if sheet.entries.is_empty() {
+            return Err(Error::Invalid("No tracks"));
+        }
+        if sheet.entries.iter().any(|e| e.start.is_none()) {
+            return Err(Error::Invalid("A track has no INDEX 01"));
+        }
+        Ok(sheet)
+    }
+
+    /// The tracks of the sheet, each with the name of the file it starts in
+    pub fn tracks(&self) -> Vec<(String, Track)> {
+        let total = self.entries.len() as u64;
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(n, entry)| {
+                let track = Track {
+                    number: entry.number,
+                    total: total,
+                    title: entry.title.clone(),
+                    performer: entry.performer.clone().or(self.performer.clone()),
+                    album: self.title.clone(),
+                    album_performer: self.performer.clone(),
+                    date: self.date.clone(),
+                    genre: self.genre.clone(),
+                    start: entry.start.unwrap_or(0.),
+                    // A track that starts in another file leaves the rest
+                    // of this one, with its pregap, to this track
+                    end: self.entries.get(n + 1).filter(|e| e.file == entry.file).and_then(|e| e.start),
+                };
+                (self.files[entry.file].clone(), track)
+            })
+            .collect()
+    }
+}
+
+impl Track {
+    /// The length of the track in a file that is `duration` seconds long
+    pub fn duration(&self, duration: f64) -> f64 {
+        self.end.unwrap_or(duration) - self.start
+    }
+    /// Arguments that cut the track out of the input that follows them
+    pub fn input_args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["-ss".into(), format!("{:.3}", self.start).into()];
+        if let Some(end) = self.end {
+            args.extend(vec!["-t".into(), format!("{:.3}", end - self.start).into()]);
+        }
+        args
+    }
+    /// Like `03 - Title.flac`, without characters that are not allowed in
+    /// file names
+    pub fn file_name(&self, extension: &str) -> String {
+        let title = self.title.clone().unwrap_or_else(|| format!("Track {}", self.number));
+        let title: String = title.chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c => c,
+            })
+            .collect();
+        format!("{:02} - {}.{}", self.number, title.trim().trim_end_matches('.'), extension)
+    }
+    pub fn tags(&self) -> Vec<(String, String)> {
+        let tags = vec![("title", self.title.clone()),
+                        ("artist", self.performer.clone()),
+                        ("album_artist", self.album_performer.clone()),
+                        ("album", self.album.clone()),
+                        ("track", Some(format!("{}/{}", self.number, self.total))),
+                        ("date", self.date.clone()),
+                        ("genre", self.genre.clone())];
+        tags.into_iter()
+            .filter_map(|(key, value)| value.map(|v| (key.to_string(), v)))
+            .collect()
+    }
+}
+
+#[test]
+fn test_parse() {
+    let text = "\u{feff}REM GENRE Rock\r\n\
+                REM DATE 1994\r\n\
+                PERFORMER \"Low\"\r\n\
+                TITLE \"Songs: Live\"\r\n\
+                FILE \"Disc 1.wav\" WAVE\r\n  \
+                  TRACK 01 AUDIO\r\n    \
+                    TITLE \"Words\"\r\n    \
+                    INDEX 01 00:00:00\r\n  \
+                  TRACK 02 AUDIO\r\n    \
+                    TITLE \"Lazy/Slow\"\r\n    \
+                    PERFORMER \"Low & Friends\"\r\n    \
+                    INDEX 00 04:10:00\r\n    \
+                    INDEX 01 04:12:00\r\n\
+                FILE \"Disc 2.wav\" WAVE\r\n  \
+                  TRACK 03 AUDIO\r\n    \
+                    PREGAP 00:02:00\r\n    \
+                    INDEX 01 00:00:00\r\n";
+    let tracks = Sheet::parse(&decode(text.as_bytes().to_vec())).unwrap().tracks();
+    assert_eq!(tracks.len(), 3);
+    let (ref file, ref first) = tracks[0];
+    assert_eq!(file, "Disc 1.wav");
+    assert_eq!((first.start, first.end), (0., Some(252.)));
+    assert_eq!(first.tags(),
+               vec![("title".to_string(), "Words".to_string()),
+                    ("artist".to_string(), "Low".to_string()),
+                    ("album_artist".to_string(), "Low".to_string()),
+                    ("album".to_string(), "Songs: Live".to_string()),
+                    ("track".to_string(), "1/3".to_string()),
+                    ("date".to_string(), "1994".to_string()),
+                    ("genre".to_string(), "Rock".to_string())]);
+    let (_, ref second) = tracks[1];
+    assert_eq!(second.end, None);
+    assert_eq!(second.duration(300.), 48.);
+    assert_eq!(second.file_name("opus"), "02 - Lazy_Slow.opus");
+    assert_eq!(tracks[2].0, "Disc 2.wav");
+    assert_eq!(tracks[2].1.file_name("mka"), "03 - Track 3.mka");
+
+    // Rippers that append pregaps to the file before list the next track
+    // under that file
+    let text = "FILE \"01.flac\" WAVE\n\
+                TRACK 01 AUDIO\n\
+                INDEX 01 00:00:00\n\
+                TRACK 02 AUDIO\n\
+                TITLE \"Two\"\n\
+                INDEX 00 03:58:00\n\
+                FILE \"02.flac\" WAVE\n\
+                INDEX 01 00:00:00\n\
+                TRACK 03 AUDIO\n\
+                INDEX 01 02:30:00\n";
+    let tracks = Sheet::parse(text).unwrap().tracks();
+    let files: Vec<&str> = tracks.iter().map(|&(ref f, _)| f.as_str()).collect();
+    assert_eq!(files, vec!["01.flac", "02.flac", "02.flac"]);
+    assert_eq!((tracks[0].1.start, tracks[0].1.end), (0., None));
+    assert_eq!((tracks[1].1.start, tracks[1].1.end), (0., Some(150.)));
+    assert_eq!(tracks[1].1.title, Some("Two".to_string()));
+
+    assert!(Sheet::parse("TRACK 01 AUDIO\n").is_err());
+    assert!(Sheet::parse("FILE \"a.flac\" WAVE\nTRACK 01 AUDIO\nINDEX 00 00:00:00\n").is_err());
+    assert_eq!(parse_time("01:02:75"), None);
+    assert_eq!(parse_time("01:02:15"), Some(62.2));
+}
@@ -3,6 +3,10 @@ use ffmpeg;
 use conversion;
 use source;
 use codecs;
+use companion;
+use formats;
+use probe_cache;
+use report;
 
 use std::error::Error as StdError;
 use std::fmt;
@@ -16,6 +20,10 @@ pub enum Error {
     NoSourcesError,
     AtLeastOneItemFailed,
     FormatError(codecs::Error),
+    CompanionError(companion::Error),
+    ProbeCacheError(probe_cache::Error),
+    FormatRegistryError(formats::Error),
+    ReportError(report::Error),
 }
 
 impl From<ffmpeg::Error> for Error {
@@ -34,6 +42,26 @@ impl From<source::Error> for Error {
         Error::SourceError(err)
     }
 }
+impl From<companion::Error> for Error {
+    fn from(err: companion::Error) -> Self {
+        Error::CompanionError(err)
+    }
+}
+impl From<probe_cache::Error> for Error {
+    fn from(err: probe_cache::Error) -> Self {
+        Error::ProbeCacheError(err)
+    }
+}
+impl From<formats::Error> for Error {
+    fn from(err: formats::Error) -> Self {
+        Error::FormatRegistryError(err)
+    }
+}
+impl From<report::Error> for Error {
+    fn from(err: report::Error) -> Self {
+        Error::ReportError(err)
+    }
+}
 impl From<conversion::Error> for Error {
     fn from(err: conversion::Error) -> Self {
         Error::ConversionError(err)
@@ -50,6 +78,10 @@ impl StdError for Error {
             Error::NoSourcesError => "No sources were found",
             Error::AtLeastOneItemFailed => "Once item failed to convert",
             Error::FormatError(_) => "An error happened while parsing --format",
+            Error::CompanionError(_) => "Companion file error",
+            Error::ProbeCacheError(_) => "Probe cache error",
+            Error::FormatRegistryError(_) => "An error happened while reading --format-registry",
+            Error::ReportError(_) => "Report error",
         }
     }
 
@@ -62,6 +94,10 @@ impl StdError for Error {
             Error::NoSourcesError => None,
             Error::AtLeastOneItemFailed => None,
             Error::FormatError(ref e) => Some(e),
+            Error::CompanionError(ref e) => Some(e),
+            Error::ProbeCacheError(ref e) => Some(e),
+            Error::FormatRegistryError(ref e) => Some(e),
+            Error::ReportError(ref e) => Some(e),
         }
     }
 }
@@ -84,7 +120,8 @@ pub fn print_arg_error(e: &args::Error) {
     use args::Error::*;
     match *e {
         MissingTargetDir { ref program_name } |
-        MissingSourceDir { ref program_name } => {
+        MissingSourceDir { ref program_name } |
+        InvalidValue { ref program_name, .. } => {
             println!("Error: {}.", e);
             println!("");
             args::print_usage(program_name);
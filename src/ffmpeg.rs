@@ -1,5 +1,6 @@
 use codecs::Codec;
 use conversion;
+use gain;
 use regexreader::RegexReadIterator;
 use std::error::Error as StdError;
 use std::ffi::OsString;
@@ -14,6 +15,11 @@ pub enum Error {
     SpawnError(io::Error),
     NoStderr,
     OutputError { stdout: String, stderr: String },
+    WaitError(io::Error),
+    Unsuccessful {
+        exit_code: Option<i32>,
+        stderr: String,
+    },
 }
 
 impl StdError for Error {
@@ -26,6 +32,8 @@ impl StdError for Error {
             Error::SpawnError(_) => "Could not spawn ffmpeg",
             Error::NoStderr => "There was no stderr in ffmpeg command for some reason",
             Error::OutputError { .. } => "FFmpeg outputted something unexpected",
+            Error::WaitError(_) => "Could not wait for ffmpeg to exit",
+            Error::Unsuccessful { .. } => "FFmpeg exited with a non-zero exit code",
         }
     }
 
@@ -33,8 +41,10 @@ impl StdError for Error {
         match *self {
             Error::MkDirError(ref s) => Some(s),
             Error::SpawnError(ref s) => Some(s),
+            Error::WaitError(ref s) => Some(s),
             Error::NoStderr => None,
             Error::OutputError { .. } => None,
+            Error::Unsuccessful { .. } => None,
         }
     }
 }
@@ -44,7 +54,16 @@ impl fmt::Display for Error {
         match *self {
             Error::MkDirError(_) |
             Error::SpawnError(_) |
+            Error::WaitError(_) |
             Error::NoStderr => write!(f, "{}", self.description()),
+            Error::Unsuccessful { exit_code, ref stderr } => {
+                let exit_code = exit_code.map_or("None".to_string(), |c| c.to_string());
+                write!(f,
+                       "{}\nExit Code: {}\nStdErr:\n{}",
+                       self.description(),
+                       exit_code,
+                       stderr)
+            }
             Error::OutputError { ref stdout, ref stderr } => {
                 write!(f,
                        "{}\nStdOut:\n{}StdErr:\n{}",
@@ -56,24 +75,108 @@ impl fmt::Display for Error {
     }
 }
 
+/// One run of ffmpeg over the source. Two-pass encodes run `First`, which
+/// only writes the statistics, and then `Second`. `Loudness` measures the
+/// audio for two-pass loudness normalization, `Gain` for gain tags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    Gain,
+    Loudness,
+    Only,
+    First,
+    Second,
+}
+
+impl Pass {
+    /// The runs needed to convert `con`. Dry runs only run the encode.
+    pub fn plan(con: &conversion::Conversion, dry_run: bool) -> Vec<Pass> {
+        let codec = &con.target.codec;
+        if dry_run {
+            return vec![Pass::Only];
+        }
+        let mut passes = Vec::new();
+        let measure = codec.loudnorm().map_or(false, |l| l.needs_measurement());
+        if measure && con.source.ffprobe.audio.is_some() {
+            passes.push(Pass::Loudness);
+        }
+        match codec.passes() {
+            2 => passes.extend(&[Pass::First, Pass::Second]),
+            _ => passes.push(Pass::Only),
+        }
+        passes
+    }
+}
+
 pub struct FFmpegIterator {
     process: process::Child,
     timeiter: TimeIterator<process::ChildStderr>,
     stdout: process::ChildStdout,
     read_once: bool,
+    finished: bool,
 }
 impl FFmpegIterator {
-    pub fn new(con: &conversion::Conversion, dry_run: bool) -> Result<Self, Error> {
+    pub fn new(con: &conversion::Conversion, dry_run: bool, pass: Pass) -> Result<Self, Error> {
         let mut c = Command::new("ffmpeg");
 
 
         let mut args: Vec<OsString> = Vec::new();
+        if let Some(ref track) = con.source.track {
+            args.extend(track.input_args());
+        }
         args.push("-i".into());
         args.push((*con.source.path).clone().into());
 
-        args.extend(con.target.codec.to_ffmpeg_args());
+        match pass {
+            Pass::Gain | Pass::Loudness => {
+                let filter = match pass {
+                    Pass::Gain => gain::FILTER.to_string(),
+                    _ => con.target.codec.measure_filter().unwrap_or_default(),
+                };
+                // The stream the normalization and the gain tags apply to
+                let audio = con.target.codec
+                    .first_audio()
+                    .or(con.source.ffprobe.audio.as_ref().map(|a| a.index))
+                    .unwrap_or(0);
+                args.extend(vec!["-map".into(),
+                                 format!("0:{}", audio).into(),
+                                 "-af".into(),
+                                 filter.into()]);
+                args.extend(vec!["-f".into(), "null".into()]);
+            }
+            Pass::Only | Pass::First | Pass::Second => {
+                args.extend(con.target.codec.input_args());
+                args.extend(con.target.codec.to_ffmpeg_args());
+                match con.target.codec.tag_scheme() {
+                    Some(scheme) => {
+                        let tags: Vec<(String, String)> = con.tags.iter().chain(&con.gain_tags).cloned().collect();
+                        args.extend(scheme.metadata_args(con.metadata, &con.source.ffprobe, &tags))
+                    }
+                    None => {
+                        args.extend(con.metadata.to_ffmpeg_args());
+                        for &(ref key, ref value) in &con.tags {
+                            args.extend(vec!["-metadata".into(), format!("{}={}", key, value).into()]);
+                        }
+                        for &(ref key, ref value) in &con.gain_tags {
+                            args.extend(vec!["-metadata:s:a:0".into(), format!("{}={}", key, value).into()]);
+                        }
+                    }
+                }
+            }
+        }
 
-        if dry_run {
+        let passlog = con.target.passlog().into_os_string();
+        match pass {
+            Pass::Only | Pass::Loudness | Pass::Gain => (),
+            Pass::First => {
+                args.extend(vec!["-pass".into(), "1".into(), "-passlogfile".into(), passlog]);
+                args.extend(vec!["-an".into(), "-sn".into(), "-f".into(), "null".into()]);
+            }
+            Pass::Second => {
+                args.extend(vec!["-pass".into(), "2".into(), "-passlogfile".into(), passlog]);
+            }
+        }
+
+        if dry_run || pass == Pass::First || pass == Pass::Loudness || pass == Pass::Gain {
             args.push("-y".into());
             args.push("/dev/null".into());
         } else {
@@ -81,7 +184,10 @@ impl FFmpegIterator {
         }
 
         c.args(args.as_slice());
+        FFmpegIterator::spawn(c)
+    }
 
+    fn spawn(mut c: Command) -> Result<Self, Error> {
         c.stderr(Stdio::piped());
         c.stdout(Stdio::piped());
         c.stdin(Stdio::null());
@@ -96,22 +202,33 @@ impl FFmpegIterator {
                     stdout: stdout,
                     timeiter: TimeIterator::new(stderr),
                     read_once: false,
+                    finished: false,
                 })
             }
             (_, _) => Err(Error::NoStderr),
         }
     }
 }
+impl FFmpegIterator {
+    /// Everything ffmpeg has written to stderr so far
+    pub fn stderr(&self) -> String {
+        String::from_utf8_lossy(&self.timeiter.0.buffer).into_owned()
+    }
+}
+
 impl Iterator for FFmpegIterator {
     type Item = Result<f64, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-
+        if self.finished {
+            return None;
+        }
         for x in &mut self.timeiter {
             self.read_once = true;
             return Some(Ok(x));
         }
-        let _ = self.process.wait();
+        self.finished = true;
+        let status = self.process.wait();
         if !self.read_once {
             let buffer_err = String::from_utf8_lossy(&self.timeiter.0.buffer).into_owned();
             let mut buffer_out: Vec<u8> = Vec::new();
@@ -122,10 +239,27 @@ impl Iterator for FFmpegIterator {
                 stderr: buffer_err,
             }));
         }
-        None
+        match status {
+            Ok(ref status) if status.success() => None,
+            Ok(status) => {
+                Some(Err(Error::Unsuccessful {
+                    exit_code: status.code(),
+                    stderr: stderr_tail(&self.timeiter.0.buffer),
+                }))
+            }
+            Err(e) => Some(Err(Error::WaitError(e))),
+        }
     }
 }
 
+// The progress lines make up most of stderr, the reason for a failure is
+// at the end
+fn stderr_tail(buffer: &[u8]) -> String {
+    const TAIL: usize = 4096;
+    let start = buffer.len().saturating_sub(TAIL);
+    String::from_utf8_lossy(&buffer[start..]).trim().to_string()
+}
+
 pub struct TimeIterator<T: Read>(pub RegexReadIterator<T>);
 
 impl<T: Read> TimeIterator<T> {
@@ -155,3 +289,32 @@ impl<'a, T: Read> Iterator for TimeIterator<T> {
         return None;
     }
 }
+
+#[test]
+fn test_exit_status() {
+    let run = |script: &str| {
+        let mut c = Command::new("sh");
+        c.args(&["-c", script]);
+        FFmpegIterator::spawn(c).unwrap().collect::<Vec<_>>()
+    };
+
+    let results = run("echo 'time=00:01:02.50' >&2");
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().ok(), Some(&62.5));
+
+    // Progress followed by a failure keeps the end of stderr
+    let results = run("echo 'time=00:00:01.00' >&2; echo 'Invalid data' >&2; exit 3");
+    match results.last() {
+        Some(&Err(Error::Unsuccessful { exit_code, ref stderr })) => {
+            assert_eq!(exit_code, Some(3));
+            assert!(stderr.ends_with("Invalid data"));
+        }
+        r => panic!("{:?}", r),
+    }
+
+    // Without any progress, the output is reported instead
+    match run("echo 'Unknown encoder' >&2; exit 1").last() {
+        Some(&Err(Error::OutputError { ref stderr, .. })) => assert!(stderr.contains("Unknown encoder")),
+        r => panic!("{:?}", r),
+    }
+}
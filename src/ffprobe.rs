@@ -1,32 +1,402 @@
 use std::process::Command;
-use rustc_serialize::json;
+use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
 use std::error::Error as StdError;
 use std::fmt;
 use std::ffi::OsStr;
 use std::io;
+use formats::{self, Category};
+use utils;
 
-const FORMAT_WHITELIST: &'static [&'static str] =
-    &["asf", "avi", "matroska,webm", "mov,mp4,m4a,3gp,3g2,mj2", "mpeg", "mpegts", "flv", "wav"];
-const FORMAT_BLACKLIST: &'static [&'static str] =
-    &["bmp_pipe", "gif", "image2", "jpeg_pipe", "lrc", "png_pipe", "tiff_pipe", "tty", "srt"];
-
+/// The video stream that gets converted
 #[derive(Debug,Clone,PartialEq)]
 pub struct Video {
+    pub index: u64,
     pub width: u64,
     pub height: u64,
     pub fps: f64,
     pub codec: String,
 }
+/// The audio stream that gets converted
 #[derive(Debug,Clone,PartialEq)]
 pub struct Audio {
+    pub index: u64,
     pub codec: String,
+    pub channels: Option<u64>,
+    /// As named by ffmpeg, e.g. `5.1(side)`
+    pub layout: Option<String>,
+    pub sample_rate: Option<u64>,
+    /// Bits per sample of lossless codecs
+    pub bits: Option<u64>,
+}
+
+impl Audio {
+    /// `None` if the stream is not audio or has no codec
+    pub fn from_stream(stream: &Stream) -> Option<Audio> {
+        match (stream.audio(), stream.codec_name.as_ref()) {
+            (Some(params), Some(codec)) => {
+                Some(Audio {
+                    index: stream.index,
+                    codec: codec.clone(),
+                    channels: params.channels,
+                    layout: params.channel_layout.clone(),
+                    sample_rate: params.sample_rate,
+                    bits: params.bits_per_raw_sample.or(params.bits_per_sample),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Tags as reported by ffprobe. Muxers disagree on the case of keys, so
+/// lookups ignore case.
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct Tags(BTreeMap<String, String>);
+
+impl Tags {
+    fn from_json(json: Option<&Json>) -> Tags {
+        let object = match json.and_then(|j| j.as_object()) {
+            Some(o) => o,
+            None => return Tags::default(),
+        };
+        Tags(object.iter()
+            .filter_map(|(key, value)| value.as_string().map(|v| (key.clone(), v.to_string())))
+            .collect())
+    }
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .get(key)
+            .or_else(|| {
+                self.0
+                    .iter()
+                    .find(|&(k, _)| k.eq_ignore_ascii_case(key))
+                    .map(|(_, v)| v)
+            })
+            .map(|v| v.as_str())
+    }
+    pub fn iter<'a>(&'a self) -> ::std::collections::btree_map::Iter<'a, String, String> {
+        self.0.iter()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct Disposition {
+    pub default: bool,
+    pub dub: bool,
+    pub original: bool,
+    pub comment: bool,
+    pub lyrics: bool,
+    pub karaoke: bool,
+    pub forced: bool,
+    pub hearing_impaired: bool,
+    pub visual_impaired: bool,
+    pub clean_effects: bool,
+    pub attached_pic: bool,
+    pub timed_thumbnails: bool,
+    pub captions: bool,
+    pub descriptions: bool,
+    pub metadata: bool,
+    pub dependent: bool,
+    pub still_image: bool,
+}
+
+impl Disposition {
+    fn from_json(json: Option<&Json>) -> Disposition {
+        let json = match json {
+            Some(j) => j,
+            None => return Disposition::default(),
+        };
+        Disposition {
+            default: get_flag(json, "default"),
+            dub: get_flag(json, "dub"),
+            original: get_flag(json, "original"),
+            comment: get_flag(json, "comment"),
+            lyrics: get_flag(json, "lyrics"),
+            karaoke: get_flag(json, "karaoke"),
+            forced: get_flag(json, "forced"),
+            hearing_impaired: get_flag(json, "hearing_impaired"),
+            visual_impaired: get_flag(json, "visual_impaired"),
+            clean_effects: get_flag(json, "clean_effects"),
+            attached_pic: get_flag(json, "attached_pic"),
+            timed_thumbnails: get_flag(json, "timed_thumbnails"),
+            captions: get_flag(json, "captions"),
+            descriptions: get_flag(json, "descriptions"),
+            metadata: get_flag(json, "metadata"),
+            dependent: get_flag(json, "dependent"),
+            still_image: get_flag(json, "still_image"),
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Format {
+    pub name: String,
+    pub long_name: Option<String>,
+    pub start_time: Option<f64>,
+    pub duration: Option<f64>,
+    pub size: Option<u64>,
+    pub bit_rate: Option<u64>,
+    pub probe_score: Option<u64>,
+    pub tags: Tags,
+}
+
+impl Format {
+    fn from_json(json: &Json) -> Option<Format> {
+        get_str(json, "format_name").map(|name| {
+            Format {
+                name: name,
+                long_name: get_str(json, "format_long_name"),
+                start_time: get_f64(json, "start_time"),
+                duration: get_f64(json, "duration"),
+                size: get_u64(json, "size"),
+                bit_rate: get_u64(json, "bit_rate"),
+                probe_score: get_u64(json, "probe_score"),
+                tags: Tags::from_json(json.find("tags")),
+            }
+        })
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct VideoStream {
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    pub coded_width: Option<u64>,
+    pub coded_height: Option<u64>,
+    pub pix_fmt: Option<String>,
+    /// As reported by the codec, e.g. 41 for H.264 level 4.1
+    pub level: Option<i64>,
+    pub field_order: Option<String>,
+    pub color_range: Option<String>,
+    pub color_space: Option<String>,
+    pub color_primaries: Option<String>,
+    pub color_transfer: Option<String>,
+    pub chroma_location: Option<String>,
+    pub sample_aspect_ratio: Option<String>,
+    pub display_aspect_ratio: Option<String>,
+    pub r_frame_rate: Option<f64>,
+    pub avg_frame_rate: Option<f64>,
+    pub bits_per_raw_sample: Option<u64>,
+    pub has_b_frames: Option<u64>,
+    pub refs: Option<u64>,
+    /// Degrees counter-clockwise, from the display matrix or the rotate tag
+    pub rotation: Option<f64>,
+}
+
+impl VideoStream {
+    fn from_json(json: &Json, tags: &Tags) -> VideoStream {
+        let side_data_rotation = json.find("side_data_list")
+            .and_then(|j| j.as_array())
+            .and_then(|list| list.iter().filter_map(|d| get_f64(d, "rotation")).next());
+        let tag_rotation = tags.get("rotate").and_then(|r| r.parse::<f64>().ok());
+
+        VideoStream {
+            width: get_u64(json, "width"),
+            height: get_u64(json, "height"),
+            coded_width: get_u64(json, "coded_width"),
+            coded_height: get_u64(json, "coded_height"),
+            pix_fmt: get_known(json, "pix_fmt"),
+            level: get_i64(json, "level").filter(|&l| l > 0),
+            field_order: get_known(json, "field_order"),
+            color_range: get_known(json, "color_range"),
+            color_space: get_known(json, "color_space"),
+            color_primaries: get_known(json, "color_primaries"),
+            color_transfer: get_known(json, "color_transfer"),
+            chroma_location: get_known(json, "chroma_location"),
+            sample_aspect_ratio: get_known(json, "sample_aspect_ratio"),
+            display_aspect_ratio: get_known(json, "display_aspect_ratio"),
+            r_frame_rate: get_fraction(json, "r_frame_rate"),
+            avg_frame_rate: get_fraction(json, "avg_frame_rate"),
+            bits_per_raw_sample: get_u64(json, "bits_per_raw_sample"),
+            has_b_frames: get_u64(json, "has_b_frames"),
+            refs: get_u64(json, "refs"),
+            rotation: side_data_rotation.or(tag_rotation),
+        }
+    }
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.avg_frame_rate.or(self.r_frame_rate)
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct AudioStream {
+    pub sample_rate: Option<u64>,
+    pub channels: Option<u64>,
+    pub channel_layout: Option<String>,
+    pub sample_fmt: Option<String>,
+    pub bits_per_sample: Option<u64>,
+    pub bits_per_raw_sample: Option<u64>,
+    pub frame_size: Option<u64>,
+}
+
+impl AudioStream {
+    fn from_json(json: &Json) -> AudioStream {
+        AudioStream {
+            sample_rate: get_u64(json, "sample_rate"),
+            channels: get_u64(json, "channels"),
+            channel_layout: get_known(json, "channel_layout"),
+            sample_fmt: get_known(json, "sample_fmt"),
+            bits_per_sample: get_u64(json, "bits_per_sample").filter(|&b| b != 0),
+            bits_per_raw_sample: get_u64(json, "bits_per_raw_sample").filter(|&b| b != 0),
+            frame_size: get_u64(json, "frame_size").filter(|&f| f != 0),
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Params {
+    Video(VideoStream),
+    Audio(AudioStream),
+    Subtitle,
+    Attachment,
+    Data,
+    Other(String),
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Stream {
+    pub index: u64,
+    pub codec_name: Option<String>,
+    pub codec_long_name: Option<String>,
+    pub codec_tag: Option<String>,
+    pub profile: Option<String>,
+    pub bit_rate: Option<u64>,
+    pub max_bit_rate: Option<u64>,
+    pub start_time: Option<f64>,
+    pub duration: Option<f64>,
+    pub nb_frames: Option<u64>,
+    /// Only present in the output of `run_count_packets`
+    pub nb_read_packets: Option<u64>,
+    pub time_base: Option<String>,
+    pub disposition: Disposition,
+    pub tags: Tags,
+    pub params: Params,
+}
+
+impl Stream {
+    /// `position` stands in for a missing index
+    fn from_json(position: usize, json: &Json) -> Stream {
+        let index = get_u64(json, "index").unwrap_or(position as u64);
+        let tags = Tags::from_json(json.find("tags"));
+        let params = match get_str(json, "codec_type") {
+            Some(ref t) if t == "video" => Params::Video(VideoStream::from_json(json, &tags)),
+            Some(ref t) if t == "audio" => Params::Audio(AudioStream::from_json(json)),
+            Some(ref t) if t == "subtitle" => Params::Subtitle,
+            Some(ref t) if t == "attachment" => Params::Attachment,
+            Some(ref t) if t == "data" => Params::Data,
+            Some(t) => Params::Other(t),
+            None => Params::Other(String::new()),
+        };
+
+        Stream {
+            index: index,
+            codec_name: get_known(json, "codec_name"),
+            codec_long_name: get_known(json, "codec_long_name"),
+            codec_tag: get_str(json, "codec_tag_string"),
+            profile: get_known(json, "profile"),
+            bit_rate: get_u64(json, "bit_rate"),
+            max_bit_rate: get_u64(json, "max_bit_rate"),
+            start_time: get_f64(json, "start_time"),
+            duration: get_f64(json, "duration"),
+            nb_frames: get_u64(json, "nb_frames"),
+            nb_read_packets: get_u64(json, "nb_read_packets"),
+            time_base: get_str(json, "time_base"),
+            disposition: Disposition::from_json(json.find("disposition")),
+            tags: tags,
+            params: params,
+        }
+    }
+
+    pub fn codec_type(&self) -> &str {
+        match self.params {
+            Params::Video(_) => "video",
+            Params::Audio(_) => "audio",
+            Params::Subtitle => "subtitle",
+            Params::Attachment => "attachment",
+            Params::Data => "data",
+            Params::Other(ref t) => t,
+        }
+    }
+    pub fn video(&self) -> Option<&VideoStream> {
+        match self.params {
+            Params::Video(ref v) => Some(v),
+            _ => None,
+        }
+    }
+    pub fn audio(&self) -> Option<&AudioStream> {
+        match self.params {
+            Params::Audio(ref a) => Some(a),
+            _ => None,
+        }
+    }
+    pub fn language(&self) -> Option<&str> {
+        self.tags.get("language").filter(|&l| l != "und")
+    }
+    pub fn title(&self) -> Option<&str> {
+        self.tags.get("title")
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Chapter {
+    pub id: i64,
+    pub start: f64,
+    pub end: f64,
+    pub tags: Tags,
+}
+
+impl Chapter {
+    fn from_json(json: &Json) -> Option<Chapter> {
+        match (get_f64(json, "start_time"), get_f64(json, "end_time")) {
+            (Some(start), Some(end)) => {
+                Some(Chapter {
+                    id: get_i64(json, "id").unwrap_or(0),
+                    start: start,
+                    end: end,
+                    tags: Tags::from_json(json.find("tags")),
+                })
+            }
+            _ => None,
+        }
+    }
+    pub fn title(&self) -> Option<&str> {
+        self.tags.get("title")
+    }
+}
+
+/// Where the duration of a source was read from. Anything but the container
+/// duration is an estimate.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum DurationSource {
+    Container,
+    Streams,
+    Frames,
+    Packets,
+    /// Nothing in the ffprobe output gave a duration, so `duration` is zero
+    /// until the packets are counted
+    Unknown,
+}
+
+impl DurationSource {
+    pub fn is_estimate(&self) -> bool {
+        *self != DurationSource::Container
+    }
 }
 
 #[derive(Debug,Clone,PartialEq)]
 pub struct FFProbe {
+    pub category: Category,
     pub duration: f64,
+    pub duration_source: DurationSource,
     pub video: Option<Video>,
     pub audio: Option<Audio>,
+    pub format: Format,
+    pub streams: Vec<Stream>,
+    pub chapters: Vec<Chapter>,
 }
 
 impl FFProbe {
@@ -39,6 +409,9 @@ impl FFProbe {
             0.
         }
     }
+    pub fn stream(&self, index: u64) -> Option<&Stream> {
+        self.streams.iter().find(|s| s.index == index)
+    }
 }
 
 #[derive(Debug)]
@@ -66,18 +439,45 @@ impl fmt::Display for Error {
     }
 }
 
+impl Error {
+    /// Whether ffprobe could not be started at all, which no other file
+    /// would fare better with
+    pub fn is_spawn_error(&self) -> bool {
+        match *self {
+            Error::RunError(RunError::OutputCaptureError(_)) => true,
+            _ => false,
+        }
+    }
+    /// A one line summary, leaving out the captured ffprobe output
+    pub fn reason(&self) -> String {
+        match *self {
+            Error::ParseError(ParseError { kind: ParseErrorKind::UnknownFormat(ref format), .. }) => {
+                format!("Unrecognized format {:?}", format)
+            }
+            Error::ParseError(ParseError { kind: ParseErrorKind::MissingField { index, field },
+                                           .. }) => {
+                format!("Stream {} has no {}", index, field)
+            }
+            Error::ParseError(ref e) => e.description().to_string(),
+            Error::RunError(RunError::OutputCaptureError(ref e)) => {
+                format!("{}: {}", self.description(), e)
+            }
+            Error::RunError(ref e) => e.description().to_string(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ParseErrorKind {
     Json,
     Format,
     UnknownFormat(String),
-    Duration,
     Stream,
-    VideoCodecName,
-    AudioCodecName,
-    Height,
-    Width,
-    FPS,
+    /// A field needed to convert the selected stream
+    MissingField {
+        index: u64,
+        field: &'static str,
+    },
 }
 
 
@@ -162,13 +562,8 @@ impl StdError for ParseError {
             Json => "Could not parse JSON",
             Format => "Could not get format name from JSON",
             UnknownFormat(_) => "Unrecognized format string",
-            Duration => "Could not get duration from JSON",
             Stream => "Could not get streams from JSON",
-            VideoCodecName => "Could not get codec name from video stream",
-            AudioCodecName => "Could not get codec name from audio stream",
-            Height => "Could not get height from video stream",
-            Width => "Could not get width from video stream",
-            FPS => "Could not get fps from video stream",
+            MissingField { .. } => "Stream is missing a required field",
         }
     }
 }
@@ -183,6 +578,14 @@ impl fmt::Display for ParseError {
                        format,
                        self.input.trim())
             }
+            ParseErrorKind::MissingField { index, field } => {
+                write!(f,
+                       "{}\nStream: {}\nField: {}\nInput:\n{}",
+                       self.description(),
+                       index,
+                       field,
+                       self.input.trim())
+            }
             _ => write!(f, "{}\nInput:\n{}", self.description(), self.input.trim()),
         }
     }
@@ -200,25 +603,97 @@ impl From<RunError> for Error {
 }
 
 
-pub fn ffprobe<T: AsRef<OsStr>>(path: T) -> Result<Option<FFProbe>, Error> {
-    let string = match ffprobe_run(path.as_ref()) {
-        Ok(string) => string,
-        Err(RunError::Unsuccessful { .. }) => return Ok(None),
-        Err(e) => return Err(e.into()),
+pub fn ffprobe<T: AsRef<OsStr>>(path: T,
+                                 formats: &formats::Registry)
+                                 -> Result<Option<FFProbe>, Error> {
+    match try!(run(path)) {
+        Some(string) => parse(string, formats),
+        None => Ok(None),
+    }
+}
+
+/// Runs ffprobe and returns its raw output, or `None` if ffprobe could not
+/// make sense of the file.
+pub fn run<T: AsRef<OsStr>>(path: T) -> Result<Option<String>, Error> {
+    match ffprobe_run(path.as_ref(),
+                      &["-show_streams", "-show_format", "-show_chapters"]) {
+        Ok(string) => Ok(Some(string)),
+        Err(RunError::Unsuccessful { .. }) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parses the output of `run`. Files that are not classified as video or
+/// audio by `formats` give `None`.
+pub fn parse(output: String, formats: &formats::Registry) -> Result<Option<FFProbe>, Error> {
+    ffprobe_parse(output, formats).map_err(|e| e.into())
+}
+
+/// Runs ffprobe with `-count_packets`, which reads through the whole file.
+/// Only meant for sources without any duration information, see
+/// `packet_duration`.
+pub fn run_count_packets<T: AsRef<OsStr>>(path: T) -> Result<Option<String>, Error> {
+    match ffprobe_run(path.as_ref(), &["-count_packets", "-show_streams", "-show_format"]) {
+        Ok(string) => Ok(Some(string)),
+        Err(RunError::Unsuccessful { .. }) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Estimates the duration from the output of `run_count_packets`: frames
+/// over frame rate for video, samples over sample rate for audio.
+pub fn packet_duration(output: &str) -> Option<f64> {
+    let json = match Json::from_str(output) {
+        Ok(j) => j,
+        Err(_) => return None,
+    };
+    let streams = match json.find("streams").and_then(|j| j.as_array()) {
+        Some(s) => s,
+        None => return None,
     };
-    return ffprobe_parse(string).map_err(|e| e.into());
+    // Only a file of a single stream is all audio
+    let size = match streams.len() {
+        1 => json.find("format").and_then(|f| get_u64(f, "size")),
+        _ => None,
+    };
+
+    streams.iter()
+        .enumerate()
+        .map(|(n, json)| Stream::from_json(n, json))
+        .filter_map(|stream| {
+            let packets = match stream.nb_read_packets {
+                Some(p) => p as f64,
+                None => return None,
+            };
+            match stream.params {
+                Params::Video(ref video) if !stream.disposition.attached_pic => {
+                    video.frame_rate().map(|fps| packets / fps)
+                }
+                Params::Audio(AudioStream { frame_size: Some(f), sample_rate: Some(r), .. })
+                    if r != 0 => Some(packets * (f as f64) / (r as f64)),
+                // PCM packets vary in size, but the bytes per second do not
+                Params::Audio(AudioStream { sample_rate: Some(r),
+                                            channels: Some(c),
+                                            bits_per_sample: Some(b),
+                                            .. }) if r * c * b != 0 => {
+                    size.map(|size| size as f64 * 8. / (r * c * b) as f64)
+                }
+                _ => None,
+            }
+        })
+        .fold(None, |max: Option<f64>, d| Some(max.map_or(d, |m| m.max(d))))
+        .filter(|&d| d > 0.)
 }
 
-fn ffprobe_run(path: &OsStr) -> Result<String, RunError> {
+fn ffprobe_run(path: &OsStr, show: &[&str]) -> Result<String, RunError> {
     let mut c = Command::new("ffprobe");
     c.args(&[OsStr::new("-print_format"),
              OsStr::new("json"),
              OsStr::new("-hide_banner"),
              OsStr::new("-loglevel"),
-             OsStr::new("error"),
-             OsStr::new("-show_streams"),
-             OsStr::new("-show_format"),
-             path]);
+             OsStr::new("error")]);
+    c.args(show);
+    c.arg(path);
 
     let result = try!(c.output().map_err(|e| RunError::OutputCaptureError(e)));
     let stdout = try!(String::from_utf8(result.stdout).map_err(|e| RunError::StdOutUTF8Error(e)));
@@ -238,8 +713,8 @@ fn ffprobe_run(path: &OsStr) -> Result<String, RunError> {
 }
 
 
-fn ffprobe_parse(text: String) -> Result<Option<FFProbe>, ParseError> {
-    let json = match json::Json::from_str(&text).ok() {
+fn ffprobe_parse(text: String, formats: &formats::Registry) -> Result<Option<FFProbe>, ParseError> {
+    let json = match Json::from_str(&text).ok() {
         Some(c) => c,
         None => {
             return Err(ParseError {
@@ -249,7 +724,7 @@ fn ffprobe_parse(text: String) -> Result<Option<FFProbe>, ParseError> {
         }
     };
 
-    let format = match json.find_path(&["format", "format_name"]).and_then(|j| j.as_string()) {
+    let format = match json.find("format").and_then(Format::from_json) {
         Some(f) => f,
         None => {
             return Err(ParseError {
@@ -259,129 +734,181 @@ fn ffprobe_parse(text: String) -> Result<Option<FFProbe>, ParseError> {
         }
     };
 
-    match (FORMAT_WHITELIST.contains(&format), FORMAT_BLACKLIST.contains(&format)) {
-        (false, true) => {
-            return Ok(None);
-        }
-        (true, false) => (),
-        (true, true) | (false, false) => {
+    let streams: Vec<Stream> = match json.find("streams").and_then(|j| j.as_array()) {
+        Some(t) => t.iter().enumerate().map(|(n, json)| Stream::from_json(n, json)).collect(),
+        None => {
             return Err(ParseError {
                 input: text,
-                kind: ParseErrorKind::UnknownFormat(format.to_string()),
-            });
-        }
-    }
-
-    let duration: f64 = match json.find_path(&["format", "duration"]) {
-        Some(j) => {
-            match j.as_string().and_then(|s| s.parse::<f64>().ok()) {
-                Some(s) => s,
-                None => {
-                    return Err(ParseError {
-                        input: text,
-                        kind: ParseErrorKind::Duration,
-                    })
-                }
-            }
+                kind: ParseErrorKind::Stream,
+            })
         }
-        None => return Ok(None),
     };
 
+    let chapters: Vec<Chapter> = json.find("chapters")
+        .and_then(|j| j.as_array())
+        .map_or(Vec::new(), |c| c.iter().filter_map(Chapter::from_json).collect());
 
-    let streams = match json.find("streams").and_then(|j| j.as_array()) {
-        Some(t) => t,
+    let mut kinds = formats::Streams::default();
+    for stream in &streams {
+        kinds.add(stream.codec_type(),
+                  stream.codec_name.as_ref().map_or("", |n| n.as_str()),
+                  stream.disposition.attached_pic);
+    }
+
+    let category = match formats.classify(&format.name, &kinds) {
+        Some(c) if c.is_media() => c,
+        Some(_) => return Ok(None),
         None => {
             return Err(ParseError {
                 input: text,
-                kind: ParseErrorKind::Stream,
-            })
+                kind: ParseErrorKind::UnknownFormat(format.name.clone()),
+            });
         }
     };
-    let audio_stream = streams.into_iter()
-        .filter(|&x| x.find("codec_type").and_then(|x| x.as_string()) == Some("audio"))
-        .filter_map(|x| x.as_object())
-        .next();
 
-    let audio: Option<Audio> = if let Some(stream) = audio_stream {
-        let audio_codec = match stream.get("codec_name").and_then(|x| x.as_string()) {
-            Some(t) => t.to_string(),
-            None => {
-                return Err(ParseError {
-                    input: text,
-                    kind: ParseErrorKind::AudioCodecName,
-                })
+    // The stream ffmpeg picks without `-map`: a default one first, then the
+    // one with the most channels, ties going to the first
+    let rank = |s: &Stream| (s.disposition.default, s.audio().and_then(|a| a.channels).unwrap_or(0));
+    let audio_stream = streams.iter()
+        .filter(|s| s.audio().is_some())
+        .fold(None, |best: Option<&Stream>, s| match best {
+            Some(b) if rank(b) >= rank(s) => Some(b),
+            _ => Some(s),
+        });
+    let audio = match audio_stream {
+        Some(stream) => {
+            match Audio::from_stream(stream) {
+                Some(audio) => Some(audio),
+                None => return Err(missing_field(text, stream, "codec_name")),
             }
-        };
-        Some(Audio { codec: audio_codec })
-    } else {
-        None
+        }
+        None => None,
     };
 
-    let video_stream = streams.into_iter()
-        .filter(|&x| x.find("codec_type").and_then(|x| x.as_string()) == Some("video"))
-        .filter_map(|x| x.as_object())
+    // Cover art is not video, and audio-only formats never carry real video
+    let video_stream = streams.iter()
+        .filter(|_| category != Category::AudioOnly)
+        .filter(|s| !s.disposition.attached_pic)
+        .filter_map(|s| s.video().map(|v| (s, v)))
         .next();
 
-    let video: Option<Video> = if let Some(stream) = video_stream {
-        let video_codec = match stream.get("codec_name").and_then(|x| x.as_string()) {
-            Some(t) => t.to_string(),
-            None => {
-                return Err(ParseError {
-                    input: text,
-                    kind: ParseErrorKind::VideoCodecName,
-                })
-            }
-        };
-
-        let height = match stream.get("height").and_then(|x| x.as_u64()) {
-            Some(t) => t,
-            None => {
-                return Err(ParseError {
-                    input: text,
-                    kind: ParseErrorKind::Height,
-                })
-            }
-        };
-
-        let width = match stream.get("width").and_then(|x| x.as_u64()) {
-            Some(t) => t,
-            None => {
-                return Err(ParseError {
-                    input: text,
-                    kind: ParseErrorKind::Width,
-                })
+    let video = match video_stream {
+        Some((stream, params)) => {
+            match (stream.codec_name.as_ref(), params.width, params.height, params.r_frame_rate) {
+                (Some(codec), Some(width), Some(height), Some(fps)) => {
+                    Some(Video {
+                        index: stream.index,
+                        codec: codec.clone(),
+                        width: width,
+                        height: height,
+                        fps: fps,
+                    })
+                }
+                (None, _, _, _) => return Err(missing_field(text, stream, "codec_name")),
+                (_, None, _, _) => return Err(missing_field(text, stream, "width")),
+                (_, _, None, _) => return Err(missing_field(text, stream, "height")),
+                (_, _, _, None) => return Err(missing_field(text, stream, "r_frame_rate")),
             }
-        };
+        }
+        None => None,
+    };
 
-        let fps: f64 = match stream.get("r_frame_rate")
-            .and_then(|x| x.as_string())
-            .and_then(|s| parse_fraction(&String::from(s))) {
-            Some(t) => t,
-            None => {
-                return Err(ParseError {
-                    input: text,
-                    kind: ParseErrorKind::FPS,
-                })
-            }
-        };
+    if video.is_none() && audio.is_none() {
+        return Ok(None);
+    }
 
-        Some(Video {
-            codec: video_codec,
-            width: width,
-            height: height,
-            fps: fps,
-        })
-    } else {
-        None
+    // Broken AVIs, raw transport streams and some WAVs lack a container
+    // duration, so fall back to estimates
+    let frames_duration = match (video_stream, video.as_ref()) {
+        (Some((&Stream { nb_frames: Some(frames), .. }, _)), Some(video)) if frames != 0 => {
+            Some(frames as f64 / video.fps)
+        }
+        _ => None,
     };
+    let (duration, duration_source) = format.duration
+        .map(|d| (d, DurationSource::Container))
+        .or_else(|| streams_duration(&streams).map(|d| (d, DurationSource::Streams)))
+        .or_else(|| frames_duration.map(|d| (d, DurationSource::Frames)))
+        .unwrap_or((0., DurationSource::Unknown));
 
     Ok(Some(FFProbe {
+        category: category,
         duration: duration,
+        duration_source: duration_source,
         audio: audio,
         video: video,
+        format: format,
+        streams: streams,
+        chapters: chapters,
     }))
 }
 
+fn missing_field(text: String, stream: &Stream, field: &'static str) -> ParseError {
+    ParseError {
+        input: text,
+        kind: ParseErrorKind::MissingField {
+            index: stream.index,
+            field: field,
+        },
+    }
+}
+
+/// The longest stream duration, also looking at the DURATION tag that
+/// Matroska muxers write
+fn streams_duration(streams: &[Stream]) -> Option<f64> {
+    streams.iter()
+        .filter(|stream| !stream.disposition.attached_pic)
+        .filter_map(|stream| {
+            stream.duration
+                .or_else(|| stream.tags.get("DURATION").and_then(utils::parse_duration))
+        })
+        .filter(|&d| d > 0.)
+        .fold(None, |max: Option<f64>, d| Some(max.map_or(d, |m| m.max(d))))
+}
+
+// ffprobe prints most numbers as strings, and "N/A" or "unknown" for
+// missing values
+
+fn get_str(json: &Json, key: &str) -> Option<String> {
+    json.find(key).and_then(|j| j.as_string()).map(|s| s.to_string())
+}
+
+fn get_known(json: &Json, key: &str) -> Option<String> {
+    get_str(json, key).filter(|s| s != "unknown" && s != "N/A" && !s.is_empty())
+}
+
+fn get_u64(json: &Json, key: &str) -> Option<u64> {
+    match json.find(key) {
+        Some(&Json::String(ref s)) => s.parse().ok(),
+        Some(j) => j.as_u64(),
+        None => None,
+    }
+}
+
+fn get_i64(json: &Json, key: &str) -> Option<i64> {
+    match json.find(key) {
+        Some(&Json::String(ref s)) => s.parse().ok(),
+        Some(j) => j.as_i64(),
+        None => None,
+    }
+}
+
+fn get_f64(json: &Json, key: &str) -> Option<f64> {
+    match json.find(key) {
+        Some(&Json::String(ref s)) => s.parse().ok(),
+        Some(j) => j.as_f64(),
+        None => None,
+    }
+}
+
+fn get_fraction(json: &Json, key: &str) -> Option<f64> {
+    json.find(key).and_then(|j| j.as_string()).and_then(|s| parse_fraction(&String::from(s)))
+}
+
+fn get_flag(json: &Json, key: &str) -> bool {
+    json.find(key).and_then(|j| j.as_u64()) == Some(1)
+}
+
 fn parse_fraction(t: &String) -> Option<f64> {
     let mut split = t.split("/").map(|n| n.parse::<u64>());
 
@@ -391,3 +918,87 @@ fn parse_fraction(t: &String) -> Option<f64> {
         (_, _) => return None,
     }
 }
+
+#[test]
+fn test_duration_fallback() {
+    let formats = formats::Registry::default();
+    let probe = |streams: &str| {
+        let output = format!("{{\"format\": {{\"format_name\": \"avi\"}}, \"streams\": [{}]}}",
+                             streams);
+        ffprobe_parse(output, &formats).unwrap().unwrap()
+    };
+    let video = "\"codec_type\": \"video\", \"codec_name\": \"mpeg4\", \"width\": 640, \
+                 \"height\": 480, \"r_frame_rate\": \"25/1\"";
+
+    let p = probe(&format!("{{{}, \"duration\": \"12.5\"}}", video));
+    assert_eq!((p.duration, p.duration_source), (12.5, DurationSource::Streams));
+
+    let p = probe(&format!("{{{}, \"tags\": {{\"DURATION\": \"00:01:00.000000000\"}}}}",
+                           video));
+    assert_eq!((p.duration, p.duration_source), (60., DurationSource::Streams));
+
+    let p = probe(&format!("{{{}, \"nb_frames\": \"250\"}}", video));
+    assert_eq!((p.duration, p.duration_source), (10., DurationSource::Frames));
+
+    let p = probe(&format!("{{{}}}", video));
+    assert_eq!(p.duration_source, DurationSource::Unknown);
+
+    let packets = "{\"streams\": [{\"codec_type\": \"audio\", \"sample_rate\": \"48000\", \
+                   \"frame_size\": 1024, \"nb_read_packets\": \"4500\"}]}";
+    assert_eq!(packet_duration(packets), Some(96.));
+    let pcm = "{\"streams\": [{\"codec_type\": \"audio\", \"codec_name\": \"pcm_s16le\", \
+               \"sample_rate\": \"44100\", \"channels\": 2, \"bits_per_sample\": 16, \
+               \"nb_read_packets\": \"2584\"}], \"format\": {\"size\": \"17640000\"}}";
+    assert_eq!(packet_duration(pcm), Some(100.));
+}
+
+#[test]
+fn test_model() {
+    let output = r#"{
+        "streams": [
+            {"index": 0, "codec_name": "hevc", "profile": "Main 10", "codec_type": "video",
+             "width": 3840, "height": 2160, "pix_fmt": "yuv420p10le", "level": 153,
+             "color_primaries": "bt2020", "color_transfer": "smpte2084",
+             "color_space": "unknown", "field_order": "progressive",
+             "r_frame_rate": "24000/1001", "avg_frame_rate": "0/0",
+             "side_data_list": [{"side_data_type": "Display Matrix", "rotation": -90}],
+             "disposition": {"default": 1, "attached_pic": 0}},
+            {"index": 1, "codec_name": "truehd", "codec_type": "audio", "sample_rate": "48000",
+             "channels": 8, "channel_layout": "7.1", "bits_per_raw_sample": "24",
+             "bit_rate": "N/A", "tags": {"LANGUAGE": "eng", "title": "Atmos"}},
+            {"index": 2, "codec_name": "mjpeg", "codec_type": "video", "width": 600,
+             "height": 600, "r_frame_rate": "90000/1", "disposition": {"attached_pic": 1}}
+        ],
+        "chapters": [
+            {"id": 0, "start_time": "0.000000", "end_time": "300.5", "tags": {"title": "One"}}
+        ],
+        "format": {"format_name": "matroska,webm", "duration": "5400.0", "bit_rate": "40000000",
+                   "tags": {"title": "Movie"}}
+    }"#;
+    let probe = ffprobe_parse(output.to_string(), &formats::Registry::default()).unwrap().unwrap();
+
+    assert_eq!(probe.format.bit_rate, Some(40000000));
+    assert_eq!(probe.format.tags.get("TITLE"), Some("Movie"));
+    assert_eq!(probe.video.as_ref().map(|v| v.index), Some(0));
+    assert_eq!(probe.audio.as_ref().map(|a| a.index), Some(1));
+
+    let video = probe.streams[0].video().unwrap();
+    assert_eq!(probe.streams[0].profile, Some("Main 10".to_string()));
+    assert_eq!(video.level, Some(153));
+    assert_eq!(video.color_transfer, Some("smpte2084".to_string()));
+    assert_eq!(video.color_space, None);
+    assert_eq!(video.avg_frame_rate, None);
+    assert_eq!(video.rotation, Some(-90.));
+    assert!(probe.streams[0].disposition.default);
+
+    let audio = probe.streams[1].audio().unwrap();
+    assert_eq!((audio.sample_rate, audio.channels), (Some(48000), Some(8)));
+    assert_eq!(audio.bits_per_raw_sample, Some(24));
+    assert_eq!(probe.streams[1].bit_rate, None);
+    assert_eq!(probe.streams[1].language(), Some("eng"));
+
+    assert!(probe.streams[2].disposition.attached_pic);
+    assert_eq!(probe.chapters.len(), 1);
+    assert_eq!(probe.chapters[0].end, 300.5);
+    assert_eq!(probe.chapters[0].title(), Some("One"));
+}
@@ -0,0 +1,120 @@
+use glob::Pattern;
+use ignore::{self, IgnoreFile};
+use path::find_relative;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Restricts which paths are picked up while walking INPUT_DIRECTORY.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub min_duration: Option<f64>,
+    pub max_duration: Option<f64>,
+    pub hidden: bool,
+    pub ignore_files: bool,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            min_size: None,
+            max_size: None,
+            min_duration: None,
+            max_duration: None,
+            hidden: false,
+            ignore_files: true,
+        }
+    }
+}
+
+impl Filter {
+    pub fn accepts_duration(&self, duration: f64) -> bool {
+        self.min_duration.map_or(true, |min| duration >= min) &&
+        self.max_duration.map_or(true, |max| duration <= max)
+    }
+
+    pub fn matcher<'a>(&'a self, base: &Path) -> Matcher<'a> {
+        Matcher {
+            filter: self,
+            base: base.to_path_buf(),
+            ignore_files: HashMap::new(),
+            defaults: IgnoreFile::parse(base, ignore::DEFAULT_RULES),
+        }
+    }
+}
+
+/// Applies a `Filter` below one base directory, caching the ignore files it
+/// has read along the way.
+pub struct Matcher<'a> {
+    filter: &'a Filter,
+    base: PathBuf,
+    ignore_files: HashMap<PathBuf, Option<IgnoreFile>>,
+    /// Applied when no ignore file has a matching rule
+    defaults: IgnoreFile,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn accepts_directory(&mut self, path: &Path) -> bool {
+        !self.is_excluded(path, true)
+    }
+
+    pub fn accepts_file(&mut self, path: &Path) -> bool {
+        if self.is_excluded(path, false) {
+            return false;
+        }
+
+        let relative = find_relative(path, &self.base);
+        if self.filter.include.len() != 0 &&
+           !self.filter.include.iter().any(|p| p.matches_relative(&relative)) {
+            return false;
+        }
+
+        if self.filter.min_size.is_none() && self.filter.max_size.is_none() {
+            return true;
+        }
+        let size = match path.metadata() {
+            Ok(m) => m.len(),
+            Err(_) => return true,
+        };
+        self.filter.min_size.map_or(true, |min| size >= min) &&
+        self.filter.max_size.map_or(true, |max| size <= max)
+    }
+
+    fn is_excluded(&mut self, path: &Path, is_dir: bool) -> bool {
+        let relative = find_relative(path, &self.base);
+
+        let hidden = relative.file_name()
+            .map_or(false, |name| name.to_string_lossy().starts_with('.'));
+        if hidden && !self.filter.hidden {
+            return true;
+        }
+
+        if self.filter.exclude.iter().any(|p| p.matches_relative(&relative)) {
+            return true;
+        }
+
+        if !self.filter.ignore_files {
+            return false;
+        }
+
+        // Deeper ignore files override shallower ones, like .gitignore, and
+        // all of them override the defaults
+        let mut ignored = None;
+        let &mut Matcher { ref base, ref mut ignore_files, ref defaults, .. } = self;
+        for dir in path.ancestors().skip(1).take_while(|dir| dir.starts_with(base)) {
+            let file = ignore_files
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| IgnoreFile::load(dir));
+            if let Some(matched) = file.as_ref().and_then(|f| f.matched(path, is_dir)) {
+                ignored = Some(matched);
+                break;
+            }
+        }
+        ignored.or_else(|| defaults.matched(path, is_dir)).unwrap_or(false)
+    }
+}
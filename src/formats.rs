@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    VideoContainer,
+    AudioOnly,
+    Image,
+    Subtitle,
+    Playlist,
+}
+
+impl Category {
+    pub fn from_str(s: &str) -> Option<Category> {
+        match s {
+            "video" => Some(Category::VideoContainer),
+            "audio" => Some(Category::AudioOnly),
+            "image" => Some(Category::Image),
+            "subtitle" => Some(Category::Subtitle),
+            "playlist" => Some(Category::Playlist),
+            _ => None,
+        }
+    }
+
+    /// Whether files of this category are converted
+    pub fn is_media(&self) -> bool {
+        match *self {
+            Category::VideoContainer | Category::AudioOnly => true,
+            Category::Image | Category::Subtitle | Category::Playlist => false,
+        }
+    }
+}
+
+// Names as reported in format.format_name by ffprobe
+const BUILTIN: &'static [(&'static str, Category)] =
+    &[("3dostr", Category::VideoContainer),
+      ("asf", Category::VideoContainer),
+      ("avi", Category::VideoContainer),
+      ("cavsvideo", Category::VideoContainer),
+      ("dirac", Category::VideoContainer),
+      ("dv", Category::VideoContainer),
+      ("dvd_nav_mpeg", Category::VideoContainer),
+      ("flv", Category::VideoContainer),
+      ("h261", Category::VideoContainer),
+      ("h263", Category::VideoContainer),
+      ("h264", Category::VideoContainer),
+      ("hevc", Category::VideoContainer),
+      ("ivf", Category::VideoContainer),
+      ("live_flv", Category::VideoContainer),
+      ("m4v", Category::VideoContainer),
+      ("matroska,webm", Category::VideoContainer),
+      ("mov,mp4,m4a,3gp,3g2,mj2", Category::VideoContainer),
+      ("mpeg", Category::VideoContainer),
+      ("mpegts", Category::VideoContainer),
+      ("mpegtsraw", Category::VideoContainer),
+      ("mpegvideo", Category::VideoContainer),
+      ("mxf", Category::VideoContainer),
+      ("nsv", Category::VideoContainer),
+      ("nut", Category::VideoContainer),
+      ("obu", Category::VideoContainer),
+      ("ogg", Category::VideoContainer),
+      ("rawvideo", Category::VideoContainer),
+      ("rm", Category::VideoContainer),
+      ("smk", Category::VideoContainer),
+      ("vc1", Category::VideoContainer),
+      ("wtv", Category::VideoContainer),
+      ("yuv4mpegpipe", Category::VideoContainer),
+      ("aac", Category::AudioOnly),
+      ("ac3", Category::AudioOnly),
+      ("aiff", Category::AudioOnly),
+      ("amr", Category::AudioOnly),
+      ("ape", Category::AudioOnly),
+      ("au", Category::AudioOnly),
+      ("caf", Category::AudioOnly),
+      ("dsf", Category::AudioOnly),
+      ("dts", Category::AudioOnly),
+      ("dtshd", Category::AudioOnly),
+      ("eac3", Category::AudioOnly),
+      ("flac", Category::AudioOnly),
+      ("mlp", Category::AudioOnly),
+      ("mp3", Category::AudioOnly),
+      ("mpc", Category::AudioOnly),
+      ("mpc8", Category::AudioOnly),
+      ("shn", Category::AudioOnly),
+      ("truehd", Category::AudioOnly),
+      ("tta", Category::AudioOnly),
+      ("w64", Category::AudioOnly),
+      ("wav", Category::AudioOnly),
+      ("wv", Category::AudioOnly),
+      ("bmp_pipe", Category::Image),
+      ("gif", Category::Image),
+      ("image2", Category::Image),
+      ("jpeg_pipe", Category::Image),
+      ("png_pipe", Category::Image),
+      ("svg_pipe", Category::Image),
+      ("tiff_pipe", Category::Image),
+      ("webp_pipe", Category::Image),
+      ("ass", Category::Subtitle),
+      ("jacosub", Category::Subtitle),
+      ("lrc", Category::Subtitle),
+      ("microdvd", Category::Subtitle),
+      ("mpl2", Category::Subtitle),
+      ("pjs", Category::Subtitle),
+      ("realtext", Category::Subtitle),
+      ("sami", Category::Subtitle),
+      ("srt", Category::Subtitle),
+      ("stl", Category::Subtitle),
+      ("subviewer", Category::Subtitle),
+      ("subviewer1", Category::Subtitle),
+      ("sup", Category::Subtitle),
+      ("vobsub", Category::Subtitle),
+      ("vplayer", Category::Subtitle),
+      ("webvtt", Category::Subtitle),
+      // Plain text files are picked up by the ANSI art demuxer
+      ("tty", Category::Subtitle),
+      ("concat", Category::Playlist),
+      ("dash", Category::Playlist),
+      ("hls", Category::Playlist)];
+
+// Video codecs that only ever hold still pictures
+const IMAGE_CODECS: &'static [&'static str] =
+    &["bmp", "gif", "jpeg2000", "jpegls", "mjpeg", "png", "ppm", "targa", "tiff", "webp"];
+
+/// The kinds of streams found in a file
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Streams {
+    pub video: usize,
+    pub audio: usize,
+    pub subtitle: usize,
+    /// Cover art and other still pictures stored as video streams
+    pub image: usize,
+}
+
+impl Streams {
+    pub fn add(&mut self, codec_type: &str, codec_name: &str, attached_pic: bool) {
+        match codec_type {
+            "video" if attached_pic || IMAGE_CODECS.contains(&codec_name) => self.image += 1,
+            "video" => self.video += 1,
+            "audio" => self.audio += 1,
+            "subtitle" => self.subtitle += 1,
+            _ => (),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Read { path: PathBuf, error: io::Error },
+    Parse { path: PathBuf, line: usize, text: String },
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Read { .. } => "Could not read format registry",
+            Error::Parse { .. } => "Could not parse format registry",
+        }
+    }
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::Read { ref error, .. } => Some(error),
+            Error::Parse { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Read { ref path, .. } => write!(f, "{}: {:?}", self.description(), path),
+            Error::Parse { ref path, line, ref text } => {
+                write!(f,
+                       "{}: {:?}, line {}: {:?}",
+                       self.description(),
+                       path,
+                       line,
+                       text)
+            }
+        }
+    }
+}
+
+/// Maps ffprobe format names to categories. Starts out with the built-in
+/// formats and can be extended with a file of `CATEGORY FORMAT_NAME` lines.
+#[derive(Debug, Clone)]
+pub struct Registry {
+    formats: HashMap<String, Category>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            formats: BUILTIN.iter().map(|&(name, category)| (name.to_string(), category)).collect(),
+        }
+    }
+}
+
+impl Registry {
+    pub fn insert(&mut self, format_name: &str, category: Category) {
+        self.formats.insert(format_name.to_string(), category);
+    }
+
+    /// Adds the formats listed in `path`, replacing built-in entries of the
+    /// same name. Empty lines and lines starting with `#` are ignored.
+    pub fn load(&mut self, path: &Path) -> Result<(), Error> {
+        let mut text = String::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_string(&mut text)).map_err(|e| {
+            Error::Read {
+                path: path.to_path_buf(),
+                error: e,
+            }
+        }));
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            match (words.next().and_then(Category::from_str), words.next(), words.next()) {
+                (Some(category), Some(name), None) => self.insert(name, category),
+                _ => {
+                    return Err(Error::Parse {
+                        path: path.to_path_buf(),
+                        line: n + 1,
+                        text: line.to_string(),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up the full format name first, then each of its comma separated
+    /// aliases.
+    pub fn lookup(&self, format_name: &str) -> Option<Category> {
+        self.formats
+            .get(format_name)
+            .or_else(|| format_name.split(',').filter_map(|name| self.formats.get(name)).next())
+            .cloned()
+    }
+
+    /// Classifies a file by its format name and its streams. Containers
+    /// like Matroska, Ogg and MP4 hold music too, so a video container
+    /// with audio but no video is audio only. Formats that are not
+    /// registered go by their streams alone. Returns `None` if neither
+    /// gives an answer.
+    pub fn classify(&self, format_name: &str, streams: &Streams) -> Option<Category> {
+        match self.lookup(format_name) {
+            Some(Category::VideoContainer) if streams.video == 0 && streams.audio != 0 => {
+                return Some(Category::AudioOnly)
+            }
+            Some(category) => return Some(category),
+            None => (),
+        }
+        if streams.video != 0 {
+            Some(Category::VideoContainer)
+        } else if streams.audio != 0 {
+            Some(Category::AudioOnly)
+        } else if streams.image != 0 {
+            Some(Category::Image)
+        } else if streams.subtitle != 0 {
+            Some(Category::Subtitle)
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn test_classify() {
+    let mut registry = Registry::default();
+    registry.insert("gif", Category::VideoContainer);
+
+    let audio = Streams { audio: 1, ..Streams::default() };
+    let cover = Streams {
+        audio: 1,
+        image: 1,
+        ..Streams::default()
+    };
+    let picture = Streams { image: 1, ..Streams::default() };
+    let subtitle = Streams { subtitle: 1, ..Streams::default() };
+    let video = Streams {
+        video: 1,
+        audio: 2,
+        ..Streams::default()
+    };
+
+    let cases = [("matroska,webm", audio, Some(Category::AudioOnly)),
+                 ("matroska,webm", video, Some(Category::VideoContainer)),
+                 ("mov,mp4,m4a,3gp,3g2,mj2", cover, Some(Category::AudioOnly)),
+                 ("mp3", cover, Some(Category::AudioOnly)),
+                 ("gif", picture, Some(Category::VideoContainer)),
+                 ("png_pipe", picture, Some(Category::Image)),
+                 ("foo,ogg", audio, Some(Category::AudioOnly)),
+                 ("foo,ogg", video, Some(Category::VideoContainer)),
+                 ("unknown", cover, Some(Category::AudioOnly)),
+                 ("unknown", picture, Some(Category::Image)),
+                 ("unknown", subtitle, Some(Category::Subtitle)),
+                 ("unknown", Streams::default(), None)];
+
+    for &(name, streams, expected) in &cases {
+        assert_eq!(registry.classify(name, &streams), expected, "{}", name);
+    }
+}
@@ -0,0 +1,175 @@
+use regex::Regex;
+
+// ReplayGain 2.0 and EBU R128 reference levels in LUFS
+const REPLAYGAIN_REFERENCE: f64 = -18.;
+const R128_REFERENCE: f64 = -23.;
+// Blocks below the absolute gate of EBU R128 are not counted as loudness,
+// so a track measured below it is silent
+const ABSOLUTE_GATE: f64 = -70.;
+
+/// Which tags the computed gain is written as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    ReplayGain,
+    R128,
+}
+
+impl Style {
+    pub fn from_str(s: &str) -> Option<Style> {
+        match s {
+            "replaygain" => Some(Style::ReplayGain),
+            "r128" => Some(Style::R128),
+            _ => None,
+        }
+    }
+    /// The names of the tags that `tags` writes
+    pub fn tag_names(&self) -> &'static [&'static str] {
+        match *self {
+            Style::ReplayGain => {
+                &["REPLAYGAIN_TRACK_GAIN", "REPLAYGAIN_TRACK_PEAK", "REPLAYGAIN_ALBUM_GAIN",
+                  "REPLAYGAIN_ALBUM_PEAK"]
+            }
+            Style::R128 => &["R128_TRACK_GAIN", "R128_ALBUM_GAIN"],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS
+    pub integrated: f64,
+    /// True peak in dBFS
+    pub peak: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gain {
+    pub track: Loudness,
+    /// The loudness of all tracks in the same directory, `None` if only
+    /// some of them are converted
+    pub album: Option<Loudness>,
+}
+
+/// The filter that measures the audio, see `parse_loudness`
+pub const FILTER: &'static str = "ebur128=peak=true";
+
+/// Reads the summary that the `ebur128` filter prints when it is done
+pub fn parse_loudness(stderr: &str) -> Option<Loudness> {
+    fn last(pattern: &str, stderr: &str) -> Option<f64> {
+        let regex = match Regex::new(pattern) {
+            Ok(r) => r,
+            Err(_) => return None,
+        };
+        regex.captures_iter(stderr)
+            .last()
+            .and_then(|c| c.at(1).map(|s| s.to_string()))
+            .and_then(|s| match s.as_str() {
+                // Digital silence
+                "-inf" => Some(-200.),
+                s => s.parse().ok(),
+            })
+    }
+    // Per frame lines also contain "I:", the summary comes last
+    match (last(r"I:\s+(-?[0-9.]+|-inf) LUFS", stderr),
+           last(r"Peak:\s+(-?[0-9.]+|-inf) dBFS", stderr)) {
+        (Some(integrated), Some(peak)) => {
+            Some(Loudness {
+                integrated: integrated,
+                peak: peak,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Combines the loudness of the tracks of an album, given with their
+/// durations. Averages the energy weighted by duration, which is close to
+/// measuring the tracks as one, but ignores gating across tracks. Silent
+/// tracks are left out, as gating would leave them out of the album.
+pub fn album(tracks: &[(Loudness, f64)]) -> Option<Loudness> {
+    let tracks: Vec<(Loudness, f64)> = tracks.iter()
+        .cloned()
+        .filter(|&(l, _)| l.integrated > ABSOLUTE_GATE)
+        .collect();
+    let duration: f64 = tracks.iter().map(|&(_, d)| d).sum();
+    if tracks.len() == 0 || duration <= 0. {
+        return None;
+    }
+    let energy: f64 = tracks.iter()
+        .map(|&(l, d)| d * 10f64.powf(l.integrated / 10.))
+        .sum();
+    Some(Loudness {
+        integrated: 10. * (energy / duration).log10(),
+        peak: tracks.iter().map(|&(l, _)| l.peak).fold(-200., f64::max),
+    })
+}
+
+/// The tags to write, named as in Vorbis comments. ReplayGain gains are
+/// in dB with linear peaks, R128 gains are Q7.8 numbers without peaks.
+pub fn tags(style: Style, gain: &Gain) -> Vec<(String, String)> {
+    match style {
+        Style::ReplayGain => {
+            let db = |l: Loudness| format!("{:.2} dB", REPLAYGAIN_REFERENCE - l.integrated);
+            let peak = |l: Loudness| format!("{:.6}", 10f64.powf(l.peak / 20.));
+            let mut tags = vec![("REPLAYGAIN_TRACK_GAIN".to_string(), db(gain.track)),
+                                ("REPLAYGAIN_TRACK_PEAK".to_string(), peak(gain.track))];
+            if let Some(album) = gain.album {
+                tags.push(("REPLAYGAIN_ALBUM_GAIN".to_string(), db(album)));
+                tags.push(("REPLAYGAIN_ALBUM_PEAK".to_string(), peak(album)));
+            }
+            tags
+        }
+        Style::R128 => {
+            let q78 = |l: Loudness| {
+                let q = ((R128_REFERENCE - l.integrated) * 256.).round();
+                format!("{}", q.max(-32768.).min(32767.) as i64)
+            };
+            let mut tags = vec![("R128_TRACK_GAIN".to_string(), q78(gain.track))];
+            if let Some(album) = gain.album {
+                tags.push(("R128_ALBUM_GAIN".to_string(), q78(album)));
+            }
+            tags
+        }
+    }
+}
+
+#[test]
+fn test_gain() {
+    let stderr = "[Parsed_ebur128_0 @ 0x1] t: 9.9  TARGET:-23 LUFS  M: -20.1 S: -19.8     \
+                  I: -19.9 LUFS       LRA:   5.1 LU  FTPK: -1.2 dBFS  TPK: -0.9 dBFS\n\
+                  [Parsed_ebur128_0 @ 0x1] Summary:\n\n  Integrated loudness:\n    \
+                  I:         -19.6 LUFS\n    Threshold: -29.9 LUFS\n\n  True peak:\n    \
+                  Peak:       -0.3 dBFS\n";
+    let track = parse_loudness(stderr).unwrap();
+    assert_eq!(track,
+               Loudness {
+                   integrated: -19.6,
+                   peak: -0.3,
+               });
+
+    let quiet = Loudness {
+        integrated: -29.6,
+        peak: -6.,
+    };
+    let combined = album(&[(track, 100.), (quiet, 100.)]).unwrap();
+    assert!((combined.integrated - -22.19).abs() < 0.01);
+    assert_eq!(combined.peak, -0.3);
+    let silence = Loudness {
+        integrated: -200.,
+        peak: -200.,
+    };
+    assert_eq!(album(&[(track, 100.), (quiet, 100.), (silence, 100.)]), Some(combined));
+    assert_eq!(album(&[(silence, 100.)]), None);
+
+    let gain = Gain {
+        track: quiet,
+        album: Some(combined),
+    };
+    assert_eq!(tags(Style::ReplayGain, &gain)[0].1, "11.60 dB");
+    assert_eq!(tags(Style::ReplayGain, &gain)[1].1, "0.501187");
+    assert_eq!(tags(Style::R128, &gain)[0].1, "1690");
+    assert_eq!(tags(Style::R128, &gain).len(), 2);
+    let partial = Gain { album: None, ..gain };
+    assert_eq!(tags(Style::ReplayGain, &partial).len(), 2);
+    assert_eq!(tags(Style::R128, &partial).len(), 1);
+}
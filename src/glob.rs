@@ -0,0 +1,207 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+    DoubleStar,
+    DoubleStarSlash,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+    pub source: String,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub pattern: String,
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        "Unterminated character class in pattern"
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {:?}", self.description(), self.pattern)
+    }
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Pattern, Error> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    if chars.get(i + 2) == Some(&'/') {
+                        tokens.push(Token::DoubleStarSlash);
+                        i += 3;
+                    } else {
+                        tokens.push(Token::DoubleStar);
+                        i += 2;
+                    }
+                }
+                '*' => {
+                    tokens.push(Token::Star);
+                    i += 1;
+                }
+                '?' => {
+                    tokens.push(Token::Any);
+                    i += 1;
+                }
+                '[' => {
+                    let (token, end) = try!(parse_class(&chars, i + 1)
+                        .ok_or_else(|| Error { pattern: source.to_string() }));
+                    tokens.push(token);
+                    i = end;
+                }
+                '\\' if i + 1 < chars.len() => {
+                    tokens.push(Token::Char(chars[i + 1]));
+                    i += 2;
+                }
+                c => {
+                    tokens.push(Token::Char(c));
+                    i += 1;
+                }
+            }
+        }
+        Ok(Pattern {
+            tokens: tokens,
+            source: source.to_string(),
+        })
+    }
+
+    pub fn matches(&self, s: &str) -> bool {
+        let chars: Vec<char> = s.chars().collect();
+        match_tokens(&self.tokens, &chars)
+    }
+
+    pub fn matches_path(&self, path: &Path) -> bool {
+        let s: Vec<_> = path.iter().map(|c| c.to_string_lossy()).collect();
+        self.matches(&s.join("/"))
+    }
+
+    /// Matches `relative` the way ignore files do: patterns containing a slash
+    /// are matched against the whole path, others against the file name only.
+    pub fn matches_relative(&self, relative: &Path) -> bool {
+        match self.has_slash() {
+            true => self.matches_path(relative),
+            false => relative.file_name().map_or(false, |name| self.matches_path(Path::new(name))),
+        }
+    }
+
+    pub fn has_slash(&self) -> bool {
+        self.tokens.iter().any(|t| match *t {
+            Token::Char('/') | Token::DoubleStarSlash => true,
+            _ => false,
+        })
+    }
+}
+
+// Returns the class and the index just after the closing bracket
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start;
+    let negated = match chars.get(i) {
+        Some(&'!') | Some(&'^') => {
+            i += 1;
+            true
+        }
+        _ => false,
+    };
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = match chars.get(i) {
+            Some(&']') if !first => {
+                return Some((Token::Class {
+                                 negated: negated,
+                                 ranges: ranges,
+                             },
+                             i + 1))
+            }
+            Some(&c) => c,
+            None => return None,
+        };
+        first = false;
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some(&'-'), Some(&end)) if end != ']' => {
+                ranges.push((c, end));
+                i += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+}
+
+fn match_tokens(tokens: &[Token], s: &[char]) -> bool {
+    let (token, rest) = match tokens.split_first() {
+        Some(t) => t,
+        None => return s.is_empty(),
+    };
+    match *token {
+        Token::Char(c) => s.first() == Some(&c) && match_tokens(rest, &s[1..]),
+        Token::Any => s.first().map_or(false, |&c| c != '/') && match_tokens(rest, &s[1..]),
+        Token::Class { negated, ref ranges } => {
+            match s.first() {
+                Some(&c) if c != '/' => {
+                    let inside = ranges.iter().any(|&(a, b)| a <= c && c <= b);
+                    inside != negated && match_tokens(rest, &s[1..])
+                }
+                _ => false,
+            }
+        }
+        Token::Star => {
+            let segment = s.iter().take_while(|&&c| c != '/').count();
+            (0..segment + 1).any(|i| match_tokens(rest, &s[i..]))
+        }
+        Token::DoubleStar => (0..s.len() + 1).any(|i| match_tokens(rest, &s[i..])),
+        Token::DoubleStarSlash => {
+            match_tokens(rest, s) ||
+            (0..s.len()).filter(|&i| s[i] == '/').any(|i| match_tokens(rest, &s[i + 1..]))
+        }
+    }
+}
+
+#[test]
+fn test_glob() {
+    let cases: &[(&str, &str, bool)] = &[("*.nfo", "movie.nfo", true),
+                                         ("*.nfo", "dir/movie.nfo", false),
+                                         ("**/*.nfo", "dir/movie.nfo", true),
+                                         ("**/*.nfo", "movie.nfo", true),
+                                         ("**", "a/b/c", true),
+                                         ("a/**/c", "a/c", true),
+                                         ("a/**/c", "a/b/b/c", true),
+                                         ("?.jpg", "a.jpg", true),
+                                         ("?.jpg", "ab.jpg", false),
+                                         ("[fp]*.jpg", "folder.jpg", true),
+                                         ("[fp]*.jpg", "poster.jpg", true),
+                                         ("[!fp]*.jpg", "poster.jpg", false),
+                                         ("[a-c]", "b", true),
+                                         ("[a-c]", "d", false),
+                                         ("image/*", "image/jpeg", true),
+                                         ("\\*", "*", true),
+                                         ("@eaDir", "@eaDir", true)];
+
+    for &(pattern, path, expected) in cases {
+        assert_eq!(Pattern::new(pattern).unwrap().matches(path),
+                   expected,
+                   "{} ~ {}",
+                   pattern,
+                   path);
+    }
+    assert!(Pattern::new("[abc").is_err());
+}
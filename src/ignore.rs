@@ -0,0 +1,130 @@
+use glob::Pattern;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAME: &'static str = ".transcodeignore";
+
+/// Rules that apply below INPUT_DIRECTORY before any ignore file, which can
+/// re-include what they skip
+pub const DEFAULT_RULES: &'static str = "# Synology thumbnails\n@eaDir/\n\
+                                         # Sample clips of releases\n[Ss]ample/\n";
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    directory_only: bool,
+    anchored: bool,
+}
+
+/// The rules of one `.transcodeignore` file. Uses the same syntax as
+/// `.gitignore`: `#` comments, `!` re-includes, a trailing `/` only matches
+/// directories and a slash anywhere else anchors the pattern to the directory
+/// of the file.
+#[derive(Debug, Clone)]
+pub struct IgnoreFile {
+    pub dir: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    pub fn parse(dir: &Path, text: &str) -> IgnoreFile {
+        let rules = text.lines().filter_map(parse_line).collect();
+        IgnoreFile {
+            dir: dir.to_path_buf(),
+            rules: rules,
+        }
+    }
+
+    /// Loads the ignore file of `dir`. Missing or unreadable files are
+    /// treated as empty.
+    pub fn load(dir: &Path) -> Option<IgnoreFile> {
+        let mut text = String::new();
+        match File::open(dir.join(FILE_NAME)).and_then(|mut f| f.read_to_string(&mut text)) {
+            Ok(_) => Some(IgnoreFile::parse(dir, &text)),
+            Err(_) => None,
+        }
+    }
+
+    /// Returns `Some(true)` if `path` is ignored, `Some(false)` if it is
+    /// explicitly re-included and `None` if no rule matches.
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = match path.strip_prefix(&self.dir) {
+            Ok(r) => r,
+            Err(_) => return None,
+        };
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| is_dir || !rule.directory_only)
+            .find(|rule| match rule.anchored {
+                true => rule.pattern.matches_path(relative),
+                false => rule.pattern.matches_relative(relative),
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+fn parse_line(line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (negated, line) = match line.starts_with('!') {
+        true => (true, &line[1..]),
+        false => (false, line),
+    };
+    let line = match line.starts_with("\\#") || line.starts_with("\\!") {
+        true => &line[1..],
+        false => line,
+    };
+    let (directory_only, line) = match line.ends_with('/') {
+        true => (true, &line[..line.len() - 1]),
+        false => (false, line),
+    };
+
+    // A leading slash anchors the pattern without being part of it
+    let (anchored, line) = match line.starts_with('/') {
+        true => (true, &line[1..]),
+        false => (false, line),
+    };
+
+    Pattern::new(line).ok().map(|pattern| {
+        Rule {
+            anchored: anchored || pattern.has_slash(),
+            pattern: pattern,
+            negated: negated,
+            directory_only: directory_only,
+        }
+    })
+}
+
+#[test]
+fn test_ignore_file() {
+    let file = IgnoreFile::parse(Path::new("/media"),
+                                 "# Synology thumbnails\n@eaDir/\n*.sample.*\n!keep.sample.mkv\n\
+                                  /Trash\nextras/*.mkv\n");
+
+    let cases = [("/media/@eaDir", true, Some(true)),
+                 ("/media/show/@eaDir", true, Some(true)),
+                 ("/media/show/@eaDir", false, None),
+                 ("/media/a.sample.mkv", false, Some(true)),
+                 ("/media/keep.sample.mkv", false, Some(false)),
+                 ("/media/Trash", true, Some(true)),
+                 ("/media/show/Trash", true, None),
+                 ("/media/extras/a.mkv", false, Some(true)),
+                 ("/media/show/extras/a.mkv", false, None),
+                 ("/media/movie.mkv", false, None),
+                 ("/other/a.sample.mkv", false, None)];
+
+    for &(path, is_dir, expected) in &cases {
+        assert_eq!(file.matched(Path::new(path), is_dir), expected, "{}", path);
+    }
+
+    let defaults = IgnoreFile::parse(Path::new("/media"), DEFAULT_RULES);
+    assert_eq!(defaults.matched(Path::new("/media/show/@eaDir"), true), Some(true));
+    assert_eq!(defaults.matched(Path::new("/media/movie/Sample"), true), Some(true));
+    assert_eq!(defaults.matched(Path::new("/media/movie/sample.mkv"), false), None);
+}
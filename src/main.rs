@@ -2,20 +2,38 @@ pub mod args;
 pub mod codecs;
 
 pub mod check_file;
+pub mod collision;
+pub mod companion;
 pub mod constants;
 pub mod conversion;
+pub mod cover;
+pub mod crf;
+pub mod cue;
 pub mod error;
 pub mod ffmpeg;
 pub mod ffprobe;
+pub mod filter;
+pub mod formats;
+pub mod gain;
+pub mod glob;
+pub mod ignore;
+pub mod metadata;
 pub mod path;
+pub mod probe_cache;
 pub mod progress;
+pub mod quality;
 pub mod regexreader;
+pub mod report;
+pub mod sniff;
 pub mod source;
+pub mod streams;
 pub mod strings;
 pub mod table;
+pub mod tagmap;
 pub mod target;
 pub mod time;
 pub mod utils;
+pub mod verify;
 extern crate getopts;
 extern crate regex;
 extern crate rustc_serialize;
@@ -43,34 +61,120 @@ pub fn main() {
 }
 
 pub fn run() -> Result<(), error::Error> {
-    let args = try!(args::Args::from_env());
+    let mut args = try!(args::Args::from_env());
+    if let Some(ref file) = args.format_registry {
+        try!(args.discovery.formats.load(Path::new(file)));
+    }
 
+    // Used by `{profile}` in tag templates
+    let profile = args.format.clone().unwrap_or_else(|| "default".to_string());
     let codec = match codecs::get_container(args.format) {
         Ok(c) => c,
         Err(e) => return Err(error::Error::FormatError(e))
     };
 
 
-    let (sources, bads) = try!(source::Sources::from_paths(args.paths, &args.source_dir));
-    let (conversions, skipped) = try!(conversion::Conversions::from_sources(sources,
-                                                                            &args.target_dir, codec));
+    let mut cache = match (args.probe_cache, probe_cache::default_path()) {
+        (true, Some(path)) => probe_cache::ProbeCache::load(path),
+        _ => probe_cache::ProbeCache::disabled(),
+    };
+    let discovery = source::Sources::from_paths(args.paths,
+                                                &args.source_dir,
+                                                &args.discovery,
+                                                &mut cache);
+    // Saved even when the discovery failed, as what was probed still
+    // holds. A cache that cannot be written only makes the next run slower.
+    cache.prune();
+    if let Err(e) = cache.save() {
+        error::print_error(&e.into());
+        println!("");
+    }
+    let discovery = try!(discovery);
+    let source::Discovery { sources, bads, filtered, problems } = discovery;
+    let (mut conversions, skipped, collisions) =
+        try!(conversion::Conversions::from_sources(sources,
+                                                   &args.target_dir,
+                                                   codec,
+                                                   &args.streams,
+                                                   args.covers,
+                                                   args.collisions));
+    conversions.set_metadata(&args.metadata, &profile);
+    if args.gain.is_some() {
+        try!(conversions.check_gain_tags());
+    }
+
+    // Files next to an album split into tracks belong to no single track
+    let media: Vec<(PathBuf, PathBuf)> = conversions.iter()
+        .map(|c| (&c.source, &c.target.path))
+        .chain(skipped.iter().map(|s| (&s.source, &s.target)))
+        .filter(|&(source, _)| source.track.is_none())
+        .map(|(source, target)| (source.path.path.clone(), target.clone()))
+        .collect();
+    let (companions, bads) = try!(companion::Companions::plan(bads,
+                                                              &media,
+                                                              &args.target_dir,
+                                                              args.companions,
+                                                              &args.companion_selector,
+                                                              args.collisions));
     print_bads(&bads);
 
+    print_problems(&problems);
+
+    print_filtered(&filtered);
+
     print_skipped(skipped.as_slice());
 
-    if conversions.len() == 0 {
+    let partial_albums = conversion::partial_albums(&skipped);
+    if args.gain.is_some() {
+        print_partial_albums(&partial_albums, &conversions);
+    }
+
+    let collisions: Vec<collision::Collision> =
+        collisions.into_iter().chain(companions.collisions.iter().cloned()).collect();
+    print_collisions(collisions.as_slice(), &args.source_dir, &args.target_dir);
+
+    if conversions.len() == 0 && companions.len() == 0 {
         return Err(error::Error::NoSourcesError);
     }
 
+    print_companions(&companions);
+
+    print_dropped_tags(&conversions, args.gain);
+
     print_conversions(&conversions, &args.target_dir);
 
+    let measurer = quality::Measurer::new(args.quality.clone());
+    if args.quality.metrics().contains(&quality::Metric::Vmaf) && !measurer.vmaf_available {
+        println!("ffmpeg is built without libvmaf, VMAF will not be measured");
+        println!("");
+    }
+
     let mut fail = false;
     if utils::prompt_continue() {
         println!("");
-        conversions.convert(args.dry_run, |err| {
+        companions.transfer(args.dry_run, |err| {
             fail = true;
             error::print_error(&err.into())
-        })
+        });
+        let conversions = conversions.convert(args.dry_run,
+                                              args.verify,
+                                              &args.discovery.formats,
+                                              &measurer,
+                                              args.gain,
+                                              &partial_albums,
+                                              |err| {
+                                                  fail = true;
+                                                  error::print_error(&err.into())
+                                              });
+        let measured = conversions.iter()
+            .any(|c| c.crf.is_some() || c.loudness.is_some() || c.gain.is_some());
+        if (measurer.is_enabled() || measured) && !args.dry_run {
+            conversions.print_results(&measurer.metrics);
+            println!("");
+        }
+        if let Some(ref file) = args.report {
+            try!(report::write(Path::new(file), &conversions));
+        }
     }
     if fail {
         return Err(error::Error::AtLeastOneItemFailed)
@@ -90,13 +194,106 @@ fn print_bads(paths: &[source::BasedPath]) {
     println!("");
 }
 
-fn print_skipped(paths: &[PathBuf]) {
-    if paths.len() == 0 {
+fn print_problems(problems: &[source::Problem]) {
+    if problems.len() == 0 {
+        return;
+    }
+    println!("Problems (these paths are skipped):");
+    for problem in problems {
+        println!("      {}: {}",
+                 problem.path.relative().to_string_lossy(),
+                 problem.error.reason());
+    }
+    println!("");
+}
+
+fn print_filtered(sources: &[source::Source]) {
+    if sources.len() == 0 {
+        return;
+    }
+    println!("Skipping files outside the duration limits:");
+    for source in sources {
+        println!("      {} ({:.0} s)",
+                 source.path.relative().to_string_lossy(),
+                 source.ffprobe.duration);
+    }
+    println!("");
+}
+
+fn print_skipped(skipped: &[conversion::Skipped]) {
+    if skipped.len() == 0 {
         return;
     }
     println!("Skipping existing targets:");
-    for path in paths.into_iter() {
-        println!("      {}", path.to_string_lossy());
+    for s in skipped.into_iter() {
+        println!("      {}", s.target.to_string_lossy());
+    }
+    println!("");
+}
+
+fn print_partial_albums(partial_albums: &[PathBuf], conversions: &conversion::Conversions) {
+    let partial: Vec<&PathBuf> = partial_albums.iter()
+        .filter(|&dir| conversions.iter().any(|c| c.source.path.path.parent() == Some(dir.as_path())))
+        .collect();
+    if partial.len() == 0 {
+        return;
+    }
+    println!("Leaving out album gain of directories with existing targets:");
+    for dir in partial {
+        println!("      {}", dir.to_string_lossy());
+    }
+    println!("");
+}
+
+fn print_companions(companions: &companion::Companions) {
+    if companions.existing.len() != 0 {
+        println!("Skipping existing companion files:");
+        for companion in &companions.existing {
+            println!("      {}", companion.target.to_string_lossy());
+        }
+        println!("");
+    }
+    if companions.len() == 0 {
+        return;
+    }
+    println!("{} companion files:", companions.policy.verb());
+    for companion in &companions.companions {
+        println!("      {} -> {}",
+                 companion.source.relative().to_string_lossy(),
+                 companion.target.to_string_lossy());
+    }
+    println!("");
+}
+
+/// Sources and targets are shown relative to their directories
+fn print_collisions(collisions: &[collision::Collision], source_dir: &str, target_dir: &str) {
+    if collisions.len() == 0 {
+        return;
+    }
+    let base = |dir: &str| path::normalize(Path::new(dir)).unwrap_or(PathBuf::from(dir));
+    let (source_dir, target_dir) = (base(source_dir), base(target_dir));
+    println!("Renaming colliding targets:");
+    for collision in collisions {
+        println!("      {} -> {}",
+                 path::find_relative(&collision.source, &source_dir).to_string_lossy(),
+                 path::find_relative(&collision.resolved, &target_dir).to_string_lossy());
+    }
+    println!("");
+}
+
+fn print_dropped_tags(conversions: &conversion::Conversions, gain: Option<gain::Style>) {
+    let dropped: Vec<(&conversion::Conversion, Vec<String>)> = conversions.iter()
+        .map(|c| (c, c.dropped_tags(gain)))
+        .filter(|&(_, ref tags)| tags.len() != 0)
+        .collect();
+    if dropped.len() == 0 {
+        return;
+    }
+    println!("Dropping tags the target format cannot hold:");
+    for (con, tags) in dropped {
+        println!("      {}: {}",
+                 con.source.path.relative().to_string_lossy(),
+                 tags.join(", "));
     }
     println!("");
 }
@@ -112,10 +309,30 @@ fn print_conversions(conversions: &conversion::Conversions, dir: &str) {
         println!("Converting: ");
     }
 
+    if let Some((metric, score)) = conversions.iter()
+        .next()
+        .and_then(|c| c.target.codec.crf_target()) {
+        println!("CRF is searched for each file to reach {} {}", metric.name(), score);
+    }
     for con in conversions.iter() {
-        println!("{: >4}: {}",
+        let estimate = match con.source.ffprobe.duration_source.is_estimate() {
+            true => " (estimated duration)",
+            false => "",
+        };
+        let bitrate = match (con.target.codec.passes(), con.target.codec.video_bitrate()) {
+            (2, Some(bitrate)) => format!(" ({} kbit/s, two passes)", bitrate),
+            _ => String::new(),
+        };
+        let track = match con.source.track {
+            Some(ref track) => format!(" (track {})", track.number),
+            None => String::new(),
+        };
+        println!("{: >4}: {}{}{}{}",
                  con.id,
-                 con.source.path.relative().to_string_lossy());
+                 con.source.path.relative().to_string_lossy(),
+                 track,
+                 estimate,
+                 bitrate);
     }
     println!("");
 }
@@ -0,0 +1,168 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+/// What happens to the metadata of the source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Copies the global metadata. Stream metadata follows the streams.
+    Copy,
+    /// Drops the global and stream metadata, including stream languages,
+    /// and the version of the muxer
+    Strip,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Copy
+    }
+}
+
+impl Policy {
+    pub fn from_str(s: &str) -> Option<Policy> {
+        match s {
+            "copy" => Some(Policy::Copy),
+            "strip" => Some(Policy::Strip),
+            _ => None,
+        }
+    }
+    pub fn to_ffmpeg_args(&self) -> Vec<OsString> {
+        let args: &[&str] = match *self {
+            Policy::Copy => &["-map_metadata", "0"],
+            Policy::Strip => &["-map_metadata", "-1", "-map_metadata:s", "-1", "-fflags", "+bitexact"],
+        };
+        args.iter().map(OsString::from).collect()
+    }
+}
+
+/// Whether the chapters of the source are carried over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chapters {
+    Keep,
+    Drop,
+}
+
+impl Default for Chapters {
+    fn default() -> Self {
+        Chapters::Keep
+    }
+}
+
+impl Chapters {
+    pub fn from_str(s: &str) -> Option<Chapters> {
+        match s {
+            "keep" => Some(Chapters::Keep),
+            "drop" => Some(Chapters::Drop),
+            _ => None,
+        }
+    }
+    pub fn to_ffmpeg_args(&self) -> Vec<OsString> {
+        let input = match *self {
+            Chapters::Keep => "0",
+            Chapters::Drop => "-1",
+        };
+        vec!["-map_chapters".into(), input.into()]
+    }
+}
+
+/// What `{variable}`s in a template are replaced with
+pub struct Variables<'a> {
+    /// The path of the source
+    pub source: &'a Path,
+    /// The `--format` the target is converted with
+    pub profile: &'a str,
+}
+
+const VARIABLES: &'static [&'static str] = &["name", "stem", "profile"];
+
+impl<'a> Variables<'a> {
+    fn get(&self, name: &str) -> String {
+        let part = match name {
+            "name" => self.source.file_name(),
+            "stem" => self.source.file_stem(),
+            _ => return self.profile.to_string(),
+        };
+        part.map_or(String::new(), |p| p.to_string_lossy().into_owned())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Variable(String),
+}
+
+/// A tag value with `{name}`, `{stem}` or `{profile}` in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template(Vec<Part>);
+
+impl Template {
+    pub fn parse(s: &str) -> Option<Template> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(n) => start + n,
+                None => return None,
+            };
+            let name = &rest[start + 1..end];
+            if !VARIABLES.contains(&name) {
+                return None;
+            }
+            parts.push(Part::Text(rest[..start].to_string()));
+            parts.push(Part::Variable(name.to_string()));
+            rest = &rest[end + 1..];
+        }
+        parts.push(Part::Text(rest.to_string()));
+        Some(Template(parts))
+    }
+    pub fn expand(&self, variables: &Variables) -> String {
+        self.0
+            .iter()
+            .map(|part| match *part {
+                Part::Text(ref t) => t.clone(),
+                Part::Variable(ref v) => variables.get(v),
+            })
+            .collect()
+    }
+}
+
+/// Parses `KEY=TEMPLATE`, e.g. `comment=converted by transcode {profile}`
+pub fn parse_tag(s: &str) -> Option<(String, Template)> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next().and_then(Template::parse)) {
+        (Some(key), Some(template)) if !key.is_empty() => Some((key.to_string(), template)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub policy: Policy,
+    /// Tags set on every target, after the metadata of the source
+    pub tags: Vec<(String, Template)>,
+}
+
+impl Options {
+    pub fn tags(&self, variables: &Variables) -> Vec<(String, String)> {
+        self.tags
+            .iter()
+            .map(|&(ref key, ref template)| (key.clone(), template.expand(variables)))
+            .collect()
+    }
+}
+
+#[test]
+fn test_template() {
+    let (key, template) = parse_tag("comment=converted by transcode {profile} from {name}").unwrap();
+    let variables = Variables {
+        source: Path::new("/films/Heat (1995).avi"),
+        profile: "mkv,h264,18,normal,opus,192",
+    };
+    assert_eq!(key, "comment");
+    assert_eq!(template.expand(&variables),
+               "converted by transcode mkv,h264,18,normal,opus,192 from Heat (1995).avi");
+    assert_eq!(Template::parse("{stem}").unwrap().expand(&variables), "Heat (1995)");
+    assert!(parse_tag("comment={unknown}").is_none());
+    assert!(parse_tag("comment={stem").is_none());
+    assert!(parse_tag("=value").is_none());
+}
@@ -1,39 +1,170 @@
 use std::env::current_dir;
+use std::error::Error as StdError;
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs::{ReadDir, create_dir_all};
 use std::io;
 use std::path::{Path, PathBuf, Component};
 use utils::common_prefix;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymlinkPolicy {
+    Follow,
+    Skip,
+    Error,
+}
+
+impl Default for SymlinkPolicy {
+    fn default() -> Self {
+        SymlinkPolicy::Follow
+    }
+}
+
+impl SymlinkPolicy {
+    pub fn from_str(s: &str) -> Option<SymlinkPolicy> {
+        match s {
+            "follow" => Some(SymlinkPolicy::Follow),
+            "skip" => Some(SymlinkPolicy::Skip),
+            "error" => Some(SymlinkPolicy::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WalkOptions {
+    pub symlinks: SymlinkPolicy,
+    pub one_file_system: bool,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    ReadDir(io::Error),
+    Metadata(io::Error),
+    Symlink,
+    Loop,
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::ReadDir(_) => "Could not read directory",
+            Error::Metadata(_) => "Could not read file metadata",
+            Error::Symlink => "Symbolic links are not allowed",
+            Error::Loop => "Directory links back to one of its parents (symlink loop)",
+        }
+    }
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::ReadDir(ref e) |
+            Error::Metadata(ref e) => Some(e),
+            Error::Symlink | Error::Loop => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ReadDir(ref e) |
+            Error::Metadata(ref e) => write!(f, "{}: {}", self.description(), e),
+            Error::Symlink | Error::Loop => write!(f, "{}", self.description()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum PathType {
     Directory(PathBuf),
     File(PathBuf),
+    Error { path: PathBuf, error: Error },
 }
 
-pub struct PathIterator(Option<ReadDir>);
+pub struct PathIterator {
+    dir: PathBuf,
+    entries: Option<ReadDir>,
+    error: Option<io::Error>,
+    symlinks: SymlinkPolicy,
+}
 
 impl PathIterator {
     pub fn new<T: AsRef<Path>>(path: T) -> Self {
-        PathIterator(path.as_ref().read_dir().ok())
+        PathIterator::with_symlinks(path, SymlinkPolicy::default())
+    }
+    pub fn with_symlinks<T: AsRef<Path>>(path: T, symlinks: SymlinkPolicy) -> Self {
+        let dir = path.as_ref().to_path_buf();
+        let (entries, error) = match dir.read_dir() {
+            Ok(entries) => (Some(entries), None),
+            Err(e) => (None, Some(e)),
+        };
+        PathIterator {
+            dir: dir,
+            entries: entries,
+            error: error,
+            symlinks: symlinks,
+        }
     }
 }
 impl Iterator for PathIterator {
     type Item = PathType;
     fn next(&mut self) -> Option<Self::Item> {
-        let PathIterator(ref mut iterator) = *self;
+        if let Some(e) = self.error.take() {
+            return Some(PathType::Error {
+                path: self.dir.clone(),
+                error: Error::ReadDir(e),
+            });
+        }
         loop {
-            let path = match iterator.as_mut().and_then(|i| i.next()) {
-                    Some(Ok(entry)) => entry,
-                    Some(Err(_)) => continue,
-                    None => {
-                        iterator.take();
-                        return None;
+            let entry = match self.entries.as_mut().and_then(|i| i.next()) {
+                Some(Ok(entry)) => entry,
+                Some(Err(e)) => {
+                    self.entries.take();
+                    return Some(PathType::Error {
+                        path: self.dir.clone(),
+                        error: Error::ReadDir(e),
+                    });
+                }
+                None => {
+                    self.entries.take();
+                    return None;
+                }
+            };
+            let path = entry.path();
+
+            // DirEntry::file_type does not follow symlinks, unlike Path::is_dir
+            let is_dir = match entry.file_type() {
+                Ok(t) if t.is_symlink() => {
+                    match self.symlinks {
+                        SymlinkPolicy::Skip => continue,
+                        SymlinkPolicy::Error => {
+                            return Some(PathType::Error {
+                                path: path,
+                                error: Error::Symlink,
+                            })
+                        }
+                        SymlinkPolicy::Follow => {
+                            match path.metadata() {
+                                Ok(m) => m.is_dir(),
+                                Err(e) => {
+                                    return Some(PathType::Error {
+                                        path: path,
+                                        error: Error::Metadata(e),
+                                    })
+                                }
+                            }
+                        }
                     }
                 }
-                .path();
+                Ok(t) => t.is_dir(),
+                Err(e) => {
+                    return Some(PathType::Error {
+                        path: path,
+                        error: Error::Metadata(e),
+                    })
+                }
+            };
 
-            return Some(match path.is_dir() {
+            return Some(match is_dir {
                 true => PathType::Directory(path),
                 false => PathType::File(path),
             });
@@ -43,14 +174,38 @@ impl Iterator for PathIterator {
 
 pub struct RecursivePathIterator {
     iterator: PathIterator,
-    tail: Vec<PathBuf>,
+    /// Directories left to walk, each with the device and inode of itself
+    /// and its parents
+    tail: Vec<(PathBuf, Vec<(u64, u64)>)>,
+    /// The device and inode of the directory being walked and its parents
+    ancestors: Vec<(u64, u64)>,
+    prunable: bool,
+    options: WalkOptions,
+    device: Option<u64>,
 }
 
 impl RecursivePathIterator {
     pub fn new<T: AsRef<Path>>(path: T) -> Self {
+        RecursivePathIterator::with_options(path, WalkOptions::default())
+    }
+
+    pub fn with_options<T: AsRef<Path>>(path: T, options: WalkOptions) -> Self {
+        let id = file_id(path.as_ref());
         RecursivePathIterator {
-            iterator: PathIterator::new(path),
+            iterator: PathIterator::with_symlinks(path, options.symlinks),
             tail: Vec::new(),
+            prunable: false,
+            options: options,
+            device: id.map(|(device, _)| device),
+            ancestors: id.into_iter().collect(),
+        }
+    }
+
+    /// Skips the contents of the directory that was just returned by `next`.
+    pub fn prune(&mut self) {
+        if self.prunable {
+            self.tail.pop();
+            self.prunable = false;
         }
     }
 }
@@ -58,24 +213,53 @@ impl RecursivePathIterator {
 impl Iterator for RecursivePathIterator {
     type Item = PathType;
     fn next(&mut self) -> Option<Self::Item> {
-        let &mut RecursivePathIterator { ref mut iterator, ref mut tail } = self;
-
         loop {
-            if let Some(path) = iterator.next() {
-                if let PathType::Directory(p) = path {
-                    tail.push(p);
-                    continue;
+            if let Some(path) = self.iterator.next() {
+                self.prunable = false;
+                if let PathType::Directory(ref p) = path {
+                    // Directories are identified by device and inode. Only a
+                    // directory that is also one of its own parents is a
+                    // loop, the same directory reached by two symlinks is not.
+                    let mut ancestors = self.ancestors.clone();
+                    if let Some((device, inode)) = file_id(p) {
+                        if self.options.one_file_system && Some(device) != self.device {
+                            continue;
+                        }
+                        if ancestors.contains(&(device, inode)) {
+                            return Some(PathType::Error {
+                                path: p.clone(),
+                                error: Error::Loop,
+                            });
+                        }
+                        ancestors.push((device, inode));
+                    }
+                    self.tail.push((p.clone(), ancestors));
+                    self.prunable = true;
                 }
                 return Some(path);
             }
-            return tail.pop().map(|path| {
-                *iterator = PathIterator::new(&path);
-                PathType::Directory(path)
-            });
+            match self.tail.pop() {
+                Some((path, ancestors)) => {
+                    self.iterator = PathIterator::with_symlinks(&path, self.options.symlinks);
+                    self.ancestors = ancestors;
+                }
+                None => return None,
+            }
         }
     }
 }
 
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    path.metadata().ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_: &Path) -> Option<(u64, u64)> {
+    None
+}
+
 pub fn find_relative(target: &Path, base: &Path) -> PathBuf {
     let target: Vec<_> = target.iter().collect();
     let base: Vec<_> = base.iter().collect();
@@ -126,3 +310,44 @@ pub fn normalize(path: &Path) -> Result<PathBuf, io::Error> {
         .map(|x| x.as_os_str())
         .collect())
 }
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_loop() {
+    use std::fs::{File, create_dir, remove_dir_all};
+    use std::os::unix::fs::symlink;
+
+    let root = ::std::env::temp_dir().join(format!("transcode-loop-{}", ::std::process::id()));
+    let _ = remove_dir_all(&root);
+    create_dir(&root).unwrap();
+    create_dir(root.join("a")).unwrap();
+    File::create(root.join("a").join("movie.avi")).unwrap();
+    symlink(&root, root.join("a").join("up")).unwrap();
+    // Two links to the same directory are no loop
+    create_dir(root.join("b")).unwrap();
+    File::create(root.join("b").join("song.flac")).unwrap();
+    symlink(root.join("b"), root.join("a").join("b1")).unwrap();
+    symlink(root.join("b"), root.join("a").join("b2")).unwrap();
+
+    let walk = |symlinks| {
+        let options = WalkOptions {
+            symlinks: symlinks,
+            one_file_system: false,
+        };
+        let (mut files, mut errors) = (0, 0);
+        for entry in RecursivePathIterator::with_options(&root, options) {
+            match entry {
+                PathType::File(_) => files += 1,
+                PathType::Error { .. } => errors += 1,
+                PathType::Directory(_) => (),
+            }
+        }
+        (files, errors)
+    };
+
+    assert_eq!(walk(SymlinkPolicy::Follow), (4, 1));
+    assert_eq!(walk(SymlinkPolicy::Skip), (2, 0));
+    assert_eq!(walk(SymlinkPolicy::Error), (2, 3));
+
+    remove_dir_all(&root).unwrap();
+}
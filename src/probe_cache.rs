@@ -0,0 +1,311 @@
+use rustc_serialize::json::{self, Json, ToJson};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Bump when the ffprobe invocation changes, so old outputs are dropped
+const VERSION: u64 = 4;
+
+/// Identifies one version of a file. A cached output is only used while the
+/// size and modification time of the file are unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stamp {
+    pub size: u64,
+    pub mtime: u64,
+    pub mtime_nsec: u64,
+}
+
+impl Stamp {
+    pub fn of(path: &Path) -> Option<Stamp> {
+        let metadata = match path.metadata() {
+            Ok(m) => m,
+            Err(_) => return None,
+        };
+        let mtime = match metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+            Some(t) => t,
+            None => return None,
+        };
+        Some(Stamp {
+            size: metadata.len(),
+            mtime: mtime.as_secs(),
+            mtime_nsec: mtime.subsec_nanos() as u64,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    stamp: Stamp,
+    outputs: BTreeMap<String, Option<String>>,
+}
+
+/// Raw ffprobe outputs keyed by canonical path. Outputs are stored unparsed,
+/// so changes to the parser never require invalidating the cache.
+#[derive(Debug)]
+pub struct ProbeCache {
+    file: Option<PathBuf>,
+    entries: HashMap<String, Entry>,
+    dirty: bool,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    CreateDir { path: PathBuf, error: io::Error },
+    Write { path: PathBuf, error: io::Error },
+    Encode { path: PathBuf, error: json::EncoderError },
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::CreateDir { .. } => "Could not create probe cache directory",
+            Error::Write { .. } => "Could not write probe cache",
+            Error::Encode { .. } => "Could not encode probe cache",
+        }
+    }
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::CreateDir { ref error, .. } |
+            Error::Write { ref error, .. } => Some(error),
+            Error::Encode { ref error, .. } => Some(error),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::CreateDir { ref path, .. } |
+            Error::Write { ref path, .. } |
+            Error::Encode { ref path, .. } => write!(f, "{}: {:?}", self.description(), path),
+        }
+    }
+}
+
+/// `$XDG_CACHE_HOME/transcode/probe-cache.json`, falling back to `~/.cache`
+pub fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            match env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".cache"),
+                None => return None,
+            }
+        }
+    };
+    Some(base.join("transcode").join("probe-cache.json"))
+}
+
+impl ProbeCache {
+    pub fn disabled() -> Self {
+        ProbeCache {
+            file: None,
+            entries: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// Loads `file`. A missing, unreadable or outdated cache starts out empty.
+    pub fn load(file: PathBuf) -> Self {
+        let mut text = String::new();
+        let entries = match File::open(&file).and_then(|mut f| f.read_to_string(&mut text)) {
+            Ok(_) => Json::from_str(&text).ok().map_or(HashMap::new(), |j| parse_entries(&j)),
+            Err(_) => HashMap::new(),
+        };
+        ProbeCache {
+            file: Some(file),
+            entries: entries,
+            dirty: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Returns `Some(output)` on a hit, where `output` itself is `None` when
+    /// the probe found nothing.
+    pub fn get(&self, path: &Path, stamp: &Stamp, kind: &str) -> Option<Option<String>> {
+        let key = match key(path) {
+            Some(k) => k,
+            None => return None,
+        };
+        self.entries
+            .get(&key)
+            .filter(|entry| entry.stamp == *stamp)
+            .and_then(|entry| entry.outputs.get(kind).cloned())
+    }
+
+    pub fn insert(&mut self, path: &Path, stamp: Stamp, kind: &str, output: Option<String>) {
+        if !self.is_enabled() {
+            return;
+        }
+        let key = match key(path) {
+            Some(k) => k,
+            None => return,
+        };
+        let entry = self.entries.entry(key).or_insert_with(|| {
+            Entry {
+                stamp: stamp.clone(),
+                outputs: BTreeMap::new(),
+            }
+        });
+        if entry.stamp != stamp {
+            entry.stamp = stamp;
+            entry.outputs.clear();
+        }
+        entry.outputs.insert(kind.to_string(), output);
+        self.dirty = true;
+    }
+
+    /// Drops the entries of files that are gone or have changed since they
+    /// were probed
+    pub fn prune(&mut self) {
+        let count = self.entries.len();
+        self.entries.retain(|path, entry| Stamp::of(Path::new(path)).as_ref() == Some(&entry.stamp));
+        if self.entries.len() != count {
+            self.dirty = true;
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let file = match self.file {
+            Some(ref f) if self.dirty => f,
+            _ => return Ok(()),
+        };
+        if let Some(dir) = file.parent() {
+            try!(fs::create_dir_all(dir).map_err(|e| {
+                Error::CreateDir {
+                    path: dir.to_path_buf(),
+                    error: e,
+                }
+            }));
+        }
+
+        // Write next to the cache and rename, so an interrupted run never
+        // leaves a truncated cache behind
+        let tmp = file.with_extension("json.tmp");
+        let text = try!(json::encode(&self.to_json()).map_err(|e| {
+            Error::Encode {
+                path: file.clone(),
+                error: e,
+            }
+        }));
+        File::create(&tmp)
+            .and_then(|mut f| f.write_all(text.as_bytes()))
+            .and_then(|_| fs::rename(&tmp, file))
+            .map_err(|e| {
+                Error::Write {
+                    path: file.clone(),
+                    error: e,
+                }
+            })
+    }
+}
+
+impl ToJson for ProbeCache {
+    fn to_json(&self) -> Json {
+        let entries: BTreeMap<String, Json> = self.entries
+            .iter()
+            .map(|(path, entry)| {
+                let mut object = BTreeMap::new();
+                object.insert("size".to_string(), entry.stamp.size.to_json());
+                object.insert("mtime".to_string(), entry.stamp.mtime.to_json());
+                object.insert("mtime_nsec".to_string(), entry.stamp.mtime_nsec.to_json());
+                object.insert("outputs".to_string(), entry.outputs.to_json());
+                (path.clone(), Json::Object(object))
+            })
+            .collect();
+
+        let mut root = BTreeMap::new();
+        root.insert("version".to_string(), VERSION.to_json());
+        root.insert("entries".to_string(), Json::Object(entries));
+        Json::Object(root)
+    }
+}
+
+/// Files are cached by canonical path, so one reached through a symlink
+/// shares the entry of its target
+fn key(path: &Path) -> Option<String> {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    path.to_str().map(|k| k.to_string())
+}
+
+fn parse_entries(json: &Json) -> HashMap<String, Entry> {
+    if json.find("version").and_then(|v| v.as_u64()) != Some(VERSION) {
+        return HashMap::new();
+    }
+    let entries = match json.find("entries").and_then(|e| e.as_object()) {
+        Some(e) => e,
+        None => return HashMap::new(),
+    };
+
+    entries.iter()
+        .filter_map(|(path, entry)| {
+            let stamp = match (entry.find("size").and_then(|j| j.as_u64()),
+                               entry.find("mtime").and_then(|j| j.as_u64()),
+                               entry.find("mtime_nsec").and_then(|j| j.as_u64())) {
+                (Some(size), Some(mtime), Some(mtime_nsec)) => {
+                    Stamp {
+                        size: size,
+                        mtime: mtime,
+                        mtime_nsec: mtime_nsec,
+                    }
+                }
+                _ => return None,
+            };
+            let outputs = match entry.find("outputs").and_then(|j| j.as_object()) {
+                Some(o) => o,
+                None => return None,
+            };
+            let outputs = outputs.iter()
+                .map(|(kind, output)| (kind.clone(), output.as_string().map(|s| s.to_string())))
+                .collect();
+            Some((path.clone(),
+                  Entry {
+                      stamp: stamp,
+                      outputs: outputs,
+                  }))
+        })
+        .collect()
+}
+
+#[test]
+fn test_roundtrip() {
+    let stamp = Stamp {
+        size: 10,
+        mtime: 20,
+        mtime_nsec: 30,
+    };
+    let mut cache = ProbeCache {
+        file: Some(PathBuf::from("/nonexistent/cache.json")),
+        entries: HashMap::new(),
+        dirty: false,
+    };
+    cache.insert(Path::new("/a.mkv"), stamp.clone(), "probe", Some("{}".to_string()));
+    cache.insert(Path::new("/b.txt"), stamp.clone(), "probe", None);
+
+    let entries = parse_entries(&cache.to_json());
+    assert_eq!(entries.len(), 2);
+
+    let mut loaded = ProbeCache {
+        file: None,
+        entries: entries,
+        dirty: false,
+    };
+    assert_eq!(loaded.get(Path::new("/a.mkv"), &stamp, "probe"),
+               Some(Some("{}".to_string())));
+    assert_eq!(loaded.get(Path::new("/b.txt"), &stamp, "probe"), Some(None));
+
+    let touched = Stamp { mtime: 21, ..stamp };
+    assert_eq!(loaded.get(Path::new("/a.mkv"), &touched, "probe"), None);
+
+    // Neither file exists
+    loaded.prune();
+    assert!(loaded.entries.is_empty());
+}
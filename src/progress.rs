@@ -6,6 +6,7 @@ use std::time::Instant;
 #[derive(Debug, Clone)]
 pub struct Pending {
     target: f64,
+    estimate: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -13,6 +14,8 @@ pub struct Progress {
     begin: Instant,
     target: f64,
     processed: f64,
+    /// The target is based on an estimated duration
+    estimate: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -96,6 +99,7 @@ impl Pending {
             begin: Instant::now(),
             processed: 0.,
             target: self.target,
+            estimate: self.estimate,
         }
     }
 }
@@ -123,8 +127,11 @@ impl From<Fail> for Status {
 
 
 impl Status {
-    pub fn new(target: f64) -> Self {
-        Status::Pending(Pending { target: target })
+    pub fn new(target: f64, estimate: bool) -> Self {
+        Status::Pending(Pending {
+            target: target,
+            estimate: estimate,
+        })
     }
     pub fn start(&mut self) {
         *self = if let Status::Pending(ref s) = *self {
@@ -161,6 +168,13 @@ impl Status {
             Status::Pending(Pending { target, .. }) => target,
         }
     }
+    pub fn is_estimate(&self) -> bool {
+        match *self {
+            Status::Pending(Pending { estimate, .. }) |
+            Status::Progress(Progress { estimate, .. }) => estimate,
+            Status::Done(_) | Status::Fail(_) => false,
+        }
+    }
     pub fn merge_begin(&self, &other: &Instant) -> Instant {
         match *self {
             Status::Pending(Pending { .. }) => other,
@@ -180,6 +194,7 @@ impl Status {
     pub fn merge(&self, other: &Self) -> Self {
         let target = other.get_target() + self.get_target();
         let processed = other.get_processed() + self.get_processed();
+        let estimate = other.is_estimate() || self.is_estimate();
 
         match *self {
             Status::Progress(Progress { begin, .. }) => {
@@ -187,12 +202,19 @@ impl Status {
                         begin: other.merge_begin(&begin),
                         processed: processed,
                         target: target,
+                        estimate: estimate,
                     }
                     .into()
             }
             Status::Pending(_) => {
                 match *other {
-                    Status::Pending(_) => Pending { target: target }.into(),
+                    Status::Pending(_) => {
+                        Pending {
+                                target: target,
+                                estimate: estimate,
+                            }
+                            .into()
+                    }
                     Status::Progress(_) => other.merge(self),
                     Status::Done(Done { ref begin, .. }) |
                     Status::Fail(Fail { ref begin, .. }) => {
@@ -200,6 +222,7 @@ impl Status {
                                 begin: *begin,
                                 processed: processed,
                                 target: target,
+                                estimate: estimate,
                             }
                             .into()
                     }
@@ -283,6 +306,7 @@ impl<'a> From<&'a Status> for Cow<'static, str> {
             Status::Done(_) => Borrowed("Done"),
             Status::Fail(_) => Borrowed("Failed"),
             Status::Pending(_) => Borrowed("       "),
+            Status::Progress(ref s) if s.estimate => Owned(format!("~{:5.1}%", s.percentage())),
             Status::Progress(ref s) => Owned(format!("{:6.2}%", s.percentage())),
         }
     }
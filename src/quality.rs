@@ -0,0 +1,389 @@
+use ffprobe::FFProbe;
+use regex::Regex;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Ssim,
+    Psnr,
+    Vmaf,
+}
+
+impl Metric {
+    pub fn from_str(s: &str) -> Option<Metric> {
+        match s {
+            "ssim" => Some(Metric::Ssim),
+            "psnr" => Some(Metric::Psnr),
+            "vmaf" => Some(Metric::Vmaf),
+            _ => None,
+        }
+    }
+
+    /// Parses a comma separated list such as `ssim,vmaf`
+    pub fn parse_list(s: &str) -> Option<Vec<Metric>> {
+        s.split(',').map(|m| Metric::from_str(m.trim())).collect()
+    }
+
+    /// Parses a target such as `vmaf=95` or `ssim=0.98`
+    pub fn parse_target(s: &str) -> Option<(Metric, f64)> {
+        let mut parts = s.splitn(2, '=');
+        match (parts.next().and_then(Metric::from_str),
+               parts.next().and_then(|v| v.parse::<f64>().ok())) {
+            (Some(metric), Some(score)) if score >= 0. => Some((metric, score)),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Metric::Ssim => "SSIM",
+            Metric::Psnr => "PSNR",
+            Metric::Vmaf => "VMAF",
+        }
+    }
+
+    fn filter(&self) -> &'static str {
+        match *self {
+            Metric::Ssim => "ssim",
+            Metric::Psnr => "psnr",
+            Metric::Vmaf => "libvmaf",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub metrics: Vec<Metric>,
+    /// Only every n-th frame is compared
+    pub subsample: u64,
+    pub min_ssim: Option<f64>,
+    pub min_psnr: Option<f64>,
+    pub min_vmaf: Option<f64>,
+}
+
+impl Options {
+    /// The requested metrics, plus those that have a threshold
+    pub fn metrics(&self) -> Vec<Metric> {
+        let mut metrics = self.metrics.clone();
+        for &(metric, threshold) in &[(Metric::Ssim, self.min_ssim),
+                                      (Metric::Psnr, self.min_psnr),
+                                      (Metric::Vmaf, self.min_vmaf)] {
+            if threshold.is_some() && !metrics.contains(&metric) {
+                metrics.push(metric);
+            }
+        }
+        metrics
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.metrics().len() != 0
+    }
+
+    /// Fails on the first metric that scored below its threshold
+    pub fn check(&self, scores: &Scores) -> Result<(), Error> {
+        let failed = [(Metric::Ssim, self.min_ssim, scores.ssim),
+                      (Metric::Psnr, self.min_psnr, scores.psnr),
+                      (Metric::Vmaf, self.min_vmaf, scores.vmaf)]
+            .iter()
+            .filter_map(|&(metric, min, score)| match (min, score) {
+                (Some(min), Some(score)) if score < min => Some((metric, score, min)),
+                _ => None,
+            })
+            .next();
+        match failed {
+            Some((metric, score, min)) => {
+                Err(Error::Threshold {
+                    metric: metric,
+                    score: score,
+                    min: min,
+                })
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Scores {
+    pub ssim: Option<f64>,
+    pub psnr: Option<f64>,
+    pub vmaf: Option<f64>,
+}
+
+impl Scores {
+    pub fn get(&self, metric: Metric) -> Option<f64> {
+        match metric {
+            Metric::Ssim => self.ssim,
+            Metric::Psnr => self.psnr,
+            Metric::Vmaf => self.vmaf,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    SpawnError(io::Error),
+    Unsuccessful {
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+    MissingScore { metric: Metric, stderr: String },
+    Threshold {
+        metric: Metric,
+        score: f64,
+        min: f64,
+    },
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::SpawnError(_) => "Could not spawn ffmpeg to measure quality",
+            Error::Unsuccessful { .. } => "Quality measurement failed",
+            Error::MissingScore { .. } => "Could not find score in ffmpeg output",
+            Error::Threshold { .. } => "Quality is below the threshold",
+        }
+    }
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::SpawnError(ref e) => Some(e),
+            Error::Unsuccessful { .. } |
+            Error::MissingScore { .. } |
+            Error::Threshold { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::SpawnError(_) => write!(f, "{}", self.description()),
+            Error::Unsuccessful { ref stderr, .. } => {
+                write!(f, "{}\nStdErr:\n{}", self.description(), stderr.trim())
+            }
+            Error::MissingScore { metric, .. } => {
+                write!(f, "{}: {}", self.description(), metric.name())
+            }
+            Error::Threshold { metric, score, min } => {
+                write!(f,
+                       "{}: {} {:.3} < {:.3}",
+                       self.description(),
+                       metric.name(),
+                       score,
+                       min)
+            }
+        }
+    }
+}
+
+/// Measures outputs against their sources. VMAF is left out when ffmpeg is
+/// built without libvmaf.
+#[derive(Debug, Clone)]
+pub struct Measurer {
+    pub options: Options,
+    pub metrics: Vec<Metric>,
+    pub vmaf_available: bool,
+}
+
+impl Measurer {
+    pub fn new(options: Options) -> Self {
+        let mut metrics = options.metrics();
+        let vmaf_available = metrics.contains(&Metric::Vmaf) && has_libvmaf();
+        if !vmaf_available {
+            metrics.retain(|&m| m != Metric::Vmaf);
+        }
+        Measurer {
+            options: options,
+            metrics: metrics,
+            vmaf_available: vmaf_available,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.metrics.len() != 0
+    }
+
+    /// Compares the first video stream of `output` with the converted video
+    /// stream of `source`, at `fps` if the output was encoded at another
+    /// frame rate. Sources without video get no scores. Thresholds are not
+    /// checked here, see `Options::check`.
+    pub fn measure(&self,
+                   source: &Path,
+                   probe: &FFProbe,
+                   fps: Option<f64>,
+                   output: &Path)
+                   -> Result<Option<Scores>, Error> {
+        self.measure_range(source, None, probe, fps, output)
+    }
+
+    /// Like `measure`, but compares `output` with the `(start, length)` range
+    /// of the source in seconds, for outputs encoded from a part of the source
+    pub fn measure_range(&self,
+                         source: &Path,
+                         range: Option<(f64, f64)>,
+                         probe: &FFProbe,
+                         fps: Option<f64>,
+                         output: &Path)
+                         -> Result<Option<Scores>, Error> {
+        let video = match probe.video {
+            Some(ref v) if self.is_enabled() => v,
+            _ => return Ok(None),
+        };
+
+        let graph = filter_graph(&self.metrics,
+                                 video.index,
+                                 video.width,
+                                 video.height,
+                                 fps,
+                                 self.options.subsample);
+        let result = try!(Command::new("ffmpeg")
+            .args(&["-nostdin", "-hide_banner", "-i"])
+            .arg(output)
+            .args(&range_args(range))
+            .arg("-i")
+            .arg(source)
+            .args(&["-lavfi", &graph, "-f", "null", "-"])
+            .stdin(Stdio::null())
+            .output()
+            .map_err(Error::SpawnError));
+
+        let stderr = String::from_utf8_lossy(&result.stderr).into_owned();
+        if !result.status.success() {
+            return Err(Error::Unsuccessful {
+                exit_code: result.status.code(),
+                stderr: stderr,
+            });
+        }
+
+        let mut scores = Scores::default();
+        for &metric in &self.metrics {
+            let score = match parse_score(metric, &stderr) {
+                Some(s) => s,
+                None => {
+                    return Err(Error::MissingScore {
+                        metric: metric,
+                        stderr: stderr,
+                    })
+                }
+            };
+            match metric {
+                Metric::Ssim => scores.ssim = Some(score),
+                Metric::Psnr => scores.psnr = Some(score),
+                Metric::Vmaf => scores.vmaf = Some(score),
+            }
+        }
+        Ok(Some(scores))
+    }
+}
+
+/// Input 0 is the output (distorted), input 1 the source (reference). The
+/// output is scaled back to the size of the source, the source is brought to
+/// the frame rate `fps` of the output if that was changed, and both are
+/// sampled at the same frames.
+fn filter_graph(metrics: &[Metric],
+                index: u64,
+                width: u64,
+                height: u64,
+                fps: Option<f64>,
+                subsample: u64)
+                -> String {
+    let step = match subsample {
+        0 | 1 => String::new(),
+        n => format!("framestep={},", n),
+    };
+    let rate = match fps {
+        Some(fps) => format!("fps={},", fps),
+        None => String::new(),
+    };
+    let count = metrics.len();
+    let outputs = |prefix: &str| {
+        (0..count).map(|n| format!("[{}{}]", prefix, n)).collect::<String>()
+    };
+
+    let mut graph = format!("[0:v:0]{step}scale={w}:{h}:flags=bicubic,format=yuv420p,\
+                             setpts=PTS-STARTPTS,split={n}{d};\
+                             [1:{index}]{rate}{step}format=yuv420p,setpts=PTS-STARTPTS,split={n}{r}",
+                            step = step,
+                            rate = rate,
+                            w = width,
+                            h = height,
+                            n = count,
+                            d = outputs("d"),
+                            r = outputs("r"),
+                            index = index);
+    for (n, metric) in metrics.iter().enumerate() {
+        graph.push_str(&format!(";[d{n}][r{n}]{filter}", n = n, filter = metric.filter()));
+    }
+    graph
+}
+
+/// Input options that limit decoding to a range of seconds
+pub fn range_args(range: Option<(f64, f64)>) -> Vec<String> {
+    match range {
+        Some((start, length)) => {
+            vec!["-ss".to_string(),
+                 format!("{:.3}", start),
+                 "-t".to_string(),
+                 format!("{:.3}", length)]
+        }
+        None => vec![],
+    }
+}
+
+fn parse_score(metric: Metric, stderr: &str) -> Option<f64> {
+    let pattern = match metric {
+        Metric::Ssim => r"SSIM .*All:([0-9.]+)",
+        Metric::Psnr => r"PSNR .*average:([0-9.]+|inf)",
+        Metric::Vmaf => r"VMAF score[:=] ?([0-9.]+)",
+    };
+    let regex = match Regex::new(pattern) {
+        Ok(r) => r,
+        Err(_) => return None,
+    };
+    regex.captures_iter(stderr)
+        .last()
+        .and_then(|c| c.at(1).map(|s| s.to_string()))
+        .and_then(|s| match s.as_str() {
+            // Identical frames
+            "inf" => Some(100.),
+            s => s.parse().ok(),
+        })
+}
+
+fn has_libvmaf() -> bool {
+    Command::new("ffmpeg")
+        .args(&["-hide_banner", "-filters"])
+        .stdin(Stdio::null())
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).contains(" libvmaf "))
+        .unwrap_or(false)
+}
+
+#[test]
+fn test_parse_score() {
+    let stderr = "[Parsed_ssim_4 @ 0x1] SSIM Y:0.990 (20.0) U:0.995 (23.0) V:0.994 (22.1) \
+                  All:0.992134 (21.0)\n\
+                  [Parsed_psnr_5 @ 0x2] PSNR y:44.1 u:48.0 v:47.9 average:45.123 min:40.0 \
+                  max:50.0\n\
+                  [Parsed_libvmaf_6 @ 0x3] VMAF score: 96.253\n";
+    assert_eq!(parse_score(Metric::Ssim, stderr), Some(0.992134));
+    assert_eq!(parse_score(Metric::Psnr, stderr), Some(45.123));
+    assert_eq!(parse_score(Metric::Vmaf, stderr), Some(96.253));
+    assert_eq!(parse_score(Metric::Vmaf, "nothing"), None);
+}
+
+#[test]
+fn test_filter_graph() {
+    // A 60 fps source capped to 30 fps is compared at 30 fps
+    let graph = filter_graph(&[Metric::Ssim, Metric::Psnr], 0, 1920, 1080, Some(30.), 2);
+    assert_eq!(graph,
+               "[0:v:0]framestep=2,scale=1920:1080:flags=bicubic,format=yuv420p,\
+                setpts=PTS-STARTPTS,split=2[d0][d1];\
+                [1:0]fps=30,framestep=2,format=yuv420p,setpts=PTS-STARTPTS,split=2[r0][r1];\
+                [d0][r0]ssim;[d1][r1]psnr");
+    assert!(!filter_graph(&[Metric::Ssim], 0, 1920, 1080, None, 1).contains("fps="));
+}
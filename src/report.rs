@@ -0,0 +1,108 @@
+use conversion::{Conversion, Conversions};
+use progress::Status;
+use rustc_serialize::json::{self, Json, ToJson};
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    Write { path: PathBuf, error: io::Error },
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Write { .. } => "Could not write report",
+        }
+    }
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::Write { ref error, .. } => Some(error),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Write { ref path, .. } => write!(f, "{}: {:?}", self.description(), path),
+        }
+    }
+}
+
+fn status_name(status: &Status) -> &'static str {
+    match *status {
+        Status::Pending(_) => "pending",
+        Status::Progress(_) => "progress",
+        Status::Done(_) => "done",
+        Status::Fail(_) => "failed",
+    }
+}
+
+fn conversion_to_json(c: &Conversion) -> Json {
+    let mut object = BTreeMap::new();
+    object.insert("id".to_string(), c.id.to_json());
+    object.insert("source".to_string(),
+                  c.source.path.path.to_string_lossy().into_owned().to_json());
+    object.insert("target".to_string(),
+                  c.target.path.to_string_lossy().into_owned().to_json());
+    object.insert("status".to_string(), status_name(&c.status).to_json());
+    let elapsed = match c.status {
+        Status::Done(ref d) => Some(d.duration),
+        Status::Fail(ref f) => Some(f.duration),
+        Status::Pending(_) | Status::Progress(_) => None,
+    };
+    object.insert("elapsed".to_string(), elapsed.to_json());
+    object.insert("crf".to_string(), c.crf.to_json());
+
+    let loudness = c.loudness.map(|l| {
+        let mut loudness = BTreeMap::new();
+        loudness.insert("integrated".to_string(), l.integrated.to_json());
+        loudness.insert("true_peak".to_string(), l.true_peak.to_json());
+        loudness.insert("range".to_string(), l.range.to_json());
+        loudness.insert("threshold".to_string(), l.threshold.to_json());
+        Json::Object(loudness)
+    });
+    object.insert("loudness".to_string(), loudness.to_json());
+
+    let gain = c.gain.map(|g| {
+        let mut gain = BTreeMap::new();
+        gain.insert("track_integrated".to_string(), g.track.integrated.to_json());
+        gain.insert("track_peak".to_string(), g.track.peak.to_json());
+        gain.insert("album_integrated".to_string(), g.album.map(|a| a.integrated).to_json());
+        gain.insert("album_peak".to_string(), g.album.map(|a| a.peak).to_json());
+        Json::Object(gain)
+    });
+    object.insert("gain".to_string(), gain.to_json());
+
+    let scores = c.scores.map(|s| {
+        let mut scores = BTreeMap::new();
+        scores.insert("ssim".to_string(), s.ssim.to_json());
+        scores.insert("psnr".to_string(), s.psnr.to_json());
+        scores.insert("vmaf".to_string(), s.vmaf.to_json());
+        Json::Object(scores)
+    });
+    object.insert("scores".to_string(), scores.to_json());
+    Json::Object(object)
+}
+
+/// Writes one JSON object per conversion to `path`
+pub fn write(path: &Path, conversions: &Conversions) -> Result<(), Error> {
+    let mut root = BTreeMap::new();
+    root.insert("conversions".to_string(),
+                Json::Array(conversions.iter().map(conversion_to_json).collect()));
+    let text = format!("{}\n", json::as_pretty_json(&Json::Object(root)));
+
+    File::create(path)
+        .and_then(|mut f| f.write_all(text.as_bytes()))
+        .map_err(|e| {
+            Error::Write {
+                path: path.to_path_buf(),
+                error: e,
+            }
+        })
+}
@@ -0,0 +1,233 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str;
+
+/// Returned for binary content that is not recognized. Unlike
+/// `application/octet-stream` from `file`, it is not probed.
+pub const UNKNOWN: &'static str = "application/x-unknown";
+
+// Enough for a few MPEG-TS packets and the first Ogg page
+const HEADER_SIZE: usize = 8192;
+
+const ASF_GUID: &'static [u8] = &[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11, 0xA6, 0xD9,
+                                  0x00, 0xAA, 0x00, 0x62, 0xCE, 0x6C];
+
+const MAGIC: &'static [(&'static [u8], &'static str)] =
+    &[(b"FLV", "video/x-flv"),
+      (b"fLaC", "audio/flac"),
+      (b"ID3", "audio/mpeg"),
+      (b"\x00\x00\x01\xBA", "video/mpeg"),
+      (b"\x00\x00\x01\xB3", "video/mpeg"),
+      (b"\xFF\xD8\xFF", "image/jpeg"),
+      (b"\x89PNG", "image/png"),
+      (b"GIF8", "image/gif"),
+      (b"%PDF", "application/pdf"),
+      (b"PK\x03\x04", "application/zip"),
+      (b"7z\xBC\xAF\x27\x1C", "application/x-7z-compressed"),
+      (b"Rar!\x1A\x07", "application/x-rar"),
+      (b"\x7FELF", "application/x-executable"),
+      (b"MZ", "application/x-dosexec"),
+      (b"II*\x00", "image/tiff"),
+      (b"MM\x00*", "image/tiff"),
+      (b"MAC ", "audio/x-ape"),
+      (b"wvpk", "audio/x-wavpack"),
+      (b"\xD0\xCF\x11\xE0", "application/CDFV2"),
+      (b"\x00\x00\x01\x00", "image/x-icon"),
+      (b"gimp xcf", "image/x-xcf")];
+
+pub fn sniff_path(path: &Path) -> io::Result<&'static str> {
+    let mut buffer = Vec::with_capacity(HEADER_SIZE);
+    try!(File::open(path).and_then(|f| f.take(HEADER_SIZE as u64).read_to_end(&mut buffer)));
+    Ok(sniff(&buffer))
+}
+
+pub fn sniff(data: &[u8]) -> &'static str {
+    if data.is_empty() {
+        return "inode/x-empty";
+    }
+    if data.starts_with(b"\x1A\x45\xDF\xA3") {
+        return match contains(&data[..data.len().min(64)], b"webm") {
+            true => "video/webm",
+            false => "video/x-matroska",
+        };
+    }
+    if let Some(mime_type) = sniff_iso_bmff(data) {
+        return mime_type;
+    }
+    if data.starts_with(b"RIFF") && data.len() >= 12 {
+        return match &data[8..12] {
+            b"AVI " => "video/x-msvideo",
+            b"WAVE" => "audio/x-wav",
+            b"WEBP" => "image/webp",
+            _ => UNKNOWN,
+        };
+    }
+    if data.starts_with(b"FORM") && data.len() >= 12 {
+        return match &data[8..12] {
+            b"AIFF" | b"AIFC" => "audio/x-aiff",
+            _ => UNKNOWN,
+        };
+    }
+    if is_bmp(data) {
+        return "image/bmp";
+    }
+    // UTF-16 subtitles and notes would pass for an MPEG audio frame
+    if data.starts_with(b"\xFF\xFE") || data.starts_with(b"\xFE\xFF") {
+        return "text/plain";
+    }
+    if data.starts_with(ASF_GUID) {
+        return "video/x-ms-asf";
+    }
+    if data.starts_with(b"OggS") {
+        return match contains(data, b"\x80theora") {
+            true => "video/ogg",
+            false => "audio/ogg",
+        };
+    }
+    if is_transport_stream(data, 0, 188) || is_transport_stream(data, 4, 192) {
+        return "video/mp2t";
+    }
+    for &(magic, mime_type) in MAGIC {
+        if data.starts_with(magic) {
+            return mime_type;
+        }
+    }
+    if let Some(mime_type) = sniff_mpeg_audio(data) {
+        return mime_type;
+    }
+    if let Some(text) = as_text(data) {
+        let start = text.trim_start().to_lowercase();
+        if start.starts_with("<svg") || start.starts_with("<?xml") && start.contains("<svg") {
+            return "image/svg+xml";
+        }
+        if start.starts_with("<?xml") {
+            return "text/xml";
+        }
+        if start.starts_with("<!doctype html") || start.starts_with("<html") {
+            return "text/html";
+        }
+        return "text/plain";
+    }
+    UNKNOWN
+}
+
+fn sniff_iso_bmff(data: &[u8]) -> Option<&'static str> {
+    if data.len() < 12 {
+        return None;
+    }
+    match &data[4..8] {
+        b"ftyp" => {
+            Some(match &data[8..12] {
+                b"qt  " => "video/quicktime",
+                b"M4A " | b"M4B " => "audio/mp4",
+                b"M4V " => "video/x-m4v",
+                brand if brand.starts_with(b"3g") => "video/3gpp",
+                _ => "video/mp4",
+            })
+        }
+        // Old QuickTime files start directly with these atoms
+        b"moov" | b"mdat" | b"wide" | b"free" | b"skip" => Some("video/quicktime"),
+        _ => None,
+    }
+}
+
+/// The frame header of MPEG audio, or of AAC in ADTS, which has the same
+/// sync with layer bits of zero. Reserved values rule out other data.
+fn sniff_mpeg_audio(data: &[u8]) -> Option<&'static str> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    let version = (data[1] >> 3) & 0x03;
+    let layer = (data[1] >> 1) & 0x03;
+    match layer {
+        // ADTS has a 12 bit sync and 13 sampling frequencies
+        0 if data[1] & 0xF0 == 0xF0 && (data[2] >> 2) & 0x0F < 13 => Some("audio/aac"),
+        0 => None,
+        _ if version == 1 || data[2] >> 4 == 0x0F || (data[2] >> 2) & 0x03 == 0x03 => None,
+        _ => Some("audio/mpeg"),
+    }
+}
+
+/// A bitmap file header followed by one of the known info headers
+fn is_bmp(data: &[u8]) -> bool {
+    if !data.starts_with(b"BM") || data.len() < 18 {
+        return false;
+    }
+    let size = data[14..18].iter().rev().fold(0u32, |size, &b| size << 8 | b as u32);
+    [12, 40, 52, 56, 64, 108, 124].contains(&size)
+}
+
+fn is_transport_stream(data: &[u8], offset: usize, packet_size: usize) -> bool {
+    let packets = 3;
+    if data.len() < offset + packet_size * (packets - 1) + 1 {
+        return false;
+    }
+    (0..packets).all(|n| data[offset + n * packet_size] == 0x47)
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|w| w == needle)
+}
+
+// The header may cut a multi-byte character in half, so a few trailing
+// bytes are allowed to be invalid.
+fn as_text(data: &[u8]) -> Option<&str> {
+    let text = match str::from_utf8(data) {
+        Ok(t) => t,
+        Err(e) if data.len() - e.valid_up_to() < 4 && data.len() == HEADER_SIZE => {
+            match str::from_utf8(&data[..e.valid_up_to()]) {
+                Ok(t) => t,
+                Err(_) => return None,
+            }
+        }
+        Err(_) => return None,
+    };
+    match text.chars().all(|c| !c.is_control() || c == '\n' || c == '\r' || c == '\t' ||
+                               c == '\x0C') {
+        true => Some(text),
+        false => None,
+    }
+}
+
+#[test]
+fn test_sniff() {
+    let mut ts = vec![0u8; 188 * 3];
+    ts[0] = 0x47;
+    ts[188] = 0x47;
+    ts[376] = 0x47;
+
+    let cases: &[(&[u8], &str)] = &[(b"", "inode/x-empty"),
+                                   (b"\x1A\x45\xDF\xA3\x01\x00\x00\x00\x42\x82\x88matroska",
+                                    "video/x-matroska"),
+                                   (b"\x1A\x45\xDF\xA3\x01\x00\x00\x00\x42\x82\x84webm",
+                                    "video/webm"),
+                                   (b"\x00\x00\x00\x18ftypisom", "video/mp4"),
+                                   (b"\x00\x00\x00\x14ftypqt  ", "video/quicktime"),
+                                   (b"\x00\x00\x00\x20ftypM4A ", "audio/mp4"),
+                                   (b"RIFF\x00\x00\x00\x00AVI LIST", "video/x-msvideo"),
+                                   (b"RIFF\x00\x00\x00\x00WAVEfmt ", "audio/x-wav"),
+                                   (&ts, "video/mp2t"),
+                                   (b"\x00\x00\x01\xBA\x44", "video/mpeg"),
+                                   (b"FLV\x01\x05", "video/x-flv"),
+                                   (ASF_GUID, "video/x-ms-asf"),
+                                   (b"OggS\x00\x02\x00\x00\x01vorbis", "audio/ogg"),
+                                   (b"OggS\x00\x02\x00\x00\x80theora", "video/ogg"),
+                                   (b"ID3\x04\x00", "audio/mpeg"),
+                                   (b"\xFF\xFB\x90\x00", "audio/mpeg"),
+                                   (b"\xFF\xF1\x50\x80", "audio/aac"),
+                                   (b"\xFF\xFB\xF0\x00", UNKNOWN),
+                                   (b"\xFF\xFE1\x00\n\x000\x000\x00", "text/plain"),
+                                   (b"BM\x36\x00\x0C\x00\x00\x00\x00\x00\x36\x00\x00\x00\x28\x00\x00\x00",
+                                    "image/bmp"),
+                                   (b"II*\x00\x08\x00\x00\x00", "image/tiff"),
+                                   (b"FORM\x00\x00\x10\x00AIFFCOMM", "audio/x-aiff"),
+                                   (b"\xFF\xD8\xFF\xE0", "image/jpeg"),
+                                   (b"1\n00:00:01,000 --> 00:00:02,000\nHi\n", "text/plain"),
+                                   (b"<?xml version=\"1.0\"?>\n<movie/>", "text/xml"),
+                                   (b"\x00\x01\x02\x03\x04", UNKNOWN)];
+
+    for &(data, expected) in cases {
+        assert_eq!(sniff(data), expected, "{:?}", data);
+    }
+}
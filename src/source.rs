@@ -1,24 +1,35 @@
 use check_file;
+use cue;
 use ffprobe;
+use filter::{Filter, Matcher};
+use formats;
 use path;
+use probe_cache::{ProbeCache, Stamp};
 use std::cmp::Ordering;
 use std::error::Error as StdError;
 use std::fmt;
 use std::io;
 use std::iter::IntoIterator;
 use std::ops::{Deref, DerefMut};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicBool};
+use utils;
 
 #[derive(Debug)]
 pub enum Error {
     CheckFileError(check_file::Error),
+    CueError { path: PathBuf, error: cue::Error },
+    /// A file named in a CUE sheet that is not among the sources
+    CueFile { path: PathBuf, file: String },
     FFProbeError {
         path: PathBuf,
         error: ffprobe::Error,
     },
     PathError { path: PathBuf, error: io::Error },
+    WalkError { path: PathBuf, error: path::Error },
     SourceDirectory { path: PathBuf, error: io::Error },
     StraySource { path: PathBuf },
+    UnknownDuration { path: PathBuf },
 }
 
 impl From<check_file::Error> for Error {
@@ -32,20 +43,28 @@ impl StdError for Error {
     fn description(&self) -> &str {
         match *self {
             Error::CheckFileError(_) => "Error happened while checking file",
+            Error::CueError { .. } => "Could not read CUE sheet",
+            Error::CueFile { .. } => "CUE sheet names a file that is not converted",
             Error::FFProbeError { .. } => "FFProbe error",
             Error::PathError { .. } => "Could not expand path",
+            Error::WalkError { .. } => "Could not read path",
             Error::SourceDirectory { .. } => "Error happened while resolving INPUT_DIRECTORY",
             Error::StraySource { .. } => "Path cannot be outside INPUT_DIRECTORY",
+            Error::UnknownDuration { .. } => "Could not determine duration",
         }
     }
 
     fn cause(&self) -> Option<&StdError> {
         match *self {
             Error::CheckFileError(ref error) => Some(error),
+            Error::CueError { ref error, .. } => Some(error),
             Error::FFProbeError { ref error, .. } => Some(error),
             Error::PathError { ref error, .. } => Some(error),
+            Error::WalkError { ref error, .. } => Some(error),
             Error::SourceDirectory { ref error, .. } => Some(error),
-            Error::StraySource { .. } => None,
+            Error::StraySource { .. } |
+            Error::CueFile { .. } |
+            Error::UnknownDuration { .. } => None,
         }
     }
 }
@@ -54,9 +73,13 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::PathError { ref path, .. } |
+            Error::CueError { ref path, .. } |
+            Error::CueFile { ref path, .. } |
+            Error::WalkError { ref path, .. } |
             Error::FFProbeError { ref path, .. } |
             Error::SourceDirectory { ref path, .. } |
-            Error::StraySource { ref path, .. } => {
+            Error::StraySource { ref path, .. } |
+            Error::UnknownDuration { ref path } => {
                 write!(f,
                        "{desc}: {path:?}",
                        desc = self.description(),
@@ -67,8 +90,68 @@ impl fmt::Display for Error {
     }
 }
 
+impl Error {
+    /// Whether the discovery is aborted even without `--strict`: when
+    /// `ffprobe` or `file` could not be run, which every other file would
+    /// run into as well, or a symbolic link was found that is not allowed
+    fn is_fatal(&self) -> bool {
+        match *self {
+            Error::CheckFileError(ref error) => error.is_run_error(),
+            Error::FFProbeError { ref error, .. } => error.is_spawn_error(),
+            Error::WalkError { error: path::Error::Symlink, .. } => true,
+            _ => false,
+        }
+    }
+
+    /// A one line explanation, for listing next to the path it concerns
+    pub fn reason(&self) -> String {
+        match *self {
+            Error::CheckFileError(ref error) => error.reason(),
+            Error::CueError { ref error, .. } => error.to_string(),
+            Error::CueFile { ref file, .. } => format!("{}: {}", self.description(), file),
+            Error::FFProbeError { ref error, .. } => error.reason(),
+            Error::WalkError { ref error, .. } => error.to_string(),
+            Error::PathError { ref error, .. } |
+            Error::SourceDirectory { ref error, .. } => {
+                format!("{}: {}", self.description(), error)
+            }
+            Error::StraySource { .. } |
+            Error::UnknownDuration { .. } => self.description().to_string(),
+        }
+    }
+}
+
 type SourceResult<T> = Result<T, Error>;
 
+/// Settings for how sources are discovered below INPUT_DIRECTORY.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub filter: Filter,
+    pub walk: path::WalkOptions,
+    pub mime_fallback: bool,
+    /// Number of files checked and probed at the same time
+    pub jobs: usize,
+    /// Abort on the first problem instead of collecting them
+    pub strict: bool,
+    pub formats: formats::Registry,
+}
+
+/// A path that could not be read, checked or probed
+#[derive(Debug)]
+pub struct Problem {
+    pub path: BasedPath,
+    pub error: Error,
+}
+
+#[derive(Debug)]
+pub struct Discovery {
+    pub sources: Sources,
+    pub bads: Vec<BasedPath>,
+    /// Sources left out by the duration limits
+    pub filtered: Vec<Source>,
+    pub problems: Vec<Problem>,
+}
+
 
 #[derive(Debug, Clone)]
 pub struct BasedPath {
@@ -85,11 +168,20 @@ impl BasedPath {
 pub struct Source {
     pub path: BasedPath,
     pub ffprobe: ffprobe::FFProbe,
+    /// The part of the file that is converted, for albums split by a CUE
+    /// sheet. The duration of the probe is that of the track.
+    pub track: Option<cue::Track>,
+}
+
+impl Source {
+    fn key(&self) -> (&PathBuf, Option<u64>) {
+        (&self.path.path, self.track.as_ref().map(|t| t.number))
+    }
 }
 
 impl Ord for Source {
     fn cmp(&self, other: &Source) -> Ordering {
-        self.path.path.cmp(&other.path.path)
+        self.key().cmp(&other.key())
     }
 }
 impl Eq for Source {}
@@ -100,7 +192,7 @@ impl PartialOrd for Source {
 }
 impl PartialEq for Source {
     fn eq(&self, other: &Source) -> bool {
-        self.path.path == other.path.path
+        self.key() == other.key()
     }
 }
 
@@ -135,8 +227,10 @@ impl Deref for BasedPath {
 
 impl Sources {
     pub fn from_paths<'a, T, U>(paths: T,
-                                base_directory: &'a str)
-                                -> SourceResult<(Self, Vec<BasedPath>)>
+                                base_directory: &'a str,
+                                options: &Options,
+                                cache: &mut ProbeCache)
+                                -> SourceResult<Discovery>
         where T: IntoIterator<Item = U>,
               U: Into<PathBuf>
     {
@@ -162,52 +256,236 @@ impl Sources {
             return Err(Error::StraySource { path: path.clone() });
         }
 
-        let mut expanded_paths: Vec<PathBuf> = paths.into_iter()
-            .flat_map(expand_path)
-            .collect();
+        let filter = &options.filter;
+        let mut matcher = filter.matcher(&base_directory);
+        let mut expanded_paths: Vec<PathBuf> = Vec::new();
+        let mut problems: Vec<Problem> = Vec::new();
+        for path in paths {
+            expand_path(path, options.walk, &mut matcher, &mut expanded_paths, |path, error| {
+                problems.push(Problem {
+                    path: BasedPath {
+                        path: path.clone(),
+                        base: base_directory.clone(),
+                    },
+                    error: Error::WalkError {
+                        path: path,
+                        error: error,
+                    },
+                })
+            });
+        }
+        if let Some(n) = problems.iter().position(|p| options.strict || p.error.is_fatal()) {
+            return Err(problems.remove(n).error);
+        }
 
         expanded_paths.sort();
         expanded_paths.dedup();
 
-        // Quick filtering using 'file'
-        let (paths, skipped_file) = try!(check_file::check_files(expanded_paths.into_iter()));
-
-        let skipped_file = skipped_file.into_iter().map(|p| {
-            BasedPath {
-                path: p,
-                base: base_directory.clone(),
+        // CUE sheets are text, and only tell how to split other sources
+        let (cue_paths, expanded_paths): (Vec<PathBuf>, Vec<PathBuf>) =
+            expanded_paths.into_iter().partition(|p| is_cue(p));
+        let mut sheets = Vec::new();
+        for path in cue_paths {
+            match cue::Sheet::load(&path) {
+                Ok(sheet) => sheets.push((path, sheet)),
+                Err(e) => {
+                    let error = Error::CueError {
+                        path: path.clone(),
+                        error: e,
+                    };
+                    if options.strict {
+                        return Err(error);
+                    }
+                    problems.push(Problem {
+                        path: BasedPath {
+                            path: path,
+                            base: base_directory.clone(),
+                        },
+                        error: error,
+                    });
+                }
             }
-        });
+        }
 
-        let sources: Result<Vec<_>, Error> = paths.into_iter()
-            .map(|path| ffprobe_it(&path).map(|probe| (path, probe)))
-            .collect();
+        // Checking and probing is dominated by waiting on the disk and on
+        // ffprobe, so it is spread over several threads. The cache is only
+        // read here and updated afterwards. Once a file fails in a way that
+        // aborts the discovery, the files not yet started are left alone.
+        let abort = AtomicBool::new(false);
+        let probes = {
+            let cache: &ProbeCache = cache;
+            let abort = &abort;
+            utils::parallel_map(expanded_paths, options.jobs, |path| {
+                if abort.load(atomic::Ordering::SeqCst) {
+                    return None;
+                }
+                let stamp = Stamp::of(&path);
+                let mut fresh = Vec::new();
+                let probe = probe(&path, stamp.as_ref(), cache, &mut fresh, options);
+                match probe {
+                    Err(ref e) if options.strict || e.is_fatal() => abort.store(true, atomic::Ordering::SeqCst),
+                    _ => (),
+                }
+                Some((path, stamp, fresh, probe))
+            })
+        };
 
-        let (good, skipped_ffprobe): (Vec<_>, Vec<_>) =
-            try!(sources).into_iter().partition(|&(_, ref probe)| probe.is_some());
+        // Outputs are cached before anything is returned, so an aborted
+        // discovery does not have to probe them again
+        let mut probed = Vec::with_capacity(probes.len());
+        for (path, stamp, fresh, probe) in probes.into_iter().filter_map(|p| p) {
+            if let Some(stamp) = stamp {
+                for (kind, output) in fresh {
+                    cache.insert(&path, stamp.clone(), kind, output);
+                }
+            }
+            probed.push((path, probe));
+        }
 
-        let good = good.into_iter().filter_map(|(path, probe)| {
-            probe.map(|probe| {
-                Source {
-                    ffprobe: probe,
-                    path: BasedPath {
-                        path: path,
-                        base: base_directory.clone(),
-                    },
+        let mut sources = Vec::with_capacity(probed.len());
+        for (path, probe) in probed {
+            match probe {
+                Ok(probe) => sources.push((path, probe)),
+                Err(e) => {
+                    if options.strict || e.is_fatal() {
+                        return Err(e);
+                    }
+                    problems.push(Problem {
+                        path: BasedPath {
+                            path: path,
+                            base: base_directory.clone(),
+                        },
+                        error: e,
+                    });
                 }
-            })
-        });
-        let skipped_ffprobe = skipped_ffprobe.into_iter().map(|(path, _)| {
+            }
+        }
+
+        let (good, skipped): (Vec<_>, Vec<_>) =
+            sources.into_iter().partition(|&(_, ref probe)| probe.is_some());
+
+        let good = good.into_iter()
+            .filter_map(|(path, probe)| {
+                probe.map(|probe| {
+                    Source {
+                        ffprobe: probe,
+                        path: BasedPath {
+                            path: path,
+                            base: base_directory.clone(),
+                        },
+                        track: None,
+                    }
+                })
+            });
+        let (mut good, filtered, missing) = split_and_filter(good.collect(), &sheets, filter);
+        for (path, file) in missing {
+            let error = Error::CueFile {
+                path: path.clone(),
+                file: file,
+            };
+            if options.strict {
+                return Err(error);
+            }
+            problems.push(Problem {
+                path: BasedPath {
+                    path: path,
+                    base: base_directory.clone(),
+                },
+                error: error,
+            });
+        }
+        good.sort();
+        problems.sort_by(|a, b| a.path.path.cmp(&b.path.path));
+        let skipped = skipped.into_iter().map(|(path, _)| {
             BasedPath {
                 path: path,
                 base: base_directory.clone(),
             }
         });
 
-        Ok((Sources(good.collect()), skipped_file.chain(skipped_ffprobe).collect()))
+        Ok(Discovery {
+            sources: Sources(good),
+            bads: skipped.collect(),
+            filtered: filtered,
+            problems: problems,
+        })
     }
 }
 
+fn is_cue(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).map_or(false, |e| e.eq_ignore_ascii_case("cue"))
+}
+
+/// Splits the sources named in `sheets` into their tracks, then separates
+/// the sources outside the duration limits of `filter`, so album rips are
+/// judged by the durations of their tracks. Also returns the files named in
+/// sheets that are not among the sources, with the path of their sheet.
+fn split_and_filter(mut sources: Vec<Source>,
+                    sheets: &[(PathBuf, cue::Sheet)],
+                    filter: &Filter)
+                    -> (Vec<Source>, Vec<Source>, Vec<(PathBuf, String)>) {
+    let mut missing = Vec::new();
+    for &(ref path, ref sheet) in sheets {
+        missing.extend(split(&mut sources, path, sheet).into_iter().map(|file| (path.clone(), file)));
+    }
+    let (good, filtered) = sources.into_iter().partition(|s| filter.accepts_duration(s.ffprobe.duration));
+    (good, filtered, missing)
+}
+
+/// Replaces the sources named in `sheet` by their tracks, and returns the
+/// names of files that are not among the sources. Rippers often name the
+/// file they ripped to, like `album.wav`, in sheets of files that were
+/// encoded afterwards, so a file with the same stem also matches, or else
+/// one with the stem of the sheet.
+fn split(sources: &mut Vec<Source>, path: &Path, sheet: &cue::Sheet) -> Vec<String> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut missing = Vec::new();
+    let tracks = sheet.tracks();
+    let mut files: Vec<&String> = tracks.iter().map(|&(ref f, _)| f).collect();
+    files.dedup();
+    let files_count = files.len();
+    for file in files {
+        let named = dir.join(file);
+        let position = sources.iter()
+            .position(|s| s.path.path == named)
+            .or_else(|| {
+                sources.iter().position(|s| {
+                    s.path.path.parent() == Some(dir) && s.path.path.file_stem() == named.file_stem()
+                })
+            })
+            .or_else(|| {
+                // A sheet of one file is usually named like the file
+                match files_count {
+                    1 => {
+                        sources.iter().position(|s| {
+                            s.path.path.parent() == Some(dir) && s.path.path.file_stem() == path.file_stem()
+                        })
+                    }
+                    _ => None,
+                }
+            });
+        let source = match position {
+            // Already split by another sheet
+            Some(n) if sources[n].track.is_some() => continue,
+            Some(n) => sources.remove(n),
+            None => {
+                missing.push(file.clone());
+                continue;
+            }
+        };
+        for &(_, ref track) in tracks.iter().filter(|&&(ref f, _)| f == file) {
+            let mut ffprobe = source.ffprobe.clone();
+            ffprobe.duration = track.duration(source.ffprobe.duration);
+            sources.push(Source {
+                path: source.path.clone(),
+                ffprobe: ffprobe,
+                track: Some(track.clone()),
+            });
+        }
+    }
+    missing
+}
+
 fn canonicalize(path: PathBuf) -> Result<PathBuf, Error> {
     match path.canonicalize() {
         Err(e) => {
@@ -220,35 +498,211 @@ fn canonicalize(path: PathBuf) -> Result<PathBuf, Error> {
     }
 }
 
-fn expand_path(path: PathBuf) -> Vec<PathBuf> {
+fn expand_path<F>(path: PathBuf,
+                  options: path::WalkOptions,
+                  matcher: &mut Matcher,
+                  paths: &mut Vec<PathBuf>,
+                  mut on_error: F)
+    where F: FnMut(PathBuf, path::Error)
+{
     use path::{RecursivePathIterator, PathType};
-    let paths: Vec<PathBuf> = match path.is_dir() {
-        false => vec![path],
-        true => {
-            RecursivePathIterator::new(path)
-                .filter_map(|x| {
-                    match x {
-                        PathType::Directory(_) => None,
-                        PathType::File(p) => Some(p),
-                    }
-                })
-                .collect()
+    if !path.is_dir() {
+        paths.push(path);
+        return;
+    }
+
+    let mut iterator = RecursivePathIterator::with_options(path, options);
+    while let Some(entry) = iterator.next() {
+        match entry {
+            PathType::Directory(p) => {
+                if !matcher.accepts_directory(&p) {
+                    iterator.prune();
+                }
+            }
+            PathType::File(p) => {
+                if matcher.accepts_file(&p) {
+                    paths.push(p);
+                }
+            }
+            PathType::Error { path, error } => {
+                if matcher.accepts_directory(&path) {
+                    on_error(path, error);
+                }
+            }
         }
-    };
-    return paths;
+    }
 }
 
-fn ffprobe_it(path: &PathBuf) -> SourceResult<Option<ffprobe::FFProbe>> {
-    use self::Error::FFProbeError;
+// Cached outputs are keyed by what produced them. The mime-type depends on
+// whether `file` is asked when sniffing gives no answer.
+const CACHE_MIME: &'static str = "mime-type";
+const CACHE_MIME_FALLBACK: &'static str = "mime-type-file";
+const CACHE_PROBE: &'static str = "ffprobe";
+const CACHE_PACKETS: &'static str = "ffprobe-count-packets";
 
-    let res = ffprobe::ffprobe(path);
-    match res {
-        Err(e) => {
-            Err(FFProbeError {
+type Fresh = Vec<(&'static str, Option<String>)>;
+
+/// Checks and probes `path`, counting packets if nothing else tells the
+/// duration. Outputs missing from `cache` are collected in `fresh`.
+fn probe(path: &Path,
+         stamp: Option<&Stamp>,
+         cache: &ProbeCache,
+         fresh: &mut Fresh,
+         options: &Options)
+         -> SourceResult<Option<ffprobe::FFProbe>> {
+    use ffprobe::DurationSource;
+
+    // Files rejected by their mime-type are never handed to ffprobe
+    let mime_kind = match options.mime_fallback {
+        true => CACHE_MIME_FALLBACK,
+        false => CACHE_MIME,
+    };
+    let mime_type = try!(cached(path, stamp, cache, fresh, mime_kind, || {
+        check_file::mime_type(path, options.mime_fallback).map(Some).map_err(Error::from)
+    }));
+    if !try!(check_file::check_mime_type(path, mime_type.as_ref().map_or("", |m| m.as_str()))) {
+        return Ok(None);
+    }
+    let output = try!(cached(path, stamp, cache, fresh, CACHE_PROBE, || {
+        ffprobe::run(path).map_err(|e| {
+            Error::FFProbeError {
                 path: path.to_owned(),
                 error: e,
-            })
+            }
+        })
+    }));
+    let mut probe = match output {
+        Some(output) => try!(ffprobe_parse(path, output, &options.formats)),
+        None => None,
+    };
+
+    if let Some(ref mut probe) = probe {
+        if probe.duration_source == DurationSource::Unknown {
+            let output = try!(cached(path, stamp, cache, fresh, CACHE_PACKETS, || {
+                ffprobe::run_count_packets(path).map_err(|e| {
+                    Error::FFProbeError {
+                        path: path.to_owned(),
+                        error: e,
+                    }
+                })
+            }));
+            match output.as_ref().and_then(|o| ffprobe::packet_duration(o)) {
+                Some(duration) => {
+                    probe.duration = duration;
+                    probe.duration_source = DurationSource::Packets;
+                }
+                None => return Err(Error::UnknownDuration { path: path.to_owned() }),
+            }
+        }
+    }
+    Ok(probe)
+}
+
+fn cached<F>(path: &Path,
+             stamp: Option<&Stamp>,
+             cache: &ProbeCache,
+             fresh: &mut Fresh,
+             kind: &'static str,
+             run: F)
+             -> SourceResult<Option<String>>
+    where F: FnOnce() -> SourceResult<Option<String>>
+{
+    if let Some(output) = stamp.and_then(|s| cache.get(path, s, kind)) {
+        return Ok(output);
+    }
+    let output = try!(run());
+    fresh.push((kind, output.clone()));
+    Ok(output)
+}
+
+fn ffprobe_parse(path: &Path,
+                 output: String,
+                 formats: &formats::Registry)
+                 -> SourceResult<Option<ffprobe::FFProbe>> {
+    ffprobe::parse(output, formats).map_err(|e| {
+        Error::FFProbeError {
+            path: path.to_owned(),
+            error: e,
+        }
+    })
+}
+
+#[test]
+fn test_split_and_filter() {
+    use formats::Registry;
+
+    let source = |name: &str, duration: f64| {
+        let output = format!("{{\"format\": {{\"format_name\": \"flac\", \"duration\": \"{}\"}}, \
+                              \"streams\": [{{\"index\": 0, \"codec_type\": \"audio\", \
+                              \"codec_name\": \"flac\", \"channels\": 2}}]}}",
+                             duration);
+        Source {
+            path: BasedPath {
+                path: PathBuf::from("/music/album").join(name),
+                base: PathBuf::from("/music"),
+            },
+            ffprobe: ffprobe::parse(output, &Registry::default()).unwrap().unwrap(),
+            track: None,
         }
-        Ok(r) => Ok(r),
+    };
+    let sheet = cue::Sheet::parse("FILE \"album.wav\" WAVE\n\
+                                   TRACK 01 AUDIO\nINDEX 01 00:00:00\n\
+                                   TRACK 02 AUDIO\nINDEX 01 20:00:00\n\
+                                   TRACK 03 AUDIO\nINDEX 01 40:00:00\n\
+                                   FILE \"bonus.wav\" WAVE\n\
+                                   TRACK 04 AUDIO\nINDEX 01 00:00:00\n")
+        .unwrap();
+    let sheets = vec![(PathBuf::from("/music/album/album.cue"), sheet)];
+    let filter = Filter { min_duration: Some(60.), max_duration: Some(1500.), ..Filter::default() };
+    let sources = vec![source("album.flac", 3600.), source("intro.flac", 10.)];
+
+    // The album is too long as a whole, but each of its tracks fits
+    let (good, filtered, missing) = split_and_filter(sources, &sheets, &filter);
+    let durations: Vec<(Option<u64>, f64)> =
+        good.iter().map(|s| (s.track.as_ref().map(|t| t.number), s.ffprobe.duration)).collect();
+    assert_eq!(durations, vec![(Some(1), 1200.), (Some(2), 1200.), (Some(3), 1200.)]);
+    assert_eq!(filtered.iter().map(|s| s.path.path.clone()).collect::<Vec<_>>(),
+               vec![PathBuf::from("/music/album/intro.flac")]);
+    assert_eq!(missing, vec![(PathBuf::from("/music/album/album.cue"), "bonus.wav".to_string())]);
+}
+
+#[test]
+fn test_problems() {
+    use std::fs::{File, create_dir, remove_dir_all};
+    use std::io::Write;
+
+    let root = ::std::env::temp_dir().join(format!("transcode-problems-{}", ::std::process::id()));
+    let _ = remove_dir_all(&root);
+    create_dir(&root).unwrap();
+    File::create(root.join("broken.cue")).unwrap().write_all(b"TITLE \"No tracks\"\n").unwrap();
+    File::create(root.join("album.cue"))
+        .unwrap()
+        .write_all(b"FILE \"gone.wav\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00:00\n")
+        .unwrap();
+
+    let discover = |strict| {
+        let options = Options { jobs: 1, strict: strict, ..Options::default() };
+        Sources::from_paths(vec![root.clone()],
+                            root.to_str().unwrap(),
+                            &options,
+                            &mut ProbeCache::disabled())
+    };
+
+    // Problems are collected and reported by path
+    let discovery = discover(false).unwrap();
+    let problems: Vec<(PathBuf, &str)> = discovery.problems
+        .iter()
+        .map(|p| (p.path.relative(), p.error.description()))
+        .collect();
+    assert_eq!(problems,
+               vec![(PathBuf::from("album.cue"), "CUE sheet names a file that is not converted"),
+                    (PathBuf::from("broken.cue"), "Could not read CUE sheet")]);
+
+    // --strict stops at the first one
+    match discover(true) {
+        Err(Error::CueError { path, .. }) => assert_eq!(path, root.join("broken.cue")),
+        other => panic!("{:?}", other),
     }
+
+    remove_dir_all(&root).unwrap();
 }
@@ -0,0 +1,316 @@
+use ffprobe::{FFProbe, Stream};
+use formats::Streams;
+use std::ffi::OsString;
+
+const COMPAT_TITLE: &'static str = "Stereo (compat)";
+
+/// Which audio and subtitle streams are kept. Without any rules no streams
+/// are mapped, and ffmpeg picks one of each kind.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rules {
+    /// Languages to keep in order of preference, `und` for streams without
+    /// a language. Empty keeps every language.
+    pub languages: Vec<String>,
+    pub drop_commentary: bool,
+    /// Keep only forced subtitles
+    pub forced_subtitles: bool,
+    /// Keep only the audio stream with the most channels per language
+    pub best_audio: bool,
+}
+
+/// Parses a comma separated list of languages, e.g. `eng,dan,und`
+pub fn parse_languages(s: &str) -> Option<Vec<String>> {
+    let languages: Vec<String> = s.split(',')
+        .map(|l| l.trim().to_lowercase())
+        .collect();
+    match languages.iter().all(|l| l.len() >= 2 && l.chars().all(|c| c.is_ascii_alphabetic())) {
+        true => Some(languages),
+        false => None,
+    }
+}
+
+/// The streams of a source that are kept, by index. The first audio and
+/// subtitle stream become the default ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub video: Option<u64>,
+    pub audio: Vec<u64>,
+    pub subtitles: Vec<Subtitle>,
+    /// Fonts and other attachments, always kept
+    pub attachments: Vec<u64>,
+    /// An audio stream that is added once more, after the other audio
+    /// streams, as the default compatibility track
+    pub compat: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subtitle {
+    pub index: u64,
+    pub forced: bool,
+    /// MP4 text subtitles cannot be copied into Matroska
+    pub convert: bool,
+}
+
+fn language(stream: &Stream) -> &str {
+    stream.language().unwrap_or("und")
+}
+
+fn is_commentary(stream: &Stream) -> bool {
+    stream.disposition.comment ||
+    stream.title().map_or(false, |t| t.to_lowercase().contains("commentary"))
+}
+
+fn attachments(probe: &FFProbe) -> Vec<u64> {
+    probe.streams.iter().filter(|s| s.codec_type() == "attachment").map(|s| s.index).collect()
+}
+
+fn channels(stream: &Stream) -> u64 {
+    stream.audio().and_then(|a| a.channels).unwrap_or(0)
+}
+
+impl Rules {
+    pub fn is_empty(&self) -> bool {
+        *self == Rules::default()
+    }
+
+    /// Where a language goes in the order of preference, `None` if it is
+    /// not kept
+    fn rank(&self, stream: &Stream) -> Option<usize> {
+        if self.languages.is_empty() {
+            return Some(0);
+        }
+        let language = language(stream);
+        self.languages.iter().position(|l| l == language)
+    }
+
+    /// Applies the rules to the streams of `probe`. Returns `None` when
+    /// there are no rules.
+    pub fn select(&self, probe: &FFProbe) -> Option<Selection> {
+        if self.is_empty() {
+            return None;
+        }
+        let kept = |s: &&Stream| !(self.drop_commentary && is_commentary(s));
+
+        let mut audio: Vec<(usize, &Stream)> = probe.streams
+            .iter()
+            .filter(|s| s.audio().is_some())
+            .filter(&kept)
+            .filter_map(|s| self.rank(s).map(|r| (r, s)))
+            .collect();
+        if self.best_audio {
+            let all = audio.clone();
+            audio.retain(|&(_, s)| {
+                // Ties go to the first stream
+                !all.iter().any(|&(_, o)| {
+                    language(o) == language(s) &&
+                    (channels(o) > channels(s) || channels(o) == channels(s) && o.index < s.index)
+                })
+            });
+        }
+        // Preferred languages first, and the default of the source first
+        // within a language
+        audio.sort_by_key(|&(rank, s)| (rank, !s.disposition.default, s.index));
+        let mut audio: Vec<u64> = audio.into_iter().map(|(_, s)| s.index).collect();
+        // Never lose all the sound because of the rules
+        if audio.is_empty() {
+            audio.extend(probe.audio.as_ref().map(|a| a.index));
+        }
+
+        let mut subtitles: Vec<(usize, &Stream)> = probe.streams
+            .iter()
+            .filter(|s| s.codec_type() == "subtitle")
+            .filter(&kept)
+            .filter(|s| !self.forced_subtitles || s.disposition.forced)
+            .filter_map(|s| self.rank(s).map(|r| (r, s)))
+            .collect();
+        subtitles.sort_by_key(|&(rank, s)| (rank, s.index));
+        let subtitles = subtitles.into_iter()
+            .map(|(_, s)| {
+                Subtitle {
+                    index: s.index,
+                    forced: s.disposition.forced,
+                    convert: s.codec_name.as_ref().map_or(false, |c| c == "mov_text"),
+                }
+            })
+            .collect();
+
+        Some(Selection {
+            video: probe.video.as_ref().map(|v| v.index),
+            audio: audio,
+            subtitles: subtitles,
+            attachments: attachments(probe),
+            compat: None,
+        })
+    }
+}
+
+impl Selection {
+    /// The streams ffmpeg picks without any `-map`, as far as the probe
+    /// tells: the video, the converted audio, which follows the choice of
+    /// ffmpeg, and the first subtitle
+    pub fn default_for(probe: &FFProbe) -> Selection {
+        let subtitle = probe.streams.iter().find(|s| s.codec_type() == "subtitle");
+        Selection {
+            video: probe.video.as_ref().map(|v| v.index),
+            audio: probe.audio.iter().map(|a| a.index).collect(),
+            subtitles: subtitle.into_iter()
+                .map(|s| {
+                    Subtitle {
+                        index: s.index,
+                        forced: s.disposition.forced,
+                        convert: s.codec_name.as_ref().map_or(false, |c| c == "mov_text"),
+                    }
+                })
+                .collect(),
+            attachments: attachments(probe),
+            compat: None,
+        }
+    }
+    /// The streams the output gets from `to_ffmpeg_args` with the same
+    /// arguments
+    pub fn planned(&self, video: bool, subtitles: bool) -> Streams {
+        Streams {
+            video: match video {
+                true => self.video.iter().count(),
+                false => 0,
+            },
+            audio: self.audio.len() + self.compat.iter().count(),
+            subtitle: match subtitles {
+                true => self.subtitles.len(),
+                false => 0,
+            },
+            image: 0,
+        }
+    }
+    /// `-map` arguments for the attachments, which only Matroska takes
+    pub fn attachment_args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
+        for &index in &self.attachments {
+            args.extend(vec!["-map".into(), format!("0:{}", index).into()]);
+        }
+        if !args.is_empty() {
+            args.extend(vec!["-c:t".into(), "copy".into()]);
+        }
+        args
+    }
+    /// Where the compatibility track is among the audio streams of the
+    /// output
+    pub fn compat_position(&self) -> Option<usize> {
+        self.compat.map(|_| self.audio.len())
+    }
+    /// `-map` arguments for the kept streams, their codecs if they are
+    /// copied, and the default flags. Only the compatibility track or else
+    /// the first audio stream is default, and a subtitle only when it is
+    /// forced.
+    pub fn to_ffmpeg_args(&self, video: bool, subtitles: bool) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
+        let map = |index: u64| {
+            vec![OsString::from("-map"), format!("0:{}", index).into()]
+        };
+        if let (true, Some(index)) = (video, self.video) {
+            args.extend(map(index));
+        }
+        for &index in self.audio.iter().chain(&self.compat) {
+            args.extend(map(index));
+        }
+        let subtitles: &[Subtitle] = match subtitles {
+            true => &self.subtitles,
+            false => &[],
+        };
+        for subtitle in subtitles {
+            args.extend(map(subtitle.index));
+        }
+
+        let default = self.compat_position().unwrap_or(0);
+        for n in 0..self.audio.len() + self.compat.iter().count() {
+            let flags = match n == default {
+                true => "default",
+                false => "0",
+            };
+            args.extend(vec![format!("-disposition:a:{}", n).into(), flags.into()]);
+        }
+        if let Some(n) = self.compat_position() {
+            args.extend(vec![format!("-metadata:s:a:{}", n).into(),
+                             format!("title={}", COMPAT_TITLE).into()]);
+        }
+        for (n, subtitle) in subtitles.iter().enumerate() {
+            let codec = match subtitle.convert {
+                true => "srt",
+                false => "copy",
+            };
+            let flags = match (n, subtitle.forced) {
+                (0, true) => "default+forced",
+                (_, true) => "forced",
+                (_, false) => "0",
+            };
+            args.extend(vec![format!("-c:s:{}", n).into(),
+                             codec.into(),
+                             format!("-disposition:s:{}", n).into(),
+                             flags.into()]);
+        }
+        args
+    }
+}
+
+#[test]
+fn test_select() {
+    use formats::Registry;
+    use ffprobe;
+
+    let output = r#"{
+        "format": {"format_name": "matroska,webm", "duration": "60"},
+        "streams": [
+            {"index": 0, "codec_type": "video", "codec_name": "h264", "width": 1920,
+             "height": 1080, "r_frame_rate": "24/1"},
+            {"index": 1, "codec_type": "audio", "codec_name": "ac3", "channels": 2,
+             "tags": {"language": "eng"}},
+            {"index": 2, "codec_type": "audio", "codec_name": "dts", "channels": 6,
+             "tags": {"language": "eng"}},
+            {"index": 3, "codec_type": "audio", "codec_name": "ac3", "channels": 2,
+             "tags": {"language": "eng", "title": "Director's Commentary"}},
+            {"index": 4, "codec_type": "audio", "codec_name": "ac3", "channels": 6,
+             "disposition": {"default": 1}, "tags": {"language": "dan"}},
+            {"index": 5, "codec_type": "audio", "codec_name": "ac3", "channels": 6,
+             "tags": {"language": "ger"}},
+            {"index": 6, "codec_type": "subtitle", "codec_name": "subrip",
+             "tags": {"language": "eng"}},
+            {"index": 7, "codec_type": "subtitle", "codec_name": "subrip",
+             "disposition": {"forced": 1}, "tags": {"language": "dan"}}
+        ]
+    }"#;
+    let probe = ffprobe::parse(output.to_string(), &Registry::default()).unwrap().unwrap();
+
+    let rules = Rules {
+        languages: parse_languages("eng,dan,und").unwrap(),
+        drop_commentary: true,
+        forced_subtitles: true,
+        best_audio: true,
+    };
+    let selection = rules.select(&probe).unwrap();
+    assert_eq!(selection.video, Some(0));
+    assert_eq!(selection.audio, vec![2, 4]);
+    assert_eq!(selection.subtitles.iter().map(|s| s.index).collect::<Vec<_>>(), vec![7]);
+    let args: Vec<String> = selection.to_ffmpeg_args(true, true)
+        .into_iter()
+        .map(|a| a.into_string().unwrap())
+        .collect();
+    assert_eq!(args.join(" "),
+               "-map 0:0 -map 0:2 -map 0:4 -map 0:7 -disposition:a:0 default \
+                -disposition:a:1 0 -c:s:0 copy -disposition:s:0 default+forced");
+
+    let languages = Rules { languages: vec!["dan".to_string()], ..Rules::default() };
+    assert_eq!(languages.select(&probe).unwrap().audio, vec![4]);
+    assert!(Rules::default().select(&probe).is_none());
+
+    // ffmpeg would pick the default Danish stream over the first one
+    assert_eq!(probe.audio.as_ref().map(|a| a.index), Some(4));
+    let compat = Selection { compat: Some(2), ..Selection::default_for(&probe) };
+    let args: Vec<String> = compat.to_ffmpeg_args(false, false)
+        .into_iter()
+        .map(|a| a.into_string().unwrap())
+        .collect();
+    assert_eq!(args.join(" "),
+               "-map 0:4 -map 0:2 -disposition:a:0 0 -disposition:a:1 default \
+                -metadata:s:a:1 title=Stereo (compat)");
+    assert!(parse_languages("eng,,dan").is_none());
+}
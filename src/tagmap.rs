@@ -0,0 +1,276 @@
+use ffprobe::FFProbe;
+use metadata::Policy;
+use std::ffi::OsString;
+
+/// How the tags of an audio format are named, as ffmpeg reads and writes
+/// them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    /// Vorbis comments of Ogg and FLAC
+    Vorbis,
+    /// ID3v2.4 frames of MP3. Names without a frame of their own go in
+    /// `TXXX` frames.
+    Id3v2,
+    /// iTunes atoms of MP4. ffmpeg only writes the standard atoms, so
+    /// other tags are dropped, see `dropped`.
+    Mp4,
+}
+
+/// A tag that each scheme names differently
+struct Field {
+    /// Lower case names it is read as, besides the names it is written as
+    aliases: &'static [&'static str],
+    vorbis: &'static str,
+    id3v2: Option<&'static str>,
+    mp4: Option<&'static str>,
+}
+
+impl Field {
+    fn name(&self, scheme: Scheme) -> Option<&'static str> {
+        match scheme {
+            Scheme::Vorbis => Some(self.vorbis),
+            Scheme::Id3v2 => self.id3v2,
+            Scheme::Mp4 => self.mp4,
+        }
+    }
+    fn is_named(&self, lower: &str) -> bool {
+        self.aliases.contains(&lower) ||
+        [Some(self.vorbis), self.id3v2, self.mp4]
+            .iter()
+            .any(|n| n.map_or(false, |n| n.to_lowercase() == lower))
+    }
+}
+
+// ffmpeg translates the generic names of ID3v2 and MP4 to frames and atoms.
+// Picard puts the recording ID in an UFID frame, which ffmpeg cannot write,
+// so it goes in TXXX like older taggers did.
+const FIELDS: &'static [Field] = &[
+    Field { aliases: &["tit2"], vorbis: "TITLE", id3v2: Some("title"), mp4: Some("title") },
+    Field { aliases: &["tpe1"], vorbis: "ARTIST", id3v2: Some("artist"), mp4: Some("artist") },
+    Field {
+        aliases: &["album artist", "tpe2"],
+        vorbis: "ALBUMARTIST",
+        id3v2: Some("album_artist"),
+        mp4: Some("album_artist"),
+    },
+    Field { aliases: &["talb"], vorbis: "ALBUM", id3v2: Some("album"), mp4: Some("album") },
+    Field { aliases: &["year", "tdrc", "tyer"], vorbis: "DATE", id3v2: Some("date"), mp4: Some("date") },
+    Field { aliases: &["tcon"], vorbis: "GENRE", id3v2: Some("genre"), mp4: Some("genre") },
+    Field { aliases: &["tcom"], vorbis: "COMPOSER", id3v2: Some("composer"), mp4: Some("composer") },
+    Field { aliases: &["description"], vorbis: "COMMENT", id3v2: Some("comment"), mp4: Some("comment") },
+    Field {
+        aliases: &[],
+        vorbis: "MUSICBRAINZ_TRACKID",
+        id3v2: Some("MusicBrainz Track Id"),
+        mp4: None,
+    },
+    Field {
+        aliases: &[],
+        vorbis: "MUSICBRAINZ_RELEASETRACKID",
+        id3v2: Some("MusicBrainz Release Track Id"),
+        mp4: None,
+    },
+    Field {
+        aliases: &[],
+        vorbis: "MUSICBRAINZ_ALBUMID",
+        id3v2: Some("MusicBrainz Album Id"),
+        mp4: None,
+    },
+    Field {
+        aliases: &[],
+        vorbis: "MUSICBRAINZ_ARTISTID",
+        id3v2: Some("MusicBrainz Artist Id"),
+        mp4: None,
+    },
+    Field {
+        aliases: &[],
+        vorbis: "MUSICBRAINZ_ALBUMARTISTID",
+        id3v2: Some("MusicBrainz Album Artist Id"),
+        mp4: None,
+    },
+    Field {
+        aliases: &[],
+        vorbis: "MUSICBRAINZ_RELEASEGROUPID",
+        id3v2: Some("MusicBrainz Release Group Id"),
+        mp4: None,
+    },
+];
+
+// Written by muxers, or describing the streams of the source
+const SKIPPED: &'static [&'static str] = &["encoder", "major_brand", "minor_version", "compatible_brands",
+                                            "creation_time", "handler_name", "vendor_id", "language",
+                                            "duration", "itunsmpb", "itunnorm", "metadata_block_picture",
+                                            "cuesheet"];
+
+/// A track or disc number with the count of tracks or discs, which Vorbis
+/// comments keep apart and the others write as `3/12`
+#[derive(Debug, Default)]
+struct Position {
+    number: Option<String>,
+    total: Option<String>,
+}
+
+impl Position {
+    fn set(&mut self, value: &str) {
+        let mut parts = value.splitn(2, '/');
+        self.number = parts.next().map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+        if let Some(total) = parts.next().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()) {
+            self.total = Some(total);
+        }
+    }
+    fn tags(&self, scheme: Scheme, vorbis: (&str, &str), other: &str) -> Vec<(String, String)> {
+        match (scheme, &self.number, &self.total) {
+            (_, &None, _) => vec![],
+            (Scheme::Vorbis, &Some(ref number), total) => {
+                Some((vorbis.0.to_string(), number.clone()))
+                    .into_iter()
+                    .chain(total.as_ref().map(|t| (vorbis.1.to_string(), t.clone())))
+                    .collect()
+            }
+            (_, &Some(ref number), &Some(ref total)) => vec![(other.to_string(), format!("{}/{}", number, total))],
+            (_, &Some(ref number), &None) => vec![(other.to_string(), number.clone())],
+        }
+    }
+}
+
+/// The tags of the source: those of the container, then those of the
+/// converted audio stream, where Ogg keeps them
+pub fn source_tags(probe: &FFProbe) -> Vec<(String, String)> {
+    let stream = probe.audio.as_ref().and_then(|a| probe.stream(a.index));
+    probe.format
+        .tags
+        .iter()
+        .chain(stream.into_iter().flat_map(|s| s.tags.iter()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Track and disc numbers, which `Position` combines
+fn is_position(lower: &str) -> bool {
+    ["track", "tracknumber", "trck", "tracktotal", "totaltracks", "disc", "discnumber", "tpos", "disctotal",
+     "totaldiscs"]
+        .contains(&lower)
+}
+
+impl Scheme {
+    /// The name of `key` in this scheme, `None` if the scheme cannot hold
+    /// it. Unknown tags keep their name outside of MP4.
+    fn name(&self, key: &str) -> Option<String> {
+        let lower = key.to_lowercase();
+        match FIELDS.iter().find(|f| f.is_named(&lower)) {
+            Some(field) => field.name(*self).map(|n| n.to_string()),
+            None if *self == Scheme::Mp4 => None,
+            None => Some(key.to_string()),
+        }
+    }
+
+    /// The tags of `tags` that `map` drops, as they are named there
+    pub fn dropped(&self, tags: &[(String, String)]) -> Vec<String> {
+        let mut dropped: Vec<String> = Vec::new();
+        for &(ref key, _) in tags {
+            let lower = key.to_lowercase();
+            if SKIPPED.contains(&lower.as_str()) || is_position(&lower) || self.name(key).is_some() {
+                continue;
+            }
+            if !dropped.iter().any(|d| d.eq_ignore_ascii_case(key)) {
+                dropped.push(key.clone());
+            }
+        }
+        dropped
+    }
+
+    /// Renames `tags` from any scheme to this one. Tags it cannot hold are
+    /// dropped, and later tags replace earlier ones with the same name.
+    pub fn map(&self, tags: &[(String, String)]) -> Vec<(String, String)> {
+        let mut mapped: Vec<(String, String)> = Vec::new();
+        let mut track = Position::default();
+        let mut disc = Position::default();
+        for &(ref key, ref value) in tags {
+            let lower = key.to_lowercase();
+            match lower.as_str() {
+                l if SKIPPED.contains(&l) => (),
+                "track" | "tracknumber" | "trck" => track.set(value),
+                "tracktotal" | "totaltracks" => track.total = Some(value.clone()),
+                "disc" | "discnumber" | "tpos" => disc.set(value),
+                "disctotal" | "totaldiscs" => disc.total = Some(value.clone()),
+                _ => {
+                    if let Some(name) = self.name(key) {
+                        mapped.retain(|&(ref k, _)| !k.eq_ignore_ascii_case(&name));
+                        mapped.push((name, value.clone()));
+                    }
+                }
+            }
+        }
+        mapped.extend(track.tags(*self, ("TRACKNUMBER", "TRACKTOTAL"), "track"));
+        mapped.extend(disc.tags(*self, ("DISCNUMBER", "DISCTOTAL"), "disc"));
+        mapped
+    }
+
+    /// Arguments that write the tags of the source, if `policy` keeps
+    /// them, and then `tags`, all renamed. The metadata ffmpeg would copy
+    /// is dropped, as it keeps the names of the source.
+    pub fn metadata_args(&self, policy: Policy, probe: &FFProbe, tags: &[(String, String)]) -> Vec<OsString> {
+        let (mut args, source) = match policy {
+            Policy::Copy => {
+                (["-map_metadata", "-1", "-map_metadata:s", "-1"].iter().map(OsString::from).collect(),
+                 source_tags(probe))
+            }
+            Policy::Strip => (policy.to_ffmpeg_args(), Vec::new()),
+        };
+        let tags: Vec<(String, String)> = source.into_iter().chain(tags.iter().cloned()).collect();
+        args.extend(self.to_ffmpeg_args(&self.map(&tags)));
+        args
+    }
+
+    /// `-metadata` arguments for `tags`, already mapped. Ogg keeps its
+    /// comments with the stream.
+    fn to_ffmpeg_args(&self, tags: &[(String, String)]) -> Vec<OsString> {
+        let option = match *self {
+            Scheme::Vorbis => "-metadata:s:a:0",
+            Scheme::Id3v2 | Scheme::Mp4 => "-metadata",
+        };
+        tags.iter()
+            .flat_map(|&(ref key, ref value)| vec![option.into(), format!("{}={}", key, value).into()])
+            .collect()
+    }
+}
+
+#[test]
+fn test_map() {
+    let tags: Vec<(String, String)> = vec![("ARTIST", "Low"),
+                                           ("ALBUMARTIST", "Low"),
+                                           ("TRACKNUMBER", "3"),
+                                           ("TRACKTOTAL", "12"),
+                                           ("DISCNUMBER", "1"),
+                                           ("DATE", "2018"),
+                                           ("MUSICBRAINZ_ALBUMID", "60a6c7a6-ae2a-4a3d-8d5f-2e1f5d5d1f4b"),
+                                           ("encoder", "Lavf58"),
+                                           ("REPLAYGAIN_TRACK_GAIN", "-6.20 dB")]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let id3v2: Vec<String> = Scheme::Id3v2.map(&tags).into_iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    assert_eq!(id3v2,
+               vec!["artist=Low",
+                    "album_artist=Low",
+                    "date=2018",
+                    "MusicBrainz Album Id=60a6c7a6-ae2a-4a3d-8d5f-2e1f5d5d1f4b",
+                    "REPLAYGAIN_TRACK_GAIN=-6.20 dB",
+                    "track=3/12",
+                    "disc=1"]);
+
+    let mp4: Vec<String> = Scheme::Mp4.map(&Scheme::Id3v2.map(&tags))
+        .into_iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+    assert_eq!(mp4,
+               vec!["artist=Low", "album_artist=Low", "date=2018", "track=3/12", "disc=1"]);
+    assert_eq!(Scheme::Mp4.dropped(&tags),
+               vec!["MUSICBRAINZ_ALBUMID", "REPLAYGAIN_TRACK_GAIN"]);
+    assert!(Scheme::Id3v2.dropped(&tags).is_empty());
+    let vorbis = Scheme::Vorbis.map(&Scheme::Id3v2.map(&tags));
+    assert!(vorbis.contains(&("ALBUMARTIST".to_string(), "Low".to_string())));
+    assert!(vorbis.contains(&("MUSICBRAINZ_ALBUMID".to_string(),
+                              "60a6c7a6-ae2a-4a3d-8d5f-2e1f5d5d1f4b".to_string())));
+    assert!(vorbis.contains(&("TRACKTOTAL".to_string(), "12".to_string())));
+}
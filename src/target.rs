@@ -67,19 +67,21 @@ impl fmt::Display for Error {
 }
 
 impl Target {
-    pub fn new(prefix: &Path, path: &Path, extension: &OsStr, codec: Codec) -> Result<Self, Error> {
+    pub fn plan_path(prefix: &Path, path: &Path, extension: &OsStr) -> Result<PathBuf, Error> {
         let path = prefix.join(path.with_extension(extension));
 
-        let path = match path::normalize(&path) {
+        match path::normalize(&path) {
             Err(e) => {
-                return Err(Error::NormalizeError {
+                Err(Error::NormalizeError {
                     path: path,
                     error: e,
                 })
             }
-            Ok(p) => p,
-        };
+            Ok(p) => Ok(p),
+        }
+    }
 
+    pub fn new(path: PathBuf, codec: Codec) -> Result<Self, Error> {
         if path.exists() {
             return Err(Error::Exists { path: path });
         }
@@ -118,6 +120,22 @@ impl Target {
         }
     }
 
+    /// Prefix of the statistics that the first of two passes leaves for the
+    /// second
+    pub fn passlog(&self) -> PathBuf {
+        self.path_tmp.with_extension("passlog")
+    }
+
+    /// Removes the files written with `passlog`, ignoring any that are missing
+    pub fn remove_passlogs(&self) {
+        let passlog = self.passlog().into_os_string();
+        for suffix in &["-0.log", "-0.log.temp", "-0.log.mbtree", "-0.log.mbtree.temp"] {
+            let mut path = passlog.clone();
+            path.push(suffix);
+            let _ = fs::remove_file(&path);
+        }
+    }
+
     pub fn mkdir_parent(&self) -> Result<(), Error> {
         match path::mkdir_parent(&self.path) {
             Ok(()) => Ok(()),
@@ -1,4 +1,5 @@
 use constants;
+use std::cmp::{max, min};
 
 
 pub fn repeat_str<T: AsRef<str>>(s: T, times: usize) -> String {
@@ -53,6 +54,74 @@ pub fn prompt_continue() -> bool {
     prompt("Do you want to continue [y/n]?", |s| s == "y" || s == "n").map_or(false, |s| s == "y")
 }
 
+/// Parses sizes like `700M`, `1.5G` or `4096` (bytes). Suffixes are binary.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let s = s.trim_end_matches(|c| c == 'B' || c == 'b');
+    let s = s.trim_end_matches('i');
+    let (number, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1u64 << 10),
+        Some('M') => (&s[..s.len() - 1], 1u64 << 20),
+        Some('G') => (&s[..s.len() - 1], 1u64 << 30),
+        Some('T') => (&s[..s.len() - 1], 1u64 << 40),
+        _ => (s, 1),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n >= 0. => Some((n * multiplier as f64).round() as u64),
+        _ => None,
+    }
+}
+
+/// Parses durations in seconds, `MM:SS` or `HH:MM:SS` (seconds may have decimals).
+pub fn parse_duration(s: &str) -> Option<f64> {
+    let parts: Vec<Option<f64>> = s.trim().split(':').map(|p| p.parse::<f64>().ok()).collect();
+    if parts.len() > 3 || parts.iter().any(|p| p.map_or(true, |n| n < 0.)) {
+        return None;
+    }
+    Some(parts.into_iter().fold(0., |acc, p| acc * 60. + p.unwrap_or(0.)))
+}
+
+/// Maps `items` on up to `jobs` threads, keeping the order of the input.
+pub fn parallel_map<T, R, F>(items: Vec<T>, jobs: usize, f: F) -> Vec<R>
+    where T: Send,
+          R: Send,
+          F: Fn(T) -> R + Sync
+{
+    use std::sync::Mutex;
+    use std::thread;
+
+    let count = items.len();
+    let jobs = max(1, min(jobs, count));
+    if jobs == 1 {
+        return items.into_iter().map(f).collect();
+    }
+
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..count).map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    let (i, item) = match next {
+                        Some(n) => n,
+                        None => break,
+                    };
+                    let result = f(item);
+                    results.lock().unwrap()[i] = Some(result);
+                }
+            });
+        }
+    });
+
+    results.into_inner().unwrap().into_iter().filter_map(|r| r).collect()
+}
+
+pub fn default_jobs() -> usize {
+    ::std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
 const HEX: &'static [char] = &['0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c',
                                'd', 'e', 'f'];
 
@@ -106,3 +175,36 @@ fn test_hex() {
         assert_eq!(u64_to_hex(n), String::from(hex))
     }
 }
+
+#[test]
+fn test_parse_size() {
+    let cases: &[(&str, Option<u64>)] = &[("4096", Some(4096)),
+                                         ("1K", Some(1024)),
+                                         ("700M", Some(700 * 1024 * 1024)),
+                                         ("700MB", Some(700 * 1024 * 1024)),
+                                         ("1.5GiB", Some(3 * 512 * 1024 * 1024)),
+                                         ("-1M", None),
+                                         ("M", None)];
+    for &(s, size) in cases {
+        assert_eq!(parse_size(s), size, "{}", s);
+    }
+}
+
+#[test]
+fn test_parse_duration() {
+    let cases: &[(&str, Option<f64>)] = &[("90", Some(90.)),
+                                         ("01:30", Some(90.)),
+                                         ("1:00:00.5", Some(3600.5)),
+                                         ("1:2:3:4", None),
+                                         ("a", None)];
+    for &(s, duration) in cases {
+        assert_eq!(parse_duration(s), duration, "{}", s);
+    }
+}
+
+#[test]
+fn test_parallel_map() {
+    let items: Vec<u64> = (0..100).collect();
+    let doubled = parallel_map(items, 8, |n| n * 2);
+    assert_eq!(doubled, (0..100).map(|n| n * 2).collect::<Vec<_>>());
+}
@@ -0,0 +1,272 @@
+use ffprobe::{self, FFProbe};
+use formats;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Containers round durations differently and encoders may drop or pad a
+// few frames at the end
+const TOLERANCE_SECONDS: f64 = 1.;
+const TOLERANCE_RATIO: f64 = 0.01;
+// Estimated source durations are only good for a rough comparison
+const TOLERANCE_RATIO_ESTIMATE: f64 = 0.05;
+
+/// How thoroughly outputs are checked before they replace the temp file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    None,
+    Probe,
+    Decode,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Level::None
+    }
+}
+
+impl Level {
+    pub fn from_str(s: &str) -> Option<Level> {
+        match s {
+            "none" => Some(Level::None),
+            "probe" => Some(Level::Probe),
+            "decode" => Some(Level::Decode),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    ProbeError(ffprobe::Error),
+    NotMedia,
+    Duration { expected: f64, actual: f64 },
+    MissingStream { codec_type: &'static str },
+    StreamCount {
+        codec_type: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    DecodeSpawnError(io::Error),
+    DecodeError {
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::ProbeError(_) => "Could not probe output",
+            Error::NotMedia => "Output is not recognized as video/audio",
+            Error::Duration { .. } => "Output duration differs from source",
+            Error::MissingStream { .. } => "Output is missing a stream",
+            Error::StreamCount { .. } => "Output has other streams than planned",
+            Error::DecodeSpawnError(_) => "Could not spawn ffmpeg to decode output",
+            Error::DecodeError { .. } => "Output could not be decoded",
+        }
+    }
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::ProbeError(ref e) => Some(e),
+            Error::DecodeSpawnError(ref e) => Some(e),
+            Error::NotMedia |
+            Error::Duration { .. } |
+            Error::MissingStream { .. } |
+            Error::StreamCount { .. } |
+            Error::DecodeError { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Duration { expected, actual } => {
+                write!(f,
+                       "{}: expected {:.2}s, got {:.2}s",
+                       self.description(),
+                       expected,
+                       actual)
+            }
+            Error::MissingStream { codec_type } => {
+                write!(f, "{}: no {} stream", self.description(), codec_type)
+            }
+            Error::StreamCount { codec_type, expected, actual } => {
+                write!(f,
+                       "{}: expected {} {} streams, got {}",
+                       self.description(),
+                       expected,
+                       codec_type,
+                       actual)
+            }
+            Error::DecodeError { ref stderr, .. } => {
+                write!(f, "{}\nStdErr:\n{}", self.description(), stderr.trim())
+            }
+            Error::ProbeError(_) |
+            Error::NotMedia |
+            Error::DecodeSpawnError(_) => write!(f, "{}", self.description()),
+        }
+    }
+}
+
+/// Checks the converted file at `path` against the probe of its source,
+/// and against the streams the container `planned` to map, if it maps them
+pub fn verify(level: Level,
+              source: &FFProbe,
+              planned: Option<formats::Streams>,
+              path: &Path,
+              formats: &formats::Registry)
+              -> Result<(), Error> {
+    if level == Level::None {
+        return Ok(());
+    }
+
+    let output = match try!(ffprobe::run(path).map_err(Error::ProbeError)) {
+        Some(o) => o,
+        None => return Err(Error::NotMedia),
+    };
+    let output = match try!(ffprobe::parse(output, formats).map_err(Error::ProbeError)) {
+        Some(o) => o,
+        None => return Err(Error::NotMedia),
+    };
+
+    try!(compare(source, &output));
+    if let Some(planned) = planned {
+        try!(compare_streams(planned, &output));
+    }
+
+    if level == Level::Decode {
+        try!(decode(path));
+    }
+    Ok(())
+}
+
+fn compare(source: &FFProbe, output: &FFProbe) -> Result<(), Error> {
+    if source.video.is_some() && output.video.is_none() {
+        return Err(Error::MissingStream { codec_type: "video" });
+    }
+    if source.audio.is_some() && output.audio.is_none() {
+        return Err(Error::MissingStream { codec_type: "audio" });
+    }
+
+    let ratio = match source.duration_source.is_estimate() {
+        true => TOLERANCE_RATIO_ESTIMATE,
+        false => TOLERANCE_RATIO,
+    };
+    let tolerance = TOLERANCE_SECONDS.max(source.duration * ratio);
+    if (output.duration - source.duration).abs() > tolerance {
+        return Err(Error::Duration {
+            expected: source.duration,
+            actual: output.duration,
+        });
+    }
+    Ok(())
+}
+
+fn compare_streams(planned: formats::Streams, output: &FFProbe) -> Result<(), Error> {
+    let mut actual = formats::Streams::default();
+    for stream in &output.streams {
+        actual.add(stream.codec_type(),
+                   stream.codec_name.as_ref().map_or("", |c| c.as_str()),
+                   stream.disposition.attached_pic);
+    }
+    // Pictures count as video, since copied video like MJPEG passes for one
+    let counts = [("video", planned.video + planned.image, actual.video + actual.image),
+                  ("audio", planned.audio, actual.audio),
+                  ("subtitle", planned.subtitle, actual.subtitle)];
+    for &(codec_type, expected, actual) in &counts {
+        if expected != actual {
+            return Err(Error::StreamCount {
+                codec_type: codec_type,
+                expected: expected,
+                actual: actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Decodes every stream of `path`, failing on the first corrupt packet
+fn decode(path: &Path) -> Result<(), Error> {
+    let output = try!(Command::new("ffmpeg")
+        .args(&["-nostdin", "-hide_banner", "-v", "error", "-xerror", "-i"])
+        .arg(path)
+        .args(&["-map", "0", "-f", "null", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(Error::DecodeSpawnError));
+
+    match output.status.success() {
+        true => Ok(()),
+        false => {
+            Err(Error::DecodeError {
+                exit_code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            })
+        }
+    }
+}
+
+#[test]
+fn test_compare() {
+    let formats = formats::Registry::default();
+    let probe = |format: &str, streams: &str| {
+        let output = format!("{{\"format\": {{{}}}, \"streams\": [{}]}}", format, streams);
+        ffprobe::parse(output, &formats).unwrap().unwrap()
+    };
+    let video = "{\"index\": 0, \"codec_type\": \"video\", \"codec_name\": \"h264\", \
+                 \"width\": 640, \"height\": 480, \"r_frame_rate\": \"25/1\"}";
+    let audio = "{\"index\": 1, \"codec_type\": \"audio\", \"codec_name\": \"aac\"}";
+    let cover = "{\"index\": 2, \"codec_type\": \"video\", \"codec_name\": \"mjpeg\", \
+                 \"width\": 600, \"height\": 600, \"r_frame_rate\": \"90000/1\", \
+                 \"disposition\": {\"attached_pic\": 1}}";
+    let both = format!("{},{}", video, audio);
+    let mkv = |duration: f64| format!("\"format_name\": \"matroska,webm\", \"duration\": \"{}\"", duration);
+
+    // One percent of a container duration, but at least a second
+    let source = probe(&mkv(1000.), &both);
+    assert!(compare(&source, &probe(&mkv(1009.), &both)).is_ok());
+    match compare(&source, &probe(&mkv(1011.), &both)) {
+        Err(Error::Duration { expected, actual }) => assert_eq!((expected, actual), (1000., 1011.)),
+        r => panic!("{:?}", r),
+    }
+    let source = probe(&mkv(10.), &both);
+    assert!(compare(&source, &probe(&mkv(10.9), &both)).is_ok());
+    assert!(compare(&source, &probe(&mkv(11.1), &both)).is_err());
+
+    // Five percent of an estimated duration
+    let counted = "{\"index\": 0, \"codec_type\": \"video\", \"codec_name\": \"mpeg4\", \
+                   \"width\": 640, \"height\": 480, \"r_frame_rate\": \"25/1\", \
+                   \"nb_frames\": \"25000\"}";
+    let source = probe("\"format_name\": \"avi\"", &format!("{},{}", counted, audio));
+    assert_eq!(source.duration, 1000.);
+    assert!(compare(&source, &probe(&mkv(1040.), &both)).is_ok());
+    assert!(compare(&source, &probe(&mkv(1060.), &both)).is_err());
+
+    // A cover is no video stream
+    let source = probe(&mkv(10.), &both);
+    match compare(&source, &probe(&mkv(10.), &format!("{},{}", audio, cover))) {
+        Err(Error::MissingStream { codec_type: "video" }) => (),
+        r => panic!("{:?}", r),
+    }
+    match compare(&source, &probe(&mkv(10.), video)) {
+        Err(Error::MissingStream { codec_type: "audio" }) => (),
+        r => panic!("{:?}", r),
+    }
+
+    // Covers count as video in the planned streams
+    let output = probe(&mkv(10.), &format!("{},{},{}", video, audio, cover));
+    let planned = formats::Streams { video: 1, audio: 1, ..formats::Streams::default() };
+    match compare_streams(planned, &output) {
+        Err(Error::StreamCount { codec_type: "video", expected: 1, actual: 2 }) => (),
+        r => panic!("{:?}", r),
+    }
+    assert!(compare_streams(formats::Streams { image: 1, ..planned }, &output).is_ok());
+    match compare_streams(formats::Streams { image: 1, audio: 2, ..planned }, &output) {
+        Err(Error::StreamCount { codec_type: "audio", expected: 2, actual: 1 }) => (),
+        r => panic!("{:?}", r),
+    }
+}